@@ -2,4 +2,5 @@
 pub mod rar;
 pub mod sevenz;
 pub mod tar;
+pub mod tar_index;
 pub mod zip;
@@ -1,6 +1,11 @@
 //! Contains RAR-specific building and unpacking functions
 
-use std::path::Path;
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::{Component, Path},
+    time::SystemTime,
+};
 
 use unrar::Archive;
 
@@ -8,12 +13,20 @@ use crate::{
     error::{Error, Result},
     info,
     list::{FileInArchive, ListFileType},
-    utils::BytesFmt,
+    utils::{BytesFmt, EntryFilter, PathFmt, PathTransform, ensure_parent_dir_exists},
+    warning,
 };
 
-/// Unpacks the archive given by `archive_path` into the folder given by `output_folder`.
+/// Unpacks the archive given by `archive_path` into the folder given by `output_folder`, skipping
+/// entries not selected by `entry_filter`, and changing their paths with `path_transform`.
 /// Assumes that output_folder is empty
-pub fn unpack_archive(archive_path: &Path, output_folder: &Path, password: Option<&[u8]>) -> Result<u64> {
+pub fn unpack_archive(
+    archive_path: &Path,
+    output_folder: &Path,
+    password: Option<&[u8]>,
+    entry_filter: &EntryFilter,
+    path_transform: &PathTransform,
+) -> Result<u64> {
     let archive = match password {
         Some(password) => Archive::with_password(archive_path, password),
         None => Archive::new(archive_path),
@@ -21,23 +34,113 @@ pub fn unpack_archive(archive_path: &Path, output_folder: &Path, password: Optio
 
     let mut archive = archive.open_for_processing()?;
     let mut files_unpacked = 0;
+    let mut flattened_paths = HashMap::new();
 
     while let Some(header) = archive.read_header()? {
         let entry = header.entry();
-        archive = if entry.is_file() {
+        let Some(relative_path) = (entry.is_file() && entry_filter.matches(&entry.filename))
+            .then(|| path_transform.apply(&entry.filename, false))
+            .flatten()
+        else {
+            archive = header.skip()?;
+            continue;
+        };
+
+        // unrar only checks the paths it builds itself, with `extract_with_base`
+        let is_enclosed = relative_path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        if !path_transform.is_identity() && !is_enclosed {
+            warning!(
+                "Skipping entry {} of rar archive, its path would be extracted outside of the output directory",
+                PathFmt(&entry.filename),
+            );
+            archive = header.skip()?;
+            continue;
+        }
+        path_transform.check_flatten_collision(&mut flattened_paths, &entry.filename, &relative_path)?;
+
+        info!(
+            "extracted ({}) {}",
+            BytesFmt(entry.unpacked_size),
+            relative_path.display(),
+        );
+        files_unpacked += 1;
+        archive = if path_transform.is_identity() {
+            header.extract_with_base(output_folder)?
+        } else {
+            let output_path = output_folder.join(relative_path);
+            ensure_parent_dir_exists(&output_path)?;
+            header.extract_to(output_path)?
+        };
+    }
+
+    Ok(files_unpacked)
+}
+
+/// Write the contents of the files selected by `entry_filter` to `writer`, one after another.
+///
+/// Returns the number of files written.
+pub fn write_entries(
+    archive_path: &Path,
+    mut writer: impl Write,
+    password: Option<&[u8]>,
+    entry_filter: &EntryFilter,
+) -> Result<u64> {
+    let archive = match password {
+        Some(password) => Archive::with_password(archive_path, password),
+        None => Archive::new(archive_path),
+    };
+
+    let mut archive = archive.open_for_processing()?;
+    let mut files_written = 0;
+
+    while let Some(header) = archive.read_header()? {
+        let entry = header.entry();
+        archive = if entry.is_file() && entry_filter.matches(&entry.filename) {
             info!(
-                "extracted ({}) {}",
+                "writing ({}) {}",
                 BytesFmt(entry.unpacked_size),
                 entry.filename.display(),
             );
-            files_unpacked += 1;
-            header.extract_with_base(output_folder)?
+            let (data, archive) = header.read()?;
+            writer.write_all(&data)?;
+            files_written += 1;
+            archive
         } else {
             header.skip()?
         };
     }
 
-    Ok(files_unpacked)
+    Ok(files_written)
+}
+
+/// Check that `password` decrypts the archive, failing with [`Error::InvalidPassword`] if it's wrong,
+/// or missing when the archive has encrypted entries.
+///
+/// Only the first encrypted file is checked (by decompressing it), the others are expected to use the
+/// same password.
+pub fn check_password(archive_path: &Path, password: Option<&[u8]>) -> Result<()> {
+    let archive = match password {
+        Some(password) => Archive::with_password(archive_path, password),
+        None => Archive::new(archive_path),
+    };
+
+    let mut archive = archive.open_for_processing()?;
+
+    while let Some(header) = archive.read_header()? {
+        let entry = header.entry();
+        if entry.is_file() && entry.is_encrypted() {
+            if password.is_none() {
+                return Err(Error::password_required());
+            }
+            header.test()?;
+            break;
+        }
+        archive = header.skip()?;
+    }
+
+    Ok(())
 }
 
 /// List contents of `archive_path`, returning a vector of archive entries
@@ -52,20 +155,81 @@ pub fn list_archive(
 
     Ok(archive.open_for_listing()?.map(|item| {
         let item = item?;
-        let is_dir = item.is_directory();
-        let path = item.filename;
 
         Ok(FileInArchive {
-            path,
-            file_type: if is_dir {
+            file_type: if item.is_directory() {
                 ListFileType::Directory
             } else {
                 ListFileType::File
             },
+            size: Some(item.unpacked_size),
+            // unrar doesn't expose the packed size, nor the host OS needed to read the attributes as a mode
+            compressed_size: None,
+            modified: dos_time_to_system_time(item.file_time),
+            mode: None,
+            encrypted: item.is_encrypted(),
+            path: item.filename,
         })
     }))
 }
 
+/// Convert a MS-DOS date and time, which has no timezone, as if it was UTC.
+fn dos_time_to_system_time(dos_time: u32) -> Option<SystemTime> {
+    let date = time::Date::from_calendar_date(
+        1980 + (dos_time >> 25) as i32,
+        time::Month::try_from(((dos_time >> 21) & 0xF) as u8).ok()?,
+        ((dos_time >> 16) & 0x1F) as u8,
+    )
+    .ok()?;
+    let time = time::Time::from_hms(
+        ((dos_time >> 11) & 0x1F) as u8,
+        ((dos_time >> 5) & 0x3F) as u8,
+        ((dos_time & 0x1F) * 2) as u8,
+    )
+    .ok()?;
+
+    Some(time::PrimitiveDateTime::new(date, time).assume_utc().into())
+}
+
+/// Test every entry of the archive at `archive_path`, verifying their checksums without extracting.
+///
+/// `on_entry` is called with the size of each entry tested, or with why it failed. The archive
+/// can't be processed further after a failure, so testing stops at the first corrupted entry.
+pub fn test_archive(
+    archive_path: &Path,
+    password: Option<&[u8]>,
+    mut on_entry: impl FnMut(&Path, Result<u64>),
+) -> Result<()> {
+    let archive = match password {
+        Some(password) => Archive::with_password(archive_path, password),
+        None => Archive::new(archive_path),
+    };
+
+    let mut archive = archive.open_for_processing()?;
+
+    while let Some(header) = archive.read_header()? {
+        let entry = header.entry();
+        let path = entry.filename.clone();
+        let size = entry.unpacked_size;
+
+        if !entry.is_file() {
+            archive = header.skip()?;
+            continue;
+        }
+
+        archive = match header.test() {
+            Ok(archive) => archive,
+            Err(err) => {
+                on_entry(&path, Err(err.into()));
+                return Ok(());
+            }
+        };
+        on_entry(&path, Ok(size));
+    }
+
+    Ok(())
+}
+
 pub fn no_compression() -> Error {
     Error::UnsupportedFormat {
         reason: "Creating RAR archives is not allowed due to licensing restrictions.".into(),
@@ -1,16 +1,18 @@
 //! SevenZip archive format compress function
 
 use std::{
+    collections::HashMap,
     env,
     io::{self, BufWriter, Read, Seek, Write},
     path::{Path, PathBuf},
+    time::{Duration, SystemTime},
 };
 
 use bstr::ByteSlice;
 use fs_err as fs;
 use fs_err::PathExt;
 use same_file::Handle;
-use sevenz_rust2::ArchiveEntry;
+use sevenz_rust2::{ArchiveEntry, EncoderMethod, NtTime, encoder_options::AesEncoderOptions};
 
 use crate::{
     Result,
@@ -18,34 +20,72 @@ use crate::{
     info,
     list::{FileInArchive, ListFileType},
     utils::{
-        BytesFmt, FileVisibilityPolicy, PathFmt, cd_into_same_dir_as, ensure_parent_dir_exists, is_same_file_as_output,
+        BytesFmt, EntryFilter, FileVisibilityPolicy, PathFmt, PathTransform, Reproducible, cd_into_same_dir_as,
+        ensure_parent_dir_exists, is_same_file_as_output,
+        progress::{Progress, Side},
     },
     warning,
 };
 
-pub fn unpack_archive<R>(reader: R, output_path: &Path, password: Option<&[u8]>) -> Result<u64>
+/// Unpacks the archive given by `reader` into the folder given by `output_path`, skipping entries
+/// not selected by `entry_filter`, and changing their paths with `path_transform`.
+///
+/// Like zip, entries whose names would be extracted outside of `output_path` are skipped with a warning.
+pub fn unpack_archive<R>(
+    reader: R,
+    output_path: &Path,
+    password: Option<&[u8]>,
+    entry_filter: &EntryFilter,
+    path_transform: &PathTransform,
+) -> Result<u64>
 where
     R: Read + Seek,
 {
     let mut files_unpacked = 0;
+    let mut flattened_paths = HashMap::new();
+    // Errors of ouch can't be returned through `sevenz_rust2`, the extraction is stopped instead
+    let mut flatten_collision = None;
 
     let entry_extract_fn =
-        |entry: &ArchiveEntry, reader: &mut dyn Read, path: &PathBuf| -> Result<bool, sevenz_rust2::Error> {
+        |entry: &ArchiveEntry, reader: &mut dyn Read, _: &PathBuf| -> Result<bool, sevenz_rust2::Error> {
             // Manually handle writing all files from 7z archive (the library defaults ignore empty files)
 
-            let file_path = output_path.join(entry.name());
+            let Some(entry_path) = enclosed_name(entry.name()) else {
+                warning!(
+                    "Skipping entry {} of 7z archive, its path would be extracted outside of the output directory",
+                    PathFmt(Path::new(entry.name())),
+                );
+                // Entries of a solid block are read sequentially, so skipped ones must still be consumed
+                io::copy(reader, &mut io::sink())?;
+                return Ok(true);
+            };
+
+            let transformed_path = entry_filter
+                .matches(entry_path)
+                .then(|| path_transform.apply(entry_path, entry.is_directory()))
+                .flatten();
+            let Some(new_path) = transformed_path else {
+                io::copy(reader, &mut io::sink())?;
+                return Ok(true);
+            };
+            if let Err(err) = path_transform.check_flatten_collision(&mut flattened_paths, entry_path, &new_path) {
+                flatten_collision = Some(err);
+                return Ok(false);
+            }
+
+            let file_path = output_path.join(new_path);
 
             if entry.is_directory() {
                 info!("File {} extracted to {}", entry.name(), PathFmt(&file_path));
-                if !path.fs_err_try_exists()? {
-                    fs::create_dir_all(path)?;
+                if !file_path.fs_err_try_exists()? {
+                    fs::create_dir_all(&file_path)?;
                 }
             } else {
                 info!("extracted ({}) {}", BytesFmt(entry.size()), PathFmt(&file_path));
 
-                ensure_parent_dir_exists(path)?;
+                ensure_parent_dir_exists(&file_path)?;
 
-                let file = fs::File::create(path)?;
+                let file = fs::File::create(&file_path)?;
                 let mut writer = BufWriter::new(file);
                 io::copy(reader, &mut writer)?;
 
@@ -67,70 +107,353 @@ where
         Some(password) => sevenz_rust2::decompress_with_extract_fn_and_password(
             reader,
             output_path,
-            sevenz_rust2::Password::from(password.to_str().map_err(|err| Error::InvalidPassword {
-                reason: err.to_string(),
-            })?),
+            to_sevenz_password(Some(password))?,
             entry_extract_fn,
         )?,
         None => sevenz_rust2::decompress_with_extract_fn(reader, output_path, entry_extract_fn)?,
     }
 
+    if let Some(err) = flatten_collision {
+        return Err(err);
+    }
     Ok(files_unpacked)
 }
 
-/// List contents of `archive_path`, returning a vector of archive entries
-pub fn list_archive<R>(reader: R, password: Option<&[u8]>) -> Result<impl Iterator<Item = Result<FileInArchive>>>
+/// Write the contents of the regular files selected by `entry_filter` to `writer`, one after another.
+///
+/// Returns the number of files written.
+pub fn write_entries<R>(
+    reader: R,
+    mut writer: impl Write,
+    password: Option<&[u8]>,
+    entry_filter: &EntryFilter,
+) -> Result<u64>
 where
     R: Read + Seek,
 {
-    let mut files = Vec::new();
+    let password = to_sevenz_password(password)?;
 
-    let entry_extract_fn = |entry: &ArchiveEntry, _: &mut dyn Read, _: &PathBuf| {
-        files.push(Ok(FileInArchive {
-            path: entry.name().into(),
-            file_type: if entry.is_directory() {
-                ListFileType::Directory
-            } else {
-                ListFileType::File
-            },
-        }));
+    let mut files_written = 0;
+    let mut archive = sevenz_rust2::ArchiveReader::new(reader, password)?;
+
+    archive.for_each_entries(|entry, reader| {
+        let path = Path::new(entry.name());
+
+        // Entries of a solid block are read sequentially, so skipped ones must still be consumed
+        if entry.is_directory() || !entry_filter.matches(path) {
+            io::copy(reader, &mut io::sink())?;
+            return Ok(true);
+        }
+
+        info!("writing ({}) {}", BytesFmt(entry.size()), PathFmt(path));
+        io::copy(reader, &mut writer)?;
+        files_written += 1;
         Ok(true)
-    };
+    })?;
 
-    match password {
-        Some(password) => {
-            let password = match password.to_str() {
-                Ok(p) => p,
-                Err(err) => {
-                    return Err(Error::InvalidPassword {
-                        reason: err.to_string(),
-                    });
-                }
-            };
-            sevenz_rust2::decompress_with_extract_fn_and_password(
-                reader,
-                ".",
-                sevenz_rust2::Password::from(password),
-                entry_extract_fn,
-            )?;
+    Ok(files_written)
+}
+
+/// Validate a 7z entry name the same way as zip's `enclosed_name`.
+///
+/// Returns `None` if the name is absolute, has a drive prefix or a `..` component, checking both `/`
+/// and `\` as separators since archives might come from Windows.
+fn enclosed_name(name: &str) -> Option<&Path> {
+    if name.contains('\0') {
+        return None;
+    }
+
+    for (i, component) in name.split(['/', '\\']).enumerate() {
+        let is_drive_prefix = matches!(component.as_bytes(), [letter, b':', ..] if letter.is_ascii_alphabetic());
+        match component {
+            "" if i == 0 => return None,
+            ".." => return None,
+            _ if i == 0 && is_drive_prefix => return None,
+            _ => {}
+        }
+    }
+
+    Some(Path::new(name))
+}
+
+/// Check that `password` decrypts the archive, failing with [`Error::InvalidPassword`] if it's wrong,
+/// or missing when the archive is encrypted.
+///
+/// Only the start of the first entry is decrypted, a wrong password is usually detected there, but
+/// might only be caught by a checksum mismatch when reading the whole entry.
+pub fn check_password<R>(mut reader: R, password: Option<&[u8]>) -> Result<()>
+where
+    R: Read + Seek,
+{
+    // Enough for the decoder to hit invalid data if the password is wrong
+    const CHECKED_SIZE: u64 = 64 * 1024;
+
+    let sevenz_password = to_sevenz_password(password)?;
+    let archive = sevenz_rust2::Archive::read(&mut reader, &sevenz_password)?;
+
+    let is_encrypted = archive.blocks.iter().any(|block| {
+        block
+            .coders
+            .iter()
+            .any(|coder| coder.encoder_method_id() == EncoderMethod::ID_AES256_SHA256)
+    });
+
+    if !is_encrypted {
+        return Ok(());
+    } else if password.is_none() {
+        return Err(Error::password_required());
+    }
+
+    reader.rewind()?;
+    let mut archive = sevenz_rust2::ArchiveReader::new(reader, sevenz_password)?;
+    archive.for_each_entries(|entry, reader| {
+        if entry.size() == 0 {
+            return Ok(true);
         }
-        None => sevenz_rust2::decompress_with_extract_fn(reader, ".", entry_extract_fn)?,
+        io::copy(&mut reader.take(CHECKED_SIZE), &mut io::sink())?;
+        Ok(false)
+    })?;
+
+    Ok(())
+}
+
+/// Check if the headers (the list of entries) of the archive are encrypted, rewinding `reader` after.
+pub fn has_encrypted_headers<R>(mut reader: R) -> Result<bool>
+where
+    R: Read + Seek,
+{
+    // Encrypted headers can't be read without the password
+    let encrypted = matches!(
+        sevenz_rust2::Archive::read(&mut reader, &sevenz_rust2::Password::empty()),
+        Err(sevenz_rust2::Error::PasswordRequired)
+    );
+    reader.rewind()?;
+    Ok(encrypted)
+}
+
+/// Convert a password given by the user, 7z passwords are encoded as UTF-16 so they must be valid UTF-8.
+fn to_sevenz_password(password: Option<&[u8]>) -> Result<sevenz_rust2::Password> {
+    match password {
+        Some(password) => Ok(sevenz_rust2::Password::from(password.to_str().map_err(|err| {
+            Error::InvalidPassword {
+                reason: err.to_string(),
+            }
+        })?)),
+        None => Ok(sevenz_rust2::Password::empty()),
+    }
+}
+
+/// Encrypt the entries written by `writer` with AES-256, and the archive headers (the list of entries)
+/// if `encrypt_headers` is set.
+fn set_encryption<W>(
+    writer: &mut sevenz_rust2::ArchiveWriter<W>,
+    password: Option<&[u8]>,
+    encrypt_headers: bool,
+) -> Result<()>
+where
+    W: Write + Seek,
+{
+    if password.is_some() {
+        writer.set_content_methods(vec![
+            AesEncoderOptions::new(to_sevenz_password(password)?).into(),
+            EncoderMethod::LZMA2.into(),
+        ]);
     }
+    writer.set_encrypt_header(password.is_some() && encrypt_headers);
+    Ok(())
+}
+
+/// List contents of `archive_path`, returning a vector of archive entries
+///
+/// Only the archive headers are read. The password is only needed if the headers are encrypted.
+pub fn list_archive<R>(mut reader: R, password: Option<&[u8]>) -> Result<impl Iterator<Item = Result<FileInArchive>>>
+where
+    R: Read + Seek,
+{
+    let password = to_sevenz_password(password)?;
+
+    let archive = sevenz_rust2::Archive::read(&mut reader, &password)?;
+    let stream_map = &archive.stream_map;
+
+    let files: Vec<_> = archive
+        .files
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            let block_index = stream_map.file_block_index[i];
+            let block = block_index.map(|index| &archive.blocks[index]);
+
+            // Entries of solid blocks are compressed together, their compressed size is unknown
+            let is_alone_in_block = block_index.is_some_and(|index| {
+                stream_map
+                    .file_block_index
+                    .iter()
+                    .filter(|&&other| other == Some(index))
+                    .count()
+                    == 1
+            });
+
+            Ok(FileInArchive {
+                path: entry.name().into(),
+                file_type: if entry.is_directory() {
+                    ListFileType::Directory
+                } else {
+                    ListFileType::File
+                },
+                size: Some(entry.size()),
+                compressed_size: is_alone_in_block.then_some(entry.compressed_size),
+                modified: entry.has_last_modified_date.then(|| entry.last_modified_date().into()),
+                // 7-Zip stores unix modes in the high bits of the attributes, flagged by 0x8000
+                mode: (entry.has_windows_attributes && entry.windows_attributes & 0x8000 != 0)
+                    .then_some(entry.windows_attributes >> 16),
+                encrypted: block.is_some_and(|block| {
+                    block
+                        .coders
+                        .iter()
+                        .any(|coder| coder.encoder_method_id() == sevenz_rust2::EncoderMethod::ID_AES256_SHA256)
+                }),
+            })
+        })
+        .collect();
 
     Ok(files.into_iter())
 }
 
+/// Read every entry of the archive to the end, verifying their CRC-32 checksums.
+///
+/// `on_entry` is called with the size of each entry read, or with why it couldn't be read. Entries
+/// of a solid block depend on the previous ones, so testing stops at the first corrupted entry.
+pub fn test_archive<R>(reader: R, password: Option<&[u8]>, mut on_entry: impl FnMut(&Path, Result<u64>)) -> Result<()>
+where
+    R: Read + Seek,
+{
+    let password = to_sevenz_password(password)?;
+
+    let mut archive = sevenz_rust2::ArchiveReader::new(reader, password)?;
+
+    archive.for_each_entries(|entry, reader| {
+        let path = Path::new(entry.name());
+        match io::copy(reader, &mut io::sink()) {
+            Ok(size) => {
+                on_entry(path, Ok(size));
+                Ok(true)
+            }
+            Err(err) => {
+                on_entry(path, Err(err.into()));
+                Ok(false)
+            }
+        }
+    })?;
+
+    Ok(())
+}
+
+/// Build a 7z archive of `files`, encrypted with AES-256 if `password` is set.
+///
+/// Entry names are only encrypted with `encrypt_headers`, otherwise the archive can be listed without
+/// the password. The input files are counted by `progress` as they're read.
 pub fn build_archive<W>(
     files: &[PathBuf],
     output_path: &Path,
     writer: W,
     file_visibility_policy: FileVisibilityPolicy,
+    password: Option<&[u8]>,
+    encrypt_headers: bool,
+    path_transform: &PathTransform,
+    reproducible: Option<Reproducible>,
+    progress: &Progress,
+) -> Result<W>
+where
+    W: Write + Seek,
+{
+    let mut writer = sevenz_rust2::ArchiveWriter::new(writer)?;
+    set_encryption(&mut writer, password, encrypt_headers)?;
+    add_files(
+        &mut writer,
+        files,
+        output_path,
+        None,
+        &file_visibility_policy,
+        path_transform,
+        reproducible,
+        Some(progress),
+    )?;
+    let bytes = writer.finish()?;
+    Ok(bytes)
+}
+
+/// Write a copy of the archive read from `reader` to `writer` with the entries of `files` added,
+/// existing entries for which `is_replaced` returns true are left out.
+///
+/// 7z archives can't be appended to in place, kept entries are decompressed and compressed again. If
+/// `password` is set, the new archive is encrypted with it, and its headers too if they were encrypted.
+/// `temp_path`, the temporary file `writer` writes to, is skipped if it's among the files.
+pub fn update_archive<R, W>(
+    mut reader: R,
+    writer: W,
+    password: Option<&[u8]>,
+    is_replaced: impl Fn(&Path) -> bool,
+    files: &[PathBuf],
+    output_path: &Path,
+    temp_path: Option<&Path>,
+    file_visibility_policy: &FileVisibilityPolicy,
 ) -> Result<W>
 where
+    R: Read + Seek,
     W: Write + Seek,
 {
+    let headers_encrypted = has_encrypted_headers(&mut reader)?;
+
     let mut writer = sevenz_rust2::ArchiveWriter::new(writer)?;
+    set_encryption(&mut writer, password, headers_encrypted)?;
+    let mut archive = sevenz_rust2::ArchiveReader::new(reader, to_sevenz_password(password)?)?;
+
+    archive.for_each_entries(|entry, reader| {
+        // Entries of a solid block are read sequentially, so skipped ones must still be consumed
+        if is_replaced(Path::new(entry.name())) {
+            io::copy(reader, &mut io::sink())?;
+            return Ok(true);
+        }
+
+        let entry_data = (!entry.is_directory()).then_some(reader);
+        writer.push_archive_entry(entry.clone(), entry_data)?;
+        Ok(true)
+    })?;
+
+    add_files(
+        &mut writer,
+        files,
+        output_path,
+        temp_path,
+        file_visibility_policy,
+        &PathTransform::default(),
+        None,
+        None,
+    )?;
+    let bytes = writer.finish()?;
+    Ok(bytes)
+}
+
+/// Add `files` (and the contents of directories) as entries of the archive being built by `writer`,
+/// with their names changed by `path_transform`.
+///
+/// If `reproducible` is set, only the modification times are stored, clamped. The files read are counted
+/// by `progress`, if set.
+fn add_files<W>(
+    writer: &mut sevenz_rust2::ArchiveWriter<W>,
+    files: &[PathBuf],
+    output_path: &Path,
+    temp_path: Option<&Path>,
+    file_visibility_policy: &FileVisibilityPolicy,
+    path_transform: &PathTransform,
+    reproducible: Option<Reproducible>,
+    progress: Option<&Progress>,
+) -> Result<()>
+where
+    W: Write + Seek,
+{
     let output_handle = Handle::from_path(output_path);
+    let temp_handle = temp_path.and_then(|temp_path| Handle::from_path(temp_path).ok());
 
     for filename in files {
         let previous_location = cd_into_same_dir_as(filename)?;
@@ -151,29 +474,53 @@ where
                 continue;
             }
 
+            if let Some(handle) = temp_handle.as_ref()
+                && is_same_file_as_output(path, handle)
+            {
+                continue;
+            }
+
             info!("Compressing {}", PathFmt(path));
 
             // use metadata instead of symlink_metadata, 7z doesn't support symlinks
             let metadata = path.metadata()?;
 
-            let entry_name = path.to_str().ok_or_else(|| {
+            let Some(entry_name) = path_transform.apply(path, metadata.is_dir()) else {
+                continue;
+            };
+            let entry_name = entry_name.to_str().ok_or_else(|| {
                 FinalError::with_title("7z requires that all entry names are valid UTF-8")
-                    .detail(format!("File {} has a non-UTF-8 name", PathFmt(path)))
+                    .detail(format!("File {} has a non-UTF-8 name", PathFmt(&entry_name)))
             })?;
 
-            let entry = sevenz_rust2::ArchiveEntry::from_path(path, entry_name.to_owned());
+            let mut entry = sevenz_rust2::ArchiveEntry::from_path(path, entry_name.to_owned());
+            if let Some(reproducible) = reproducible {
+                let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(reproducible.mtime(&metadata));
+                entry.has_last_modified_date = false;
+                if let Ok(date) = NtTime::try_from(mtime) {
+                    entry.last_modified_date = date;
+                    entry.has_last_modified_date = true;
+                }
+                entry.has_creation_date = false;
+                entry.has_access_date = false;
+            }
             let entry_data = if metadata.is_dir() {
                 None
             } else {
                 Some(fs::File::open(path)?)
             };
 
-            writer.push_archive_entry::<fs::File>(entry, entry_data)?;
+            match progress {
+                Some(progress) => writer.push_archive_entry(
+                    entry,
+                    entry_data.map(|file| progress.count_read(file, Side::Uncompressed)),
+                )?,
+                None => writer.push_archive_entry(entry, entry_data)?,
+            };
         }
 
         env::set_current_dir(previous_location)?;
     }
 
-    let bytes = writer.finish()?;
-    Ok(bytes)
+    Ok(())
 }
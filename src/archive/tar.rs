@@ -7,7 +7,8 @@ use std::{
     env,
     io::{self, prelude::*},
     ops::Not,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
+    time::{Duration, SystemTime},
 };
 
 use fs_err as fs;
@@ -19,72 +20,137 @@ use crate::{
     info,
     list::{FileInArchive, ListFileType},
     utils::{
-        self, BytesFmt, FileType, FileVisibilityPolicy, PathFmt, canonicalize, create_symlink, is_same_file_as_output,
-        read_file_type, set_permission_mode,
+        self, BytesFmt, EntryFilter, FileType, FileVisibilityPolicy, PathFmt, PathTransform, Reproducible,
+        canonicalize, create_symlink, ensure_parent_dir_exists, is_same_file_as_output, read_file_type,
+        set_permission_mode,
     },
     warning,
 };
 
-/// Unpacks the archive given by `archive` into the folder given by `into`.
+/// Unpacks the archive given by `archive` into the folder given by `into`, skipping entries not
+/// selected by `entry_filter`, and changing their paths (and the targets of hardlinks) with
+/// `path_transform`.
+///
+/// Entries that could write outside of `output_folder` (through absolute paths, `..` or symlinks) are
+/// rejected.
 /// Assumes that output_folder is empty
-pub fn unpack_archive(reader: impl Read, output_folder: &Path) -> Result<u64> {
-    let mut archive = tar::Archive::new(reader);
+pub fn unpack_archive(
+    reader: impl Read,
+    output_folder: &Path,
+    entry_filter: &EntryFilter,
+    path_transform: &PathTransform,
+) -> Result<u64> {
+    unpack_entries(
+        tar::Archive::new(reader).entries()?,
+        output_folder,
+        entry_filter,
+        path_transform,
+    )
+}
+
+/// Like [`unpack_archive`], but seeks over the contents of the entries not selected by `entry_filter`
+/// instead of reading them.
+pub fn unpack_archive_with_seek(
+    reader: impl Read + Seek,
+    output_folder: &Path,
+    entry_filter: &EntryFilter,
+    path_transform: &PathTransform,
+) -> Result<u64> {
+    unpack_entries(
+        tar::Archive::new(reader).entries_with_seek()?,
+        output_folder,
+        entry_filter,
+        path_transform,
+    )
+}
 
+fn unpack_entries(
+    entries: tar::Entries<impl Read>,
+    output_folder: &Path,
+    entry_filter: &EntryFilter,
+    path_transform: &PathTransform,
+) -> Result<u64> {
     let mut files_unpacked = 0;
     let mut read_only_dirs_and_modes = Vec::new();
+    let mut flattened_paths = HashMap::new();
 
-    for entry in archive.entries()? {
+    for entry in entries {
         let mut entry = entry?;
+        let entry_path = entry.path()?.into_owned();
+
+        if !entry_filter.matches(&entry_path) {
+            continue;
+        }
 
-        match entry.header().entry_type() {
+        let entry_type = entry.header().entry_type();
+        let Some(relative_path) = path_transform.apply(&entry_path, entry_type.is_dir()) else {
+            continue;
+        };
+        let hardlink_target = match entry_type {
+            tar::EntryType::Link => {
+                let target = entry
+                    .link_name()?
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Missing hardlink target"))?;
+                match path_transform.apply(&target, false) {
+                    Some(target) => Some(target),
+                    None => {
+                        warning!(
+                            "Skipping hardlink {}, its target {} was stripped",
+                            PathFmt(&entry_path),
+                            PathFmt(&target)
+                        );
+                        continue;
+                    }
+                }
+            }
+            _ => None,
+        };
+
+        check_entry_is_safe(&relative_path, hardlink_target.as_deref(), output_folder)?;
+        path_transform.check_flatten_collision(&mut flattened_paths, &entry_path, &relative_path)?;
+        let full_path = output_folder.join(&relative_path);
+
+        match entry_type {
             tar::EntryType::Symlink => {
-                let relative_path = entry.path()?;
-                let full_path = output_folder.join(&relative_path);
                 let target = entry
                     .link_name()?
                     .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Missing symlink target"))?;
 
+                // Parent directory entries might have been filtered out
+                ensure_parent_dir_exists(&full_path)?;
                 create_symlink(&target, &full_path)?;
             }
             tar::EntryType::Link => {
-                let link_path = entry.path()?;
-                let target = entry
-                    .link_name()?
-                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Missing hardlink target"))?;
-
-                let full_link_path = output_folder.join(&link_path);
-                let full_target_path = output_folder.join(&target);
+                // Unwrap safety: set for every hardlink above
+                let full_target_path = output_folder.join(hardlink_target.unwrap());
 
-                fs::hard_link(&full_target_path, &full_link_path)?;
+                ensure_parent_dir_exists(&full_path)?;
+                fs::hard_link(&full_target_path, &full_path)?;
             }
             tar::EntryType::Regular => {
-                entry.unpack_in(output_folder)?;
+                ensure_parent_dir_exists(&full_path)?;
+                entry.unpack(&full_path)?;
             }
             tar::EntryType::Directory => {
                 let original_mode = entry.header().mode()?;
                 let is_writeable = (original_mode & 0o200) != 0;
 
                 // this is no-op when dir already exists, errs if a file with another type is found there
-                entry.unpack_in(output_folder)?;
+                ensure_parent_dir_exists(&full_path)?;
+                entry.unpack(&full_path)?;
 
                 if cfg!(unix) && is_writeable.not() {
                     // We unpacked a read-only directory, make it writeable so that we can
                     // create the files inside of it, by the end, restore the original mode
-                    let original_path = entry.path()?.to_path_buf();
-                    let unpacked = output_folder.join(&original_path);
-                    set_permission_mode(&unpacked, original_mode | 0o200)?;
+                    set_permission_mode(&full_path, original_mode | 0o200)?;
 
-                    read_only_dirs_and_modes.push((original_path, original_mode));
+                    read_only_dirs_and_modes.push((full_path.clone(), original_mode));
                 }
             }
             _ => continue,
         }
 
-        info!(
-            "extracted ({}) {}",
-            BytesFmt(entry.size()),
-            PathFmt(&output_folder.join(entry.path()?)),
-        );
+        info!("extracted ({}) {}", BytesFmt(entry.size()), PathFmt(&full_path));
         files_unpacked += 1;
     }
 
@@ -98,16 +164,162 @@ pub fn unpack_archive(reader: impl Read, output_folder: &Path) -> Result<u64> {
     Ok(files_unpacked)
 }
 
+/// Write the contents of the regular files selected by `entry_filter` to `writer`, one after another.
+///
+/// Returns the number of files written.
+pub fn write_entries(reader: impl Read, writer: impl Write, entry_filter: &EntryFilter) -> Result<u64> {
+    write_selected_entries(tar::Archive::new(reader).entries()?, writer, entry_filter)
+}
+
+/// Like [`write_entries`], but seeks over the contents of the entries not selected by `entry_filter`
+/// instead of reading them.
+pub fn write_entries_with_seek(
+    reader: impl Read + Seek,
+    writer: impl Write,
+    entry_filter: &EntryFilter,
+) -> Result<u64> {
+    write_selected_entries(tar::Archive::new(reader).entries_with_seek()?, writer, entry_filter)
+}
+
+fn write_selected_entries(
+    entries: tar::Entries<impl Read>,
+    mut writer: impl Write,
+    entry_filter: &EntryFilter,
+) -> Result<u64> {
+    let mut files_written = 0;
+
+    for entry in entries {
+        let mut entry = entry?;
+
+        if !entry.header().entry_type().is_file() || !entry_filter.matches(&entry.path()?) {
+            continue;
+        }
+
+        info!("writing ({}) {}", BytesFmt(entry.size()), PathFmt(&entry.path()?));
+        io::copy(&mut entry, &mut writer)?;
+        files_written += 1;
+    }
+
+    Ok(files_written)
+}
+
+/// Check that extracting an entry to `path` (and its hardlink target, if any) inside of `output_folder`
+/// can't write outside of it.
+///
+/// Fails with an error naming the entry if its path (or hardlink target) is absolute, contains `..`
+/// or goes through a symlink extracted before.
+fn check_entry_is_safe(path: &Path, hardlink_target: Option<&Path>, output_folder: &Path) -> Result<()> {
+    let mut unsafe_reason = find_unsafe_path_reason(path, output_folder, true);
+
+    if unsafe_reason.is_none()
+        && let Some(target) = hardlink_target
+    {
+        unsafe_reason = find_unsafe_path_reason(target, output_folder, false)
+            .map(|reason| format!("Hardlink target {}: {reason}", PathFmt(target)));
+    }
+
+    match unsafe_reason {
+        None => Ok(()),
+        Some(reason) => {
+            let error = FinalError::with_title(format!("Refusing to extract unsafe entry {}", PathFmt(path)))
+                .detail(reason)
+                .detail("Extracting it could write files outside of the output directory")
+                .hint(
+                    "The archive might have been crafted to overwrite files, make sure it comes from a trusted source",
+                );
+            Err(error.into())
+        }
+    }
+}
+
+/// Why extracting to `path` (relative to `output_folder`) is unsafe, if it is.
+///
+/// The last component is only checked for symlinks if `check_last_component` is set, hardlinks to
+/// symlinks are fine because `fs::hard_link` doesn't follow them.
+fn find_unsafe_path_reason(path: &Path, output_folder: &Path, check_last_component: bool) -> Option<String> {
+    let mut current = output_folder.to_path_buf();
+    let mut components = path.components().peekable();
+
+    while let Some(component) = components.next() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => return Some("Path is absolute".into()),
+            Component::ParentDir => return Some("Path contains \"..\"".into()),
+            Component::CurDir => {}
+            Component::Normal(name) => {
+                current.push(name);
+                let is_last_component = components.peek().is_none();
+
+                if (check_last_component || !is_last_component) && current.is_symlink() {
+                    let symlink = current.strip_prefix(output_folder).unwrap_or(&current);
+                    return Some(format!("Path goes through the symlink {}", PathFmt(symlink)));
+                }
+            }
+        }
+    }
+
+    None
+}
+
 /// List contents of `archive`, returning a vector of archive entries
 pub fn list_archive(mut archive: tar::Archive<impl Read>) -> Result<impl Iterator<Item = Result<FileInArchive>>> {
-    let entries = archive.entries()?.map(|file| {
+    Ok(list_entries(archive.entries()?))
+}
+
+/// Like [`list_archive`], but seeks over the contents of the entries instead of reading them.
+pub fn list_archive_with_seek(
+    mut archive: tar::Archive<impl Read + Seek>,
+) -> Result<impl Iterator<Item = Result<FileInArchive>>> {
+    Ok(list_entries(archive.entries_with_seek()?))
+}
+
+fn list_entries(entries: tar::Entries<impl Read>) -> std::vec::IntoIter<Result<FileInArchive>> {
+    let entries = entries.map(|file| {
         let file = file?;
+        let header = file.header();
         let path = file.path()?.into_owned();
-        let file_type = get_file_type(file.header(), &file)?;
-        Ok(FileInArchive { path, file_type })
+        let file_type = get_file_type(header, &file)?;
+
+        Ok(FileInArchive {
+            path,
+            file_type,
+            size: Some(file.size()),
+            compressed_size: None,
+            modified: header
+                .mtime()
+                .ok()
+                .map(|mtime| SystemTime::UNIX_EPOCH + Duration::from_secs(mtime)),
+            mode: header.mode().ok(),
+            encrypted: false,
+        })
     });
 
-    Ok(entries.collect::<Vec<_>>().into_iter())
+    entries.collect::<Vec<_>>().into_iter()
+}
+
+/// Read every entry of the archive to the end, so the checksums of the decoders below it are verified.
+///
+/// `on_entry` is called with the size of each entry read, or with why it couldn't be read. Entries
+/// after a corrupted one can't be trusted, so testing stops at the first corrupted entry.
+pub fn test_archive(reader: impl Read, mut on_entry: impl FnMut(&Path, Result<u64>)) -> Result<()> {
+    let mut archive = tar::Archive::new(reader);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+
+        match io::copy(&mut entry, &mut io::sink()) {
+            Ok(size) => on_entry(&path, Ok(size)),
+            Err(err) => {
+                on_entry(&path, Err(err.into()));
+                return Ok(());
+            }
+        }
+    }
+
+    // Compression formats might have checksums after the end of the tar archive (e.g. gzip's trailer)
+    io::copy(&mut archive.into_inner(), &mut io::sink())?;
+
+    Ok(())
 }
 
 fn get_file_type(header: &tar::Header, file: &tar::Entry<impl Read>) -> Result<ListFileType> {
@@ -132,12 +344,48 @@ pub fn build_archive<W>(
     writer: W,
     file_visibility_policy: FileVisibilityPolicy,
     follow_symlinks: bool,
+    path_transform: &PathTransform,
+    reproducible: Option<Reproducible>,
 ) -> Result<W>
 where
     W: Write,
 {
     let mut builder = tar::Builder::new(writer);
+    append_files(
+        &mut builder,
+        explicit_paths,
+        output_path,
+        None,
+        &file_visibility_policy,
+        follow_symlinks,
+        path_transform,
+        reproducible,
+    )?;
+    Ok(builder.into_inner()?)
+}
+
+/// Append `explicit_paths` (and the contents of directories) to the archive being built by `builder`.
+///
+/// Hardlinks are stored as links to the first path seen with the same inode, symlinks are stored as
+/// links unless `follow_symlinks` is set. Entry names are changed with `path_transform`.
+///
+/// If `reproducible` is set, the headers of files and directories have no owner, normalized permissions
+/// and clamped modification times. `temp_path`, the temporary file an archive is rewritten to, is skipped.
+pub fn append_files<W>(
+    builder: &mut tar::Builder<W>,
+    explicit_paths: &[PathBuf],
+    output_path: &Path,
+    temp_path: Option<&Path>,
+    file_visibility_policy: &FileVisibilityPolicy,
+    follow_symlinks: bool,
+    path_transform: &PathTransform,
+    reproducible: Option<Reproducible>,
+) -> Result<()>
+where
+    W: Write,
+{
     let output_handle = Handle::from_path(output_path);
+    let temp_handle = temp_path.and_then(|temp_path| Handle::from_path(temp_path).ok());
     let mut seen_inode: HashMap<(u64, u64), PathBuf> = HashMap::new();
 
     for explicit_path in explicit_paths {
@@ -160,6 +408,12 @@ where
                 continue;
             }
 
+            if let Some(handle) = temp_handle.as_ref()
+                && is_same_file_as_output(&path, handle)
+            {
+                continue;
+            }
+
             info!("Compressing {}", PathFmt(&path));
 
             let (metadata, file_type) = {
@@ -170,6 +424,10 @@ where
                 }
             };
 
+            let Some(entry_name) = path_transform.apply(&path, file_type.is_directory()) else {
+                continue;
+            };
+
             // Treat unix hardlinks (ignore directory, since user-created directory hard links are
             // not a thing)
             //
@@ -186,16 +444,18 @@ where
                         header.set_entry_type(tar::EntryType::Link);
                         header.set_size(0);
 
-                        builder.append_link(&mut header, &path, target_path).map_err(|err| {
-                            FinalError::with_title("Could not create archive")
-                                .detail(format!("Error appending hard link {}: {err}", PathFmt(&path)))
-                        })?;
+                        builder
+                            .append_link(&mut header, &entry_name, target_path)
+                            .map_err(|err| {
+                                FinalError::with_title("Could not create archive")
+                                    .detail(format!("Error appending hard link {}: {err}", PathFmt(&path)))
+                            })?;
                         continue; // skip handling this file
                     }
                     None => {
                         // First time we see this file, let it be processed normally by the
                         // code below, but save it to this hashmap
-                        seen_inode.insert(inode_identifier, path.to_path_buf());
+                        seen_inode.insert(inode_identifier, entry_name.clone());
                     }
                 }
             }
@@ -203,15 +463,26 @@ where
             match file_type {
                 FileType::Regular => {
                     let mut file = fs::File::open(&path)?;
-                    builder.append_file(&path, file.file_mut()).map_err(|err| {
+                    let result = match reproducible {
+                        Some(reproducible) => {
+                            let mut header = reproducible_header(&metadata, reproducible);
+                            builder.append_data(&mut header, &entry_name, &mut file)
+                        }
+                        None => builder.append_file(&entry_name, file.file_mut()),
+                    };
+                    result.map_err(|err| {
                         FinalError::with_title("Could not create archive")
                             .detail("Unexpected error while trying to read file")
                             .detail(format!("Error: {err}"))
                     })?;
                 }
-                FileType::Directory => {
-                    builder.append_dir(&path, &path)?;
-                }
+                FileType::Directory => match reproducible {
+                    Some(reproducible) => {
+                        let mut header = reproducible_header(&metadata, reproducible);
+                        builder.append_data(&mut header, &entry_name, io::empty())?;
+                    }
+                    None => builder.append_dir(&entry_name, &path)?,
+                },
                 FileType::Symlink => {
                     let target_path = path.read_link()?;
 
@@ -219,16 +490,44 @@ where
                     header.set_entry_type(tar::EntryType::Symlink);
                     header.set_size(0);
 
-                    builder.append_link(&mut header, &path, &target_path).map_err(|err| {
-                        FinalError::with_title("Could not create archive")
-                            .detail("Unexpected error while trying to read link")
-                            .detail(format!("Error: {err}"))
-                    })?;
+                    builder
+                        .append_link(&mut header, &entry_name, &target_path)
+                        .map_err(|err| {
+                            FinalError::with_title("Could not create archive")
+                                .detail("Unexpected error while trying to read link")
+                                .detail(format!("Error: {err}"))
+                        })?;
                 }
             }
         }
         env::set_current_dir(previous_location)?;
     }
 
-    Ok(builder.into_inner()?)
+    Ok(())
+}
+
+/// Header of a file or directory with `metadata`, without its owner, with permissions normalized by
+/// [`tar::HeaderMode::Deterministic`] and the modification time clamped by `reproducible`.
+fn reproducible_header(metadata: &std::fs::Metadata, reproducible: Reproducible) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_metadata_in_mode(metadata, tar::HeaderMode::Deterministic);
+    header.set_mtime(reproducible.mtime(metadata));
+    header
+}
+
+/// Find where the entries of a tar archive end, that is, the offset of its end-of-archive marker.
+///
+/// New entries can be appended by overwriting the archive from this offset.
+pub fn find_end_of_archive(reader: impl Read) -> Result<u64> {
+    let mut archive = tar::Archive::new(reader);
+    let mut end = 0;
+
+    for entry in archive.entries()? {
+        let entry = entry?;
+        // Entry data is padded to a multiple of the 512 bytes block size
+        let size = entry.header().entry_size()?.next_multiple_of(512);
+        end = entry.raw_file_position() + size;
+    }
+
+    Ok(end)
 }
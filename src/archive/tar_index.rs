@@ -0,0 +1,349 @@
+//! Index of the entries of a compressed tar archive, written next to it as `<archive>.ouchidx` by
+//! `ouch compress --index`, so `ouch list` can show its contents without decompressing it.
+
+use std::{
+    ffi::OsString,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use bstr::ByteSlice;
+use fs_err as fs;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    BUFFER_CAPACITY, Result,
+    list::{FileInArchive, ListFileType},
+    utils::PathFmt,
+    warning,
+};
+
+/// Bumped on incompatible changes, indexes of other versions are ignored
+const INDEX_VERSION: u32 = 2;
+const BLOCK_SIZE: u64 = 512;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TarIndex {
+    version: u32,
+    archive_size: u64,
+    /// CRC32 of the whole archive, to check that the index still matches it
+    archive_crc32: u32,
+    entries: Vec<TarIndexEntry>,
+}
+
+/// An entry of a tar archive, with the offsets of its headers and data in the uncompressed archive
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TarIndexEntry {
+    pub path: PathBuf,
+    #[serde(flatten)]
+    pub entry_type: TarIndexEntryType,
+    /// Offset of the first header of the entry, GNU long name and PAX headers come before the main one
+    pub header_offset: u64,
+    pub data_offset: u64,
+    pub size: u64,
+    /// Modification time in seconds since the Unix epoch
+    pub modified: Option<u64>,
+    pub mode: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum TarIndexEntryType {
+    File,
+    Directory,
+    Symlink { target: PathBuf },
+    Hardlink { target: PathBuf },
+}
+
+impl From<TarIndexEntry> for FileInArchive {
+    fn from(entry: TarIndexEntry) -> Self {
+        Self {
+            path: entry.path,
+            file_type: match entry.entry_type {
+                TarIndexEntryType::File => ListFileType::File,
+                TarIndexEntryType::Directory => ListFileType::Directory,
+                TarIndexEntryType::Symlink { target } => ListFileType::Symlink { target },
+                TarIndexEntryType::Hardlink { target } => ListFileType::Hardlink { target },
+            },
+            size: Some(entry.size),
+            compressed_size: None,
+            modified: entry
+                .modified
+                .map(|mtime| SystemTime::UNIX_EPOCH + Duration::from_secs(mtime)),
+            mode: entry.mode,
+            encrypted: false,
+        }
+    }
+}
+
+/// Path of the index of the archive at `archive_path`, e.g. "backup.tar.zst.ouchidx".
+pub fn index_path(archive_path: &Path) -> PathBuf {
+    let mut path = OsString::from(archive_path);
+    path.push(".ouchidx");
+    PathBuf::from(path)
+}
+
+/// Write the index of the archive at `archive_path`, which must be complete already.
+pub fn write_index(archive_path: &Path, entries: Vec<TarIndexEntry>) -> Result<()> {
+    let (archive_size, archive_crc32) = read_size_and_checksum(archive_path)?;
+    let index = TarIndex {
+        version: INDEX_VERSION,
+        archive_size,
+        archive_crc32,
+        entries,
+    };
+
+    let mut writer = BufWriter::new(fs::File::create(index_path(archive_path))?);
+    serde_json::to_writer(&mut writer, &index)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Read the entries of the archive at `archive_path` from its index, if it has one that matches it.
+///
+/// Warns about indexes that don't match the archive anymore, e.g. because it was overwritten.
+pub fn read_index(archive_path: &Path) -> Result<Option<Vec<FileInArchive>>> {
+    let index_path = index_path(archive_path);
+    if !index_path.exists() {
+        return Ok(None);
+    }
+
+    let index: Option<TarIndex> = serde_json::from_reader(BufReader::new(fs::File::open(&index_path)?)).ok();
+    let Some(index) = index.filter(|index| index.version == INDEX_VERSION) else {
+        warning!("Ignoring the unreadable index {}", PathFmt(&index_path));
+        return Ok(None);
+    };
+
+    if (index.archive_size, index.archive_crc32) != read_size_and_checksum(archive_path)? {
+        warning!(
+            "Ignoring the index {}, it doesn't match the archive anymore",
+            PathFmt(&index_path)
+        );
+        return Ok(None);
+    }
+
+    Ok(Some(index.entries.into_iter().map(FileInArchive::from).collect()))
+}
+
+/// Size and CRC32 of the archive at `archive_path`.
+///
+/// The whole archive is hashed, its size and last bytes alone don't tell archives apart: an uncompressed
+/// tar ends with zeros, whatever its entries are.
+fn read_size_and_checksum(archive_path: &Path) -> Result<(u64, u32)> {
+    let mut reader = BufReader::with_capacity(BUFFER_CAPACITY, fs::File::open(archive_path)?);
+    let mut hasher = crc32fast::Hasher::new();
+    let mut size = 0;
+
+    loop {
+        let buffer = reader.fill_buf()?;
+        if buffer.is_empty() {
+            break;
+        }
+        hasher.update(buffer);
+        let amount = buffer.len();
+        size += amount as u64;
+        reader.consume(amount);
+    }
+
+    Ok((size, hasher.finalize()))
+}
+
+/// Passes a tar archive through to the inner writer, recording the entries in its headers.
+pub struct TarIndexer<W: Write> {
+    inner: W,
+    /// Number of bytes written
+    position: u64,
+    /// Offset of the next header, data is written until there
+    next_header: u64,
+    header: Vec<u8>,
+    /// Offset of the first header of the entry being read
+    entry_start: Option<u64>,
+    /// Type and data of a header extending the next entry, like a GNU long name
+    extension: Option<(tar::EntryType, Vec<u8>)>,
+    long_path: Option<PathBuf>,
+    long_link: Option<PathBuf>,
+    entries: Vec<TarIndexEntry>,
+}
+
+impl<W: Write> TarIndexer<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            position: 0,
+            next_header: 0,
+            header: Vec::with_capacity(BLOCK_SIZE as usize),
+            entry_start: None,
+            extension: None,
+            long_path: None,
+            long_link: None,
+            entries: Vec::new(),
+        }
+    }
+
+    /// The entries written so far.
+    pub fn into_entries(self) -> Vec<TarIndexEntry> {
+        self.entries
+    }
+
+    fn observe(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            if self.position < self.next_header {
+                let amount = (data.len() as u64).min(self.next_header - self.position) as usize;
+                if let Some((_, extension_data)) = &mut self.extension {
+                    extension_data.extend_from_slice(&data[..amount]);
+                }
+                self.position += amount as u64;
+                data = &data[amount..];
+            } else {
+                let amount = data.len().min(BLOCK_SIZE as usize - self.header.len());
+                self.header.extend_from_slice(&data[..amount]);
+                self.position += amount as u64;
+                data = &data[amount..];
+
+                if self.header.len() == BLOCK_SIZE as usize {
+                    self.read_header();
+                    self.header.clear();
+                }
+            }
+
+            if self.position == self.next_header
+                && let Some((entry_type, extension_data)) = self.extension.take()
+            {
+                self.read_extension(entry_type, &extension_data);
+            }
+        }
+    }
+
+    fn read_header(&mut self) {
+        // The end of the archive is marked by blocks of zeros
+        if self.header.iter().all(|&byte| byte == 0) {
+            self.next_header = self.position;
+            return;
+        }
+
+        let header = tar::Header::from_byte_slice(&self.header);
+        let header_offset = *self.entry_start.get_or_insert(self.position - BLOCK_SIZE);
+        let data_offset = self.position;
+        let data_size = header.entry_size().unwrap_or(0);
+        self.next_header = data_offset + data_size.next_multiple_of(BLOCK_SIZE);
+
+        let entry_type = header.entry_type();
+        if entry_type.is_gnu_longname() || entry_type.is_gnu_longlink() || entry_type.is_pax_local_extensions() {
+            self.extension = Some((entry_type, Vec::with_capacity(data_size as usize)));
+            return;
+        }
+        self.entry_start = None;
+        if entry_type.is_pax_global_extensions() {
+            return;
+        }
+
+        let path = self
+            .long_path
+            .take()
+            .unwrap_or_else(|| header.path_bytes().to_path_lossy().into_owned());
+        let link = self
+            .long_link
+            .take()
+            .or_else(|| header.link_name_bytes().map(|link| link.to_path_lossy().into_owned()));
+
+        let entry_type = match (entry_type, link) {
+            (tar::EntryType::Directory, _) => TarIndexEntryType::Directory,
+            (tar::EntryType::Symlink, Some(target)) => TarIndexEntryType::Symlink { target },
+            (tar::EntryType::Link, Some(target)) => TarIndexEntryType::Hardlink { target },
+            _ => TarIndexEntryType::File,
+        };
+
+        self.entries.push(TarIndexEntry {
+            path,
+            entry_type,
+            header_offset,
+            data_offset,
+            size: header.size().unwrap_or(data_size),
+            modified: header.mtime().ok(),
+            mode: header.mode().ok(),
+        });
+    }
+
+    fn read_extension(&mut self, entry_type: tar::EntryType, data: &[u8]) {
+        // GNU long names end with a NUL
+        let name = |data: &[u8]| {
+            data.split(|&byte| byte == 0)
+                .next()
+                .unwrap_or_default()
+                .to_path_lossy()
+                .into_owned()
+        };
+
+        if entry_type.is_gnu_longname() {
+            self.long_path = Some(name(data));
+        } else if entry_type.is_gnu_longlink() {
+            self.long_link = Some(name(data));
+        } else {
+            for extension in tar::PaxExtensions::new(data).flatten() {
+                match extension.key() {
+                    Ok("path") => self.long_path = Some(extension.value_bytes().to_path_lossy().into_owned()),
+                    Ok("linkpath") => self.long_link = Some(extension.value_bytes().to_path_lossy().into_owned()),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+impl<W: Write> Write for TarIndexer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let amount = self.inner.write(buf)?;
+        self.observe(&buf[..amount]);
+        Ok(amount)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tar_indexer_records_entries() {
+        let long_name = format!("{}/file.txt", "nested".repeat(30));
+
+        let mut builder = tar::Builder::new(TarIndexer::new(Vec::new()));
+        let mut header = tar::Header::new_gnu();
+        header.set_size(600);
+        header.set_mtime(1_700_000_000);
+        header.set_mode(0o644);
+        builder.append_data(&mut header, "small.txt", &[1_u8; 600][..]).unwrap();
+        builder.append_data(&mut header, &long_name, &[2_u8; 600][..]).unwrap();
+
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        builder.append_link(&mut header, "link", "small.txt").unwrap();
+
+        let indexer = builder.into_inner().unwrap();
+        let entries = indexer.entries.clone();
+        let archive = indexer.inner;
+
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].path, Path::new("small.txt"));
+        assert_eq!((entries[0].header_offset, entries[0].data_offset), (0, 512));
+        assert_eq!(entries[0].size, 600);
+        assert_eq!(entries[0].modified, Some(1_700_000_000));
+        assert_eq!(entries[0].mode, Some(0o644));
+
+        // The long name is written in an entry before the file
+        assert_eq!(entries[1].path, Path::new(&long_name));
+        assert_eq!(entries[1].header_offset, 512 + 1024);
+        assert_eq!(&archive[entries[1].data_offset as usize..][..600], &[2; 600]);
+
+        assert_eq!(
+            entries[2].entry_type,
+            TarIndexEntryType::Symlink {
+                target: PathBuf::from("small.txt")
+            }
+        );
+    }
+}
@@ -3,38 +3,50 @@
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 use std::{
+    collections::HashMap,
     env,
     io::{self, prelude::*},
     path::{Path, PathBuf},
 };
 
+use bstr::ByteSlice;
 use filetime_creation::{FileTime, set_file_mtime};
 use fs_err as fs;
 use is_executable::is_executable;
 use same_file::Handle;
 use time::OffsetDateTime;
-use zip::{self, DateTime, ZipArchive, read::ZipFile};
+use zip::{self, AesMode, DateTime, ZipArchive, read::ZipFile};
 
 use crate::{
     Result,
-    error::FinalError,
+    error::{Error, FinalError},
     info, info_accessible,
     list::{FileInArchive, ListFileType},
     utils::{
-        BytesFmt, FileType, FileVisibilityPolicy, PathFmt, canonicalize, cd_into_same_dir_as, create_symlink,
-        ensure_parent_dir_exists, get_invalid_utf8_paths, is_same_file_as_output, pretty_format_list_of_paths,
+        BytesFmt, EntryFilter, FileType, FileVisibilityPolicy, PathFmt, PathTransform, Reproducible, canonicalize,
+        cd_into_same_dir_as, create_symlink, ensure_parent_dir_exists, get_invalid_utf8_paths, is_same_file_as_output,
+        pretty_format_list_of_paths,
+        progress::{Progress, Side},
         read_file_type, strip_cur_dir,
     },
     warning,
 };
 
-/// Unpacks the archive given by `archive` into the folder given by `output_folder`.
+/// Unpacks the archive given by `archive` into the folder given by `output_folder`, skipping entries
+/// not selected by `entry_filter`, and changing their paths with `path_transform`.
 /// Assumes that output_folder is empty
-pub fn unpack_archive<R>(reader: R, output_folder: &Path, password: Option<&[u8]>) -> Result<u64>
+pub fn unpack_archive<R>(
+    reader: R,
+    output_folder: &Path,
+    password: Option<&[u8]>,
+    entry_filter: &EntryFilter,
+    path_transform: &PathTransform,
+) -> Result<u64>
 where
     R: Read + Seek,
 {
     let mut files_unpacked = 0;
+    let mut flattened_paths = HashMap::new();
     let mut archive = ZipArchive::new(reader)?;
 
     for idx in 0..archive.len() {
@@ -44,10 +56,24 @@ where
         };
         let file_path = match file.enclosed_name() {
             Some(path) => path.to_owned(),
-            None => continue,
+            None => {
+                warning!(
+                    "Skipping entry {} of zip archive, its path would be extracted outside of the output directory",
+                    PathFmt(Path::new(file.name())),
+                );
+                continue;
+            }
         };
 
-        let file_path = output_folder.join(file_path);
+        if !entry_filter.matches(&file_path) {
+            continue;
+        }
+
+        let Some(new_path) = path_transform.apply(&file_path, file.name().ends_with('/')) else {
+            continue;
+        };
+        path_transform.check_flatten_collision(&mut flattened_paths, &file_path, &new_path)?;
+        let file_path = output_folder.join(new_path);
 
         display_zip_comment_if_exists(&file);
 
@@ -103,63 +129,337 @@ where
     Ok(files_unpacked)
 }
 
+/// Write the contents of the regular files selected by `entry_filter` to `writer`, one after another.
+///
+/// Returns the number of files written.
+pub fn write_entries<R>(
+    reader: R,
+    mut writer: impl Write,
+    password: Option<&[u8]>,
+    entry_filter: &EntryFilter,
+) -> Result<u64>
+where
+    R: Read + Seek,
+{
+    let mut files_written = 0;
+    let mut archive = ZipArchive::new(reader)?;
+
+    for idx in 0..archive.len() {
+        let mut file = match password {
+            Some(password) => archive.by_index_decrypt(idx, password)?,
+            None => archive.by_index(idx)?,
+        };
+
+        let is_symlink = file.unix_mode().is_some_and(|mode| mode & 0o170000 == 0o120000);
+        if file.is_dir() || is_symlink || !entry_filter.matches(Path::new(file.name())) {
+            continue;
+        }
+
+        info!(
+            "writing ({}) {}",
+            BytesFmt(file.size()),
+            PathFmt(Path::new(file.name()))
+        );
+        io::copy(&mut file, &mut writer)?;
+        files_written += 1;
+    }
+
+    Ok(files_written)
+}
+
+/// Check that `password` decrypts the archive, failing with [`Error::InvalidPassword`] if it's wrong,
+/// or missing when the archive has encrypted entries.
+///
+/// Only the first encrypted entry is checked, the others are expected to use the same password.
+pub fn check_password<R>(reader: R, password: Option<&[u8]>) -> Result<()>
+where
+    R: Read + Seek,
+{
+    let mut archive = ZipArchive::new(reader)?;
+
+    let Some(idx) = (0..archive.len()).find(|&idx| archive.by_index_raw(idx).is_ok_and(|file| file.encrypted())) else {
+        return Ok(());
+    };
+
+    match password {
+        Some(password) => archive.by_index_decrypt(idx, password).map(drop).map_err(Into::into),
+        None => Err(Error::password_required()),
+    }
+}
+
 /// List contents of `archive`, returning a vector of archive entries
 pub fn list_archive<R>(
     mut archive: ZipArchive<R>,
     password: Option<&[u8]>,
-) -> impl Iterator<Item = Result<FileInArchive>>
+) -> impl Iterator<Item = Result<FileInArchive>> + use<R>
 where
     R: Read + Seek,
 {
     let password = password.map(|p| p.to_owned());
 
     (0..archive.len()).map(move |idx| {
-        let zip_result = match password.clone() {
-            Some(password) => archive.by_index_decrypt(idx, &password),
+        // Read the metadata without decrypting, so encrypted entries can be listed without the password
+        let mut file_in_archive = {
+            let file = archive.by_index_raw(idx)?;
+
+            FileInArchive {
+                path: file.enclosed_name().unwrap_or_else(|| file.mangled_name()).to_owned(),
+                file_type: if file.is_dir() {
+                    ListFileType::Directory
+                } else {
+                    ListFileType::File
+                },
+                size: Some(file.size()),
+                compressed_size: Some(file.compressed_size()),
+                modified: file
+                    .last_modified()
+                    .and_then(|datetime| OffsetDateTime::try_from(datetime).ok())
+                    .map(Into::into),
+                mode: file.unix_mode(),
+                encrypted: file.encrypted(),
+            }
+        };
+
+        let is_symlink = file_in_archive.mode.is_some_and(|mode| mode & 0o170000 == 0o120000);
+
+        if file_in_archive.file_type == ListFileType::File && is_symlink {
+            let zip_result = match password.clone() {
+                Some(password) => archive.by_index_decrypt(idx, &password),
+                None => archive.by_index(idx),
+            };
+
+            let mut target = Vec::new();
+            if zip_result?.read_to_end(&mut target).is_ok() {
+                file_in_archive.file_type = ListFileType::Symlink {
+                    target: PathBuf::from(String::from_utf8_lossy(&target).into_owned()),
+                };
+            }
+        }
+
+        Ok(file_in_archive)
+    })
+}
+
+/// Read every entry of `archive` to the end, verifying their CRC-32 checksums.
+///
+/// `on_entry` is called with the size of each entry read, or with why it couldn't be read.
+pub fn test_archive<R>(
+    mut archive: ZipArchive<R>,
+    password: Option<&[u8]>,
+    mut on_entry: impl FnMut(&Path, Result<u64>),
+) -> Result<()>
+where
+    R: Read + Seek,
+{
+    for idx in 0..archive.len() {
+        // Fallback for entries whose headers can't be read
+        let name = PathBuf::from(archive.name_for_index(idx).unwrap_or_default());
+
+        let zip_result = match password {
+            Some(password) => archive.by_index_decrypt(idx, password),
             None => archive.by_index(idx),
         };
 
         let mut file = match zip_result {
-            Ok(f) => f,
-            Err(e) => return Err(e.into()),
+            Ok(file) => file,
+            Err(err) => {
+                on_entry(&name, Err(err.into()));
+                continue;
+            }
         };
 
-        let path = file.enclosed_name().unwrap_or_else(|| file.mangled_name()).to_owned();
-
-        let file_type = if file.is_dir() {
-            ListFileType::Directory
-        } else if let Some(target) = file
-            .unix_mode()
-            .filter(|mode| mode & 0o170000 == 0o120000)
-            .and_then(|_| {
-                let mut s = Vec::new();
-                file.read_to_end(&mut s)
-                    .ok()
-                    .map(|_| PathBuf::from(String::from_utf8_lossy(&s).into_owned()))
-            })
-        {
-            ListFileType::Symlink { target }
-        } else {
-            ListFileType::File
-        };
+        let path = file.enclosed_name().unwrap_or_else(|| file.mangled_name());
+        on_entry(&path, io::copy(&mut file, &mut io::sink()).map_err(Into::into));
+    }
 
-        Ok(FileInArchive { path, file_type })
-    })
+    Ok(())
 }
 
-/// Compresses the archives given by `input_filenames` into the file given previously to `writer`.
+/// Build a zip archive of `input_filenames`, the contents of files are encrypted with AES-256 if
+/// `password` is set.
+///
+/// The input files are counted by `progress` as they're read.
 pub fn build_archive<W>(
     input_filenames: &[PathBuf],
     output_path: &Path,
     writer: W,
     file_visibility_policy: FileVisibilityPolicy,
     follow_symlinks: bool,
+    password: Option<&[u8]>,
+    path_transform: &PathTransform,
+    reproducible: Option<Reproducible>,
+    progress: &Progress,
 ) -> Result<W>
 where
     W: Write + Seek,
 {
     let mut writer = zip::ZipWriter::new(writer);
+    add_files(
+        &mut writer,
+        input_filenames,
+        output_path,
+        None,
+        &file_visibility_policy,
+        follow_symlinks,
+        password,
+        path_transform,
+        reproducible,
+        false,
+        Some(progress),
+    )?;
+    let bytes = writer.finish()?;
+    Ok(bytes)
+}
+
+/// Same as [`build_archive`], for writers that can't seek, like STDOUT or a compression encoder.
+///
+/// The sizes and checksums of the entries are written after their data (in data descriptors), so
+/// the archive doesn't have to be kept in memory until it's complete.
+///
+/// Entries can't be encrypted, the `zip` crate has to seek back to write the AES headers.
+pub fn build_archive_stream<W>(
+    input_filenames: &[PathBuf],
+    output_path: &Path,
+    writer: W,
+    file_visibility_policy: FileVisibilityPolicy,
+    follow_symlinks: bool,
+    path_transform: &PathTransform,
+    reproducible: Option<Reproducible>,
+    progress: &Progress,
+) -> Result<W>
+where
+    W: Write,
+{
+    let mut writer = zip::ZipWriter::new_stream(writer);
+    add_files(
+        &mut writer,
+        input_filenames,
+        output_path,
+        None,
+        &file_visibility_policy,
+        follow_symlinks,
+        None,
+        path_transform,
+        reproducible,
+        true,
+        Some(progress),
+    )?;
+    let stream = writer.finish()?;
+    Ok(stream.into_inner())
+}
+
+/// Add new entries to an existing zip archive, `archive` must be readable and writable.
+///
+/// The central directory is rewritten after the new entries, the existing entries are left untouched,
+/// so none of the new entries can have the same name as an existing one.
+pub fn append_to_archive<A>(
+    archive: A,
+    input_filenames: &[PathBuf],
+    output_path: &Path,
+    file_visibility_policy: &FileVisibilityPolicy,
+    follow_symlinks: bool,
+    password: Option<&[u8]>,
+) -> Result<A>
+where
+    A: Read + Write + Seek,
+{
+    let mut writer = zip::ZipWriter::new_append(archive)?;
+    add_files(
+        &mut writer,
+        input_filenames,
+        output_path,
+        None,
+        file_visibility_policy,
+        follow_symlinks,
+        password,
+        &PathTransform::default(),
+        None,
+        false,
+        None,
+    )?;
+    let bytes = writer.finish()?;
+    Ok(bytes)
+}
+
+/// Write a copy of `archive` to `writer` with the entries of `input_filenames` added, existing entries
+/// for which `is_replaced` returns true are left out.
+///
+/// Kept entries are copied without being decompressed, so encrypted entries don't need the password.
+/// `temp_path`, the temporary file `writer` writes to, is skipped if it's among the files.
+pub fn update_archive<R, W>(
+    mut archive: ZipArchive<R>,
+    writer: W,
+    is_replaced: impl Fn(&Path) -> bool,
+    input_filenames: &[PathBuf],
+    output_path: &Path,
+    temp_path: Option<&Path>,
+    file_visibility_policy: &FileVisibilityPolicy,
+    follow_symlinks: bool,
+    password: Option<&[u8]>,
+) -> Result<W>
+where
+    R: Read + Seek,
+    W: Write + Seek,
+{
+    let mut writer = zip::ZipWriter::new(writer);
+
+    for idx in 0..archive.len() {
+        let file = archive.by_index_raw(idx)?;
+        if is_replaced(Path::new(file.name().trim_end_matches('/'))) {
+            continue;
+        }
+        writer.raw_copy_file(file)?;
+    }
+
+    add_files(
+        &mut writer,
+        input_filenames,
+        output_path,
+        temp_path,
+        file_visibility_policy,
+        follow_symlinks,
+        password,
+        &PathTransform::default(),
+        None,
+        false,
+        None,
+    )?;
+    let bytes = writer.finish()?;
+    Ok(bytes)
+}
+
+/// Add `input_filenames` (and the contents of directories) as entries of the archive being built by `writer`.
+///
+/// Entry names are changed with `path_transform`, `is_stream` must be set when `writer` was created with
+/// [`zip::ZipWriter::new_stream`].
+///
+/// If `reproducible` is set, permissions are normalized and modification times clamped. The files read
+/// are counted by `progress`, if set.
+fn add_files<W>(
+    writer: &mut zip::ZipWriter<W>,
+    input_filenames: &[PathBuf],
+    output_path: &Path,
+    temp_path: Option<&Path>,
+    file_visibility_policy: &FileVisibilityPolicy,
+    follow_symlinks: bool,
+    password: Option<&[u8]>,
+    path_transform: &PathTransform,
+    reproducible: Option<Reproducible>,
+    is_stream: bool,
+    progress: Option<&Progress>,
+) -> Result<()>
+where
+    W: Write + Seek,
+{
     let output_handle = Handle::from_path(output_path);
+    let temp_handle = temp_path.and_then(|temp_path| Handle::from_path(temp_path).ok());
+    let password = password
+        .map(|password| {
+            password.to_str().map_err(|err| Error::InvalidPassword {
+                reason: err.to_string(),
+            })
+        })
+        .transpose()?;
 
     // always use ZIP64 to allow compression of files larger than 4GB
     // the format is widely supported and the 20B cost is negligible
@@ -200,6 +500,12 @@ where
                 continue;
             }
 
+            if let Some(handle) = temp_handle.as_ref()
+                && is_same_file_as_output(&path, handle)
+            {
+                continue;
+            }
+
             info!("Compressing {}", PathFmt(&path));
 
             let (metadata, file_type) = {
@@ -213,27 +519,59 @@ where
             #[cfg(unix)]
             let mode = metadata.permissions().mode();
 
-            let entry_name = path.to_str().ok_or_else(zip_non_utf8_error(&path))?;
+            let Some(entry_name) = path_transform.apply(&path, file_type.is_directory()) else {
+                continue;
+            };
+            let entry_name = entry_name.to_str().ok_or_else(zip_non_utf8_error(&entry_name))?;
             // ZIP format requires forward slashes as path separators, regardless of platform
             let entry_name = entry_name.replace(std::path::MAIN_SEPARATOR, "/");
 
+            // Without it, the entries of directories and symlinks get the current time
+            let default_options = match reproducible {
+                Some(reproducible) => default_options.last_modified_time(zip_date_time(reproducible.mtime(&metadata))),
+                None => default_options,
+            };
+
             match file_type {
                 FileType::Regular => {
-                    let options = if cfg!(not(unix)) && is_executable(&path) {
-                        default_executable_options
-                    } else {
-                        default_options
-                    };
-
                     let mut file = fs::File::open(&path)?;
 
-                    #[cfg(unix)]
-                    let options = options.unix_permissions(mode);
-                    // Updated last modified time
-                    let last_modified_time = options.last_modified_time(get_last_modified_time(&file));
+                    let options = if reproducible.is_some() {
+                        #[cfg(unix)]
+                        let executable = mode & 0o100 != 0;
+                        #[cfg(not(unix))]
+                        let executable = is_executable(&path);
+                        default_options.unix_permissions(Reproducible::mode(executable))
+                    } else {
+                        let options = if cfg!(not(unix)) && is_executable(&path) {
+                            default_executable_options
+                        } else {
+                            default_options
+                        };
+
+                        #[cfg(unix)]
+                        let options = options.unix_permissions(mode);
+                        // Updated last modified time
+                        options.last_modified_time(get_last_modified_time(&file))
+                    };
+                    let options = match password {
+                        Some(password) => options.with_aes_encryption(AesMode::Aes256, password),
+                        None => options,
+                    };
 
-                    writer.start_file(entry_name, last_modified_time)?;
-                    io::copy(&mut file, &mut writer)?;
+                    writer.start_file(entry_name, options)?;
+                    match progress {
+                        Some(progress) => io::copy(&mut progress.count_read(file, Side::Uncompressed), writer)?,
+                        None => io::copy(&mut file, writer)?,
+                    };
+                }
+                FileType::Directory if is_stream => {
+                    // `add_directory` marks the entry as followed by a data descriptor when streaming,
+                    // but never writes it, which `unzip` rejects, so write it as an empty file instead
+                    let options = default_options
+                        .compression_method(zip::CompressionMethod::Stored)
+                        .unix_permissions(0o755);
+                    writer.start_file(format!("{entry_name}/"), options)?;
                 }
                 FileType::Directory => {
                     writer.add_directory(entry_name, default_options)?;
@@ -247,7 +585,11 @@ where
                     // This approach writes the symlink target path as the content of the symlink entry.
                     // We detect symlinks during extraction by checking for the Unix symlink mode (0o120000) in the entry's permissions.
                     #[cfg(unix)]
-                    let symlink_options = default_options.unix_permissions(0o120000 | (mode & 0o777));
+                    let symlink_options = if reproducible.is_some() {
+                        default_options.unix_permissions(0o120777)
+                    } else {
+                        default_options.unix_permissions(0o120000 | (mode & 0o777))
+                    };
                     #[cfg(windows)]
                     let symlink_options = default_options.unix_permissions(0o120777);
 
@@ -259,8 +601,7 @@ where
         env::set_current_dir(previous_location)?;
     }
 
-    let bytes = writer.finish()?;
-    Ok(bytes)
+    Ok(())
 }
 
 fn display_zip_comment_if_exists<R: Read>(file: &ZipFile<'_, R>) {
@@ -288,6 +629,15 @@ fn get_last_modified_time(file: &fs::File) -> DateTime {
         .unwrap_or_default()
 }
 
+/// Convert seconds since the Unix epoch to a zip date, zip dates start in 1980.
+fn zip_date_time(timestamp: u64) -> DateTime {
+    i64::try_from(timestamp)
+        .ok()
+        .and_then(|timestamp| OffsetDateTime::from_unix_timestamp(timestamp).ok())
+        .and_then(|time| DateTime::try_from(time).ok())
+        .unwrap_or_default()
+}
+
 fn set_last_modified_time<R: Read>(zip_file: &ZipFile<'_, R>, path: &Path) -> Result<()> {
     // Extract modification time from zip file and convert to FileTime
     let file_time = zip_file
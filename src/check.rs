@@ -10,7 +10,8 @@ use std::{
 use crate::{
     QuestionAction, QuestionPolicy, Result,
     error::FinalError,
-    extension::{Extension, build_archive_file_suggestion},
+    extension::{CompressionFormat, Extension, build_archive_file_suggestion},
+    format_options::FormatOptions,
     info_accessible,
     utils::{
         NoQuotePathFmt, PathFmt, append_ascii_suffix_to_os_str, pretty_format_list_of_paths, try_infer_format,
@@ -132,6 +133,34 @@ pub fn check_for_non_archive_formats(files: &[PathBuf], formats: &[Vec<Extension
     Ok(())
 }
 
+/// In the context of extracting selected entries, this function checks if `ouch` was told to select
+/// entries of a compressed file that is not an archive
+pub fn check_for_non_archive_formats_when_selecting_entries(
+    files: &[PathBuf],
+    formats: &[Vec<Extension>],
+) -> Result<()> {
+    let not_archives: Vec<_> = files
+        .iter()
+        .zip(formats)
+        .filter(|(_, formats)| !formats.first().map(Extension::is_archive).unwrap_or(false))
+        .map(|(path, _)| path)
+        .collect();
+
+    if !not_archives.is_empty() {
+        let error = FinalError::with_title("Cannot select entries to extract")
+            .detail("Only archives have entries that can be selected with patterns")
+            .detail(format!(
+                "Files are not archives: {}",
+                pretty_format_list_of_paths(&not_archives)
+            ))
+            .hint("Patterns are arguments after the first one that aren't compressed files, or passed with --include/--exclude");
+
+        return Err(error.into());
+    }
+
+    Ok(())
+}
+
 /// Show error if archive format is not the first format in the chain.
 pub fn check_archive_formats_position(formats: &[Extension], output_path: &Path) -> Result<()> {
     if let Some(format) = formats.iter().skip(1).find(|format| format.is_archive()) {
@@ -154,6 +183,87 @@ pub fn check_archive_formats_position(formats: &[Extension], output_path: &Path)
     Ok(())
 }
 
+/// Show error if a password is given to a format that can't be encrypted, or if the 7z headers can't be
+/// encrypted.
+pub fn check_encryption_support(
+    formats: &[Extension],
+    output_path: &Path,
+    has_password: bool,
+    encrypt_headers: bool,
+) -> Result<()> {
+    let first_format = formats
+        .first()
+        .and_then(|extension| extension.compression_formats.first());
+    let error = FinalError::with_title(format!("Cannot compress to {}", PathFmt(output_path)));
+
+    if has_password && !matches!(first_format, Some(CompressionFormat::Zip | CompressionFormat::SevenZip)) {
+        let error = error
+            .detail("Only zip and 7z archives can be encrypted with a password")
+            .hint("Use a zip or 7z output, like 'archive.7z'");
+        return Err(error.into());
+    }
+
+    if encrypt_headers && !has_password {
+        let error = error
+            .detail("Encrypting the archive headers requires a password")
+            .hint("Pass one with --password");
+        return Err(error.into());
+    }
+
+    if encrypt_headers && first_format != Some(&CompressionFormat::SevenZip) {
+        let error = error
+            .detail("Only 7z archives can have their headers encrypted")
+            .hint("Zip entry names are always visible, use a 7z output to hide them");
+        return Err(error.into());
+    }
+
+    Ok(())
+}
+
+/// Warn about compression levels that can't be used as requested: levels out of the range of a format
+/// (which are clamped), levels of formats without levels, and levels of formats missing from the output.
+pub fn check_compression_levels(formats: &[CompressionFormat], level: Option<i16>, format_options: &FormatOptions) {
+    for (format, format_level) in &format_options.levels {
+        if !formats.contains(format) {
+            warning!(
+                "Ignoring the level {format_level} of '{}', the output isn't compressed with it",
+                format.as_str()
+            );
+        }
+    }
+
+    let mut checked_formats = Vec::new();
+    for &format in formats {
+        if checked_formats.contains(&format) {
+            continue;
+        }
+        checked_formats.push(format);
+
+        let Some(requested_level) = format_options.level(format, level) else {
+            continue;
+        };
+        match format.level_range() {
+            // `--slow` asks for the highest level of every format
+            Some(range) if requested_level == i16::MAX || range.contains(&requested_level) => {}
+            Some(range) => warning!(
+                "Level {requested_level} is out of the range of '{}' ({} to {}), using {} instead",
+                format.as_str(),
+                range.start(),
+                range.end(),
+                requested_level.clamp(*range.start(), *range.end()),
+            ),
+            // The level applied to all formats is only meant for the ones that support it
+            None if format_options.level(format, None).is_some() => {
+                warning!(
+                    "The format '{}' has no compression levels, ignoring the level {requested_level}",
+                    format.as_str()
+                );
+            }
+            None => {}
+        }
+    }
+}
+
 /// Check if all provided files have formats to decompress.
 pub fn check_missing_formats_when_decompressing(files: &[PathBuf], formats: &[Vec<Extension>]) -> Result<()> {
     let files_with_broken_extension: Vec<&PathBuf> = files
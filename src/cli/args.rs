@@ -42,9 +42,33 @@ pub struct CliArgs {
     #[arg(short, long, global = true)]
     pub format: Option<String>,
 
-    /// Decompress or list with password
-    #[arg(short, long = "password", aliases = ["pass", "pw"], global = true)]
-    pub password: Option<OsString>,
+    /// Password to read or create encrypted archives, asked for if empty, defaults to $OUCH_PASSWORD when
+    /// reading
+    #[arg(
+        short,
+        long = "password",
+        aliases = ["pass", "pw"],
+        num_args = 0..=1,
+        require_equals = true,
+        global = true
+    )]
+    pub password: Option<Option<OsString>>,
+
+    /// Read the password of archives to decrypt from the first line of a file
+    #[arg(long, value_name = "PATH", value_hint = ValueHint::FilePath, conflicts_with = "password", global = true)]
+    pub password_file: Option<PathBuf>,
+
+    /// Directory for temporary copies of zip, 7z and rar archives read from a stream (e.g. .zip.gz), and of archives rewritten by `add`
+    #[arg(long, value_name = "DIR", value_hint = ValueHint::DirPath, global = true)]
+    pub temp_dir: Option<PathBuf>,
+
+    /// MiB of those copies to keep in memory before writing to the temporary directory
+    #[arg(long, value_name = "MIB", default_value_t = 256, global = true)]
+    pub max_memory: u64,
+
+    /// Zstd dictionary to compress and decompress with, see the train-dict subcommand
+    #[arg(long, value_name = "PATH", value_hint = ValueHint::FilePath, global = true)]
+    pub zstd_dict: Option<PathBuf>,
 
     /// Concurrent working threads
     #[arg(short = 'c', long, global = true)]
@@ -62,16 +86,24 @@ pub enum Subcommand {
     #[command(visible_alias = "c")]
     Compress {
         /// Files to be compressed
-        #[arg(required = true, value_hint = ValueHint::FilePath)]
+        #[arg(required = true, conflicts_with = "files_from", value_hint = ValueHint::FilePath)]
         files: Vec<PathBuf>,
 
-        /// The resulting file. Its extensions can be used to specify the compression formats
+        /// The resulting file. Its extensions can be used to specify the compression formats.
+        /// Use "-" to write to stdout, with extensions like "-.tar.gz" or with --format
         #[arg(required = true, value_hint = ValueHint::FilePath)]
         output: PathBuf,
 
-        /// Compression level, applied to all formats
-        #[arg(short, long, group = "compression-level")]
-        level: Option<i16>,
+        /// Compression level, applied to all formats, or of one format like xz=9 or zstd=-5.
+        /// Can be repeated, and set in --format too, like tar.zst:19
+        #[arg(
+            short,
+            long,
+            value_name = "[FORMAT=]LEVEL",
+            allow_negative_numbers = true,
+            group = "compression-level"
+        )]
+        level: Vec<String>,
 
         /// Fastest compression level possible,
         /// conflicts with --level and --slow
@@ -83,14 +115,74 @@ pub enum Subcommand {
         #[arg(long, group = "compression-level")]
         slow: bool,
 
+        /// Format specific tunable, like zst.long=27, xz.dict=64MiB, br.window=24 or bz3.block=64MiB,
+        /// can be repeated
+        #[arg(long = "opt", value_name = "FORMAT.KEY=VALUE")]
+        options: Vec<String>,
+
         /// Read from target files instead of from symlinks (relevant for `tar` and `zip`)
         #[arg(long, short = 'S')]
         follow_symlinks: bool,
+
+        /// Also encrypt the entry names of 7z archives, so listing them requires the password
+        #[arg(long)]
+        encrypt_headers: bool,
+
+        /// Write an index of the entries of tar archives next to them, in OUTPUT.ouchidx,
+        /// so listing them doesn't need to decompress them
+        #[arg(long)]
+        index: bool,
+
+        /// Rename archive entries with a sed-like substitution, like 's|^build/|bin/|',
+        /// can be repeated (flags: g, i)
+        #[arg(long, value_name = "EXPR")]
+        transform: Vec<String>,
+
+        /// Put archive entries under the directory PREFIX, after --transform
+        #[arg(long, value_name = "PREFIX")]
+        prefix: Option<PathBuf>,
+
+        /// Only compress the files matching the glob pattern (and what's inside of matched directories),
+        /// patterns without a "/" match names at any depth
+        #[arg(long, value_name = "GLOB")]
+        include: Vec<String>,
+
+        /// Don't compress the files matching the glob pattern (and what's inside of matched directories),
+        /// patterns without a "/" match names at any depth
+        #[arg(long, value_name = "GLOB")]
+        exclude: Vec<String>,
+
+        /// Don't compress the directories and files of version control systems, like .git and .gitignore
+        #[arg(long)]
+        exclude_vcs: bool,
+
+        /// Don't compress directories tagged as caches with a CACHEDIR.TAG file
+        #[arg(long)]
+        exclude_caches: bool,
+
+        /// Compress the files listed in FILE instead, one per line, use "-" to read them from stdin
+        #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+        files_from: Option<PathBuf>,
+
+        /// Paths in --files-from are separated by NUL characters, like the output of `find -print0`
+        #[arg(long, requires = "files_from")]
+        null: bool,
+
+        /// Build archives that are the same byte for byte from the same files: sort the entries, leave
+        /// out their owners, normalize permissions and clamp modification times to --mtime
+        #[arg(long)]
+        reproducible: bool,
+
+        /// Latest modification time stored with --reproducible, in seconds since the Unix epoch,
+        /// defaults to $SOURCE_DATE_EPOCH
+        #[arg(long, value_name = "SECONDS", requires = "reproducible")]
+        mtime: Option<u64>,
     },
     /// Decompresses one or more files, optionally into another folder
     #[command(visible_alias = "d")]
     Decompress {
-        /// Files to be decompressed, or "-" for stdin
+        /// Files to be decompressed, or "-" for stdin.
+        /// Arguments after the first one that aren't compressed files are patterns of entries to extract, like --include
         #[arg(required = true, num_args = 1.., value_hint = ValueHint::FilePath)]
         files: Vec<PathBuf>,
 
@@ -101,6 +193,43 @@ pub enum Subcommand {
         /// Remove the source file after successful decompression
         #[arg(short = 'r', long)]
         remove: bool,
+
+        /// Write the decompressed data to stdout instead of files,
+        /// the selected entries of archives are written one after another
+        #[arg(short = 'O', long, conflicts_with_all = ["output_dir", "remove"])]
+        stdout: bool,
+
+        /// Only extract archive entries matching the glob pattern (and what's inside of matched directories)
+        #[arg(long, value_name = "GLOB")]
+        include: Vec<String>,
+
+        /// Don't extract archive entries matching the glob pattern (and what's inside of matched directories)
+        #[arg(long, value_name = "GLOB")]
+        exclude: Vec<String>,
+
+        /// Unpack archives straight into the current directory, instead of into a directory named
+        /// after the archive (unless it has a single top-level entry). Implied by --dir
+        #[arg(long)]
+        no_smart_unpack: bool,
+
+        /// Remove the first N components from the paths of archive entries, entries with fewer
+        /// components are skipped
+        #[arg(long, value_name = "N", default_value_t = 0)]
+        strip_components: usize,
+
+        /// Extract every file of archives directly into the output directory, without their
+        /// directories, fails if two files have the same name
+        #[arg(long, conflicts_with = "strip_components")]
+        flatten: bool,
+
+        /// Rename archive entries with a sed-like substitution, like 's|^myapp-1.0/||',
+        /// after --strip-components and --flatten, can be repeated (flags: g, i)
+        #[arg(long, value_name = "EXPR")]
+        transform: Vec<String>,
+
+        /// Extract archive entries under the directory PREFIX, after --transform
+        #[arg(long, value_name = "PREFIX")]
+        prefix: Option<PathBuf>,
     },
     /// List contents of an archive
     #[command(visible_aliases = ["l", "ls"])]
@@ -112,9 +241,93 @@ pub enum Subcommand {
         /// Show archive contents as a tree
         #[arg(short, long)]
         tree: bool,
+
+        /// Show permissions, sizes, compression ratios and modification times, with a totals footer.
+        /// Encrypted entries are marked with a *
+        #[arg(short, long, conflicts_with = "tree")]
+        long: bool,
+
+        /// Print the listing as text, as a JSON array with a document per archive, or as newline-delimited
+        /// JSON with an archive header line followed by a line per entry
+        #[arg(long, value_enum, default_value_t, conflicts_with = "long")]
+        output_format: ListOutputFormat,
+    },
+    /// Test the integrity of archives and compressed files without extracting them
+    #[command(visible_aliases = ["t", "verify"])]
+    Test {
+        /// Files to be tested, or "-" for stdin
+        #[arg(required = true, num_args = 1.., value_hint = ValueHint::FilePath)]
+        files: Vec<PathBuf>,
+    },
+    /// Add files to an existing tar, zip or 7z archive
+    ///
+    /// Zip and 7z entries with the same name as an added file are replaced, tar entries are appended
+    /// after the old ones, the last one wins when extracting
+    #[command(visible_alias = "a")]
+    Add {
+        /// The archive to add files to. Its extensions are used to detect the formats
+        #[arg(required = true, value_hint = ValueHint::FilePath)]
+        archive: PathBuf,
+
+        /// Files to be added
+        #[arg(required = true, num_args = 1.., value_hint = ValueHint::FilePath)]
+        files: Vec<PathBuf>,
+
+        /// Read from target files instead of from symlinks (relevant for `tar` and `zip`)
+        #[arg(long, short = 'S')]
+        follow_symlinks: bool,
+
+        /// Compression level of archives that are compressed again, applied to all formats, or of one
+        /// format like xz=9 or zstd=-5. Can be repeated
+        #[arg(
+            short,
+            long,
+            value_name = "[FORMAT=]LEVEL",
+            allow_negative_numbers = true,
+            group = "compression-level"
+        )]
+        level: Vec<String>,
+
+        /// Fastest compression level possible,
+        /// conflicts with --level and --slow
+        #[arg(long, group = "compression-level")]
+        fast: bool,
+
+        /// Slowest (and best) compression level possible,
+        /// conflicts with --level and --fast
+        #[arg(long, group = "compression-level")]
+        slow: bool,
+
+        /// Format specific tunable of archives that are compressed again, like zst.long=27 or
+        /// xz.dict=64MiB, can be repeated
+        #[arg(long = "opt", value_name = "FORMAT.KEY=VALUE")]
+        options: Vec<String>,
+    },
+    /// Train a zstd dictionary on sample files, to compress many small and similar files with --zstd-dict
+    TrainDict {
+        /// Sample files, or directories of sample files
+        #[arg(required = true, num_args = 1.., value_hint = ValueHint::AnyPath)]
+        samples: Vec<PathBuf>,
+
+        /// The dictionary file to write
+        #[arg(short, long, required = true, value_hint = ValueHint::FilePath)]
+        output: PathBuf,
+
+        /// Maximum size of the dictionary in bytes
+        #[arg(long, value_name = "BYTES", default_value_t = 112_640)]
+        max_size: usize,
     },
 }
 
+/// How `ouch list` prints the archive contents
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ListOutputFormat {
+    #[default]
+    Text,
+    Json,
+    Ndjson,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -151,12 +364,24 @@ mod tests {
             format: None,
             // This is usually replaced in assertion tests
             password: None,
+            password_file: None,
+            temp_dir: None,
+            max_memory: 256,
+            zstd_dict: None,
             threads: None,
             cmd: Subcommand::Decompress {
                 // Put a crazy value here so no test can assert it unintentionally
                 files: vec!["\x00\x11\x22".into()],
                 output_dir: None,
                 remove: false,
+                stdout: false,
+                include: vec![],
+                exclude: vec![],
+                no_smart_unpack: false,
+                strip_components: 0,
+                flatten: false,
+                transform: vec![],
+                prefix: None,
             },
         }
     }
@@ -170,6 +395,14 @@ mod tests {
                     files: to_paths(["file.tar.gz"]),
                     output_dir: None,
                     remove: false,
+                    stdout: false,
+                    include: vec![],
+                    exclude: vec![],
+                    no_smart_unpack: false,
+                    strip_components: 0,
+                    flatten: false,
+                    transform: vec![],
+                    prefix: None,
                 },
                 ..mock_cli_args()
             }
@@ -181,6 +414,14 @@ mod tests {
                     files: to_paths(["file.tar.gz"]),
                     output_dir: None,
                     remove: false,
+                    stdout: false,
+                    include: vec![],
+                    exclude: vec![],
+                    no_smart_unpack: false,
+                    strip_components: 0,
+                    flatten: false,
+                    transform: vec![],
+                    prefix: None,
                 },
                 ..mock_cli_args()
             }
@@ -192,6 +433,33 @@ mod tests {
                     files: to_paths(["a", "b", "c"]),
                     output_dir: None,
                     remove: false,
+                    stdout: false,
+                    include: vec![],
+                    exclude: vec![],
+                    no_smart_unpack: false,
+                    strip_components: 0,
+                    flatten: false,
+                    transform: vec![],
+                    prefix: None,
+                },
+                ..mock_cli_args()
+            }
+        );
+        test!(
+            "ouch d log.gz -O",
+            CliArgs {
+                cmd: Subcommand::Decompress {
+                    files: to_paths(["log.gz"]),
+                    output_dir: None,
+                    remove: false,
+                    stdout: true,
+                    include: vec![],
+                    exclude: vec![],
+                    no_smart_unpack: false,
+                    strip_components: 0,
+                    flatten: false,
+                    transform: vec![],
+                    prefix: None,
                 },
                 ..mock_cli_args()
             }
@@ -203,10 +471,23 @@ mod tests {
                 cmd: Subcommand::Compress {
                     files: to_paths(["file"]),
                     output: PathBuf::from("file.tar.gz"),
-                    level: None,
+                    level: vec![],
                     fast: false,
                     slow: false,
+                    options: vec![],
                     follow_symlinks: false,
+                    encrypt_headers: false,
+                    index: false,
+                    transform: vec![],
+                    prefix: None,
+                    include: vec![],
+                    exclude: vec![],
+                    exclude_vcs: false,
+                    exclude_caches: false,
+                    files_from: None,
+                    null: false,
+                    reproducible: false,
+                    mtime: None,
                 },
                 ..mock_cli_args()
             }
@@ -217,10 +498,23 @@ mod tests {
                 cmd: Subcommand::Compress {
                     files: to_paths(["a", "b", "c"]),
                     output: PathBuf::from("archive.tar.gz"),
-                    level: None,
+                    level: vec![],
                     fast: false,
                     slow: false,
+                    options: vec![],
                     follow_symlinks: false,
+                    encrypt_headers: false,
+                    index: false,
+                    transform: vec![],
+                    prefix: None,
+                    include: vec![],
+                    exclude: vec![],
+                    exclude_vcs: false,
+                    exclude_caches: false,
+                    files_from: None,
+                    null: false,
+                    reproducible: false,
+                    mtime: None,
                 },
                 ..mock_cli_args()
             }
@@ -231,10 +525,51 @@ mod tests {
                 cmd: Subcommand::Compress {
                     files: to_paths(["a", "b", "c"]),
                     output: PathBuf::from("archive.tar.gz"),
-                    level: None,
+                    level: vec![],
+                    fast: false,
+                    slow: false,
+                    options: vec![],
+                    follow_symlinks: false,
+                    encrypt_headers: false,
+                    index: false,
+                    transform: vec![],
+                    prefix: None,
+                    include: vec![],
+                    exclude: vec![],
+                    exclude_vcs: false,
+                    exclude_caches: false,
+                    files_from: None,
+                    null: false,
+                    reproducible: false,
+                    mtime: None,
+                },
+                ..mock_cli_args()
+            }
+        );
+
+        test!(
+            "ouch compress a archive.tar.zst -l -5 --level xz=9",
+            CliArgs {
+                cmd: Subcommand::Compress {
+                    files: to_paths(["a"]),
+                    output: PathBuf::from("archive.tar.zst"),
+                    level: vec!["-5".into(), "xz=9".into()],
                     fast: false,
                     slow: false,
+                    options: vec![],
                     follow_symlinks: false,
+                    encrypt_headers: false,
+                    index: false,
+                    transform: vec![],
+                    prefix: None,
+                    include: vec![],
+                    exclude: vec![],
+                    exclude_vcs: false,
+                    exclude_caches: false,
+                    files_from: None,
+                    null: false,
+                    reproducible: false,
+                    mtime: None,
                 },
                 ..mock_cli_args()
             }
@@ -256,10 +591,23 @@ mod tests {
                     cmd: Subcommand::Compress {
                         files: to_paths(["a", "b", "c"]),
                         output: PathBuf::from("output"),
-                        level: None,
+                        level: vec![],
                         fast: false,
                         slow: false,
+                        options: vec![],
                         follow_symlinks: false,
+                        encrypt_headers: false,
+                        index: false,
+                        transform: vec![],
+                        prefix: None,
+                        include: vec![],
+                        exclude: vec![],
+                        exclude_vcs: false,
+                        exclude_caches: false,
+                        files_from: None,
+                        null: false,
+                        reproducible: false,
+                        mtime: None,
                     },
                     format: Some("tar.gz".into()),
                     ..mock_cli_args()
@@ -274,5 +622,7 @@ mod tests {
         assert!(CliArgs::try_parse_from(args_splitter("ouch c input")).is_err());
         assert!(CliArgs::try_parse_from(args_splitter("ouch d")).is_err());
         assert!(CliArgs::try_parse_from(args_splitter("ouch l")).is_err());
+        assert!(CliArgs::try_parse_from(args_splitter("ouch t")).is_err());
+        assert!(CliArgs::try_parse_from(args_splitter("ouch a archive.zip")).is_err());
     }
 }
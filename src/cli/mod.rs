@@ -2,16 +2,26 @@
 
 mod args;
 
-use std::path::{Path, PathBuf, absolute};
+use std::{
+    env,
+    ffi::OsString,
+    io::{self, Read},
+    mem,
+    path::{Path, PathBuf},
+};
 
+use bstr::ByteSlice;
 use clap::Parser;
+use fs_err as fs;
 
-pub use self::args::{CliArgs, Subcommand};
+pub use self::args::{CliArgs, ListOutputFormat, Subcommand};
 use crate::{
     QuestionPolicy, Result,
     accessible::set_accessible,
+    extension, list,
     utils::{
-        FileVisibilityPolicy, canonicalize, is_path_stdin, logger::set_log_display_level, threads::set_thread_count,
+        EntryFilter, FileVisibilityPolicy, VCS_NAMES, absolutize_paths, canonicalize, is_path_stdin, is_path_stdout,
+        logger::set_log_display_level, threads::set_thread_count, try_infer_format,
     },
 };
 
@@ -19,10 +29,14 @@ impl CliArgs {
     /// A helper method that calls `clap::Parser::parse`.
     ///
     /// And:
-    ///   1. Make paths absolute.
-    ///   2. Checks the QuestionPolicy.
+    ///   1. Separate entry patterns from the files to decompress.
+    ///   2. Make paths absolute.
+    ///   3. Checks the QuestionPolicy.
     pub fn parse_and_validate_args() -> Result<(Self, QuestionPolicy, FileVisibilityPolicy)> {
-        let mut args = Self::parse();
+        let raw_args: Vec<OsString> = env::args_os().collect();
+        let mut args = Self::try_parse_from(&raw_args)
+            .or_else(|err| parse_with_stdout_output(&raw_args).ok_or(err))
+            .unwrap_or_else(|err| err.exit());
 
         set_accessible(args.accessible);
         set_log_display_level(args.quiet);
@@ -32,11 +46,48 @@ impl CliArgs {
             Some(threads) => set_thread_count(threads),
         }
 
+        // Arguments after the first one that aren't compressed files are patterns of entries to extract,
+        // e.g. `ouch decompress archive.tar.gz 'src/**/*.rs'`
+        //
+        // If the first one isn't a compressed file either, keep them all so they're reported together
+        if let Subcommand::Decompress { files, include, .. } = &mut args.cmd
+            && is_decompression_input(&files[0])
+        {
+            let (inputs, patterns): (Vec<_>, Vec<_>) = mem::take(files)
+                .into_iter()
+                .enumerate()
+                .partition(|(i, path)| *i == 0 || is_decompression_input(path));
+
+            *files = inputs.into_iter().map(|(_, path)| path).collect();
+            include.extend(
+                patterns
+                    .into_iter()
+                    .map(|(_, path)| path.to_string_lossy().into_owned()),
+            );
+        }
+
+        if let Subcommand::Compress {
+            files,
+            files_from: Some(files_from),
+            null,
+            ..
+        } = &mut args.cmd
+        {
+            *files = read_files_from(files_from, *null)?;
+        }
+
         let (Subcommand::Compress { files, .. }
         | Subcommand::Decompress { files, .. }
-        | Subcommand::List { archives: files, .. }) = &mut args.cmd;
+        | Subcommand::List { archives: files, .. }
+        | Subcommand::Test { files }
+        | Subcommand::Add { files, .. }
+        | Subcommand::TrainDict { samples: files, .. }) = &mut args.cmd;
         *files = absolutize_paths(files)?;
 
+        if let Some(temp_dir) = &mut args.temp_dir {
+            *temp_dir = canonicalize(&*temp_dir)?;
+        }
+
         let skip_questions_positively = match (args.yes, args.no) {
             (false, false) => QuestionPolicy::Ask,
             (true, false) => QuestionPolicy::AlwaysYes,
@@ -49,32 +100,119 @@ impl CliArgs {
             Subcommand::Compress {
                 follow_symlinks: true,
                 ..
+            } | Subcommand::Add {
+                follow_symlinks: true,
+                ..
             }
         );
 
+        let (entry_filter, exclude_caches) = match &args.cmd {
+            Subcommand::Compress {
+                include,
+                exclude,
+                exclude_vcs,
+                exclude_caches,
+                ..
+            } => {
+                let mut exclude = exclude.clone();
+                if *exclude_vcs {
+                    exclude.extend(VCS_NAMES.iter().map(|name| name.to_string()));
+                }
+                (EntryFilter::new_unanchored(include, &exclude)?, *exclude_caches)
+            }
+            _ => (EntryFilter::default(), false),
+        };
+
         let file_visibility_policy = FileVisibilityPolicy::new()
             .read_git_exclude(args.gitignore)
             .read_ignore(args.gitignore)
             .read_git_ignore(args.gitignore)
             .read_hidden(args.hidden)
-            .follow_symlinks(follow_symlinks);
+            .follow_symlinks(follow_symlinks)
+            .entry_filter(entry_filter)
+            .exclude_caches(exclude_caches);
 
         Ok((args, skip_questions_positively, file_visibility_policy))
     }
 }
 
-fn absolutize_paths(paths: &[impl AsRef<Path>]) -> Result<Vec<PathBuf>> {
-    paths
+impl From<ListOutputFormat> for list::OutputFormat {
+    fn from(format: ListOutputFormat) -> Self {
+        match format {
+            ListOutputFormat::Text => Self::Text,
+            ListOutputFormat::Json => Self::Json,
+            ListOutputFormat::Ndjson => Self::Ndjson,
+        }
+    }
+}
+
+/// Clap parses outputs like "-.tar.gz" as short flags, so when parsing fails, try again with one of them
+/// escaped as "./-.tar.gz" (no flag starts with "-."), keeping it only if it's the output of `compress`.
+fn parse_with_stdout_output(raw_args: &[OsString]) -> Option<CliArgs> {
+    raw_args
         .iter()
+        .enumerate()
+        .skip(1)
+        .filter(|(_, arg)| *arg != "-" && is_path_stdout(Path::new(arg)))
+        .find_map(|(i, arg)| {
+            let escaped = Path::new(".").join(arg);
+            let mut escaped_args = raw_args.to_vec();
+            escaped_args[i] = escaped.clone().into_os_string();
+
+            let mut args = CliArgs::try_parse_from(escaped_args).ok()?;
+            let Subcommand::Compress { output, .. } = &mut args.cmd else {
+                return None;
+            };
+            if *output != escaped {
+                return None;
+            }
+            *output = PathBuf::from(arg);
+            Some(args)
+        })
+}
+
+/// Read the paths listed in `files_from` (or STDIN if it's "-"), one per line, or separated by NUL
+/// characters if `null` is set.
+fn read_files_from(files_from: &Path, null: bool) -> Result<Vec<PathBuf>> {
+    let contents = if is_path_stdin(files_from) {
+        let mut contents = Vec::new();
+        io::stdin().lock().read_to_end(&mut contents)?;
+        contents
+    } else {
+        fs::read(files_from)?
+    };
+
+    let separator = if null { b'\0' } else { b'\n' };
+    let paths = contents
+        .split(|&byte| byte == separator)
         .map(|path| {
-            let path = path.as_ref();
-            if is_path_stdin(path) {
-                Ok(path.into())
-            } else if path.is_symlink() {
-                Ok(absolute(path)?)
+            if null {
+                path
             } else {
-                canonicalize(path)
+                path.trim_end_with(|char| char == '\r')
             }
         })
-        .collect()
+        .filter(|path| !path.is_empty())
+        .map(|path| path.to_path_lossy().into_owned())
+        .collect();
+
+    Ok(paths)
+}
+
+/// Check if `path` is a file to decompress instead of a pattern of archive entries to extract.
+///
+/// Patterns can end with an extension too, like `*.tar.gz`, so paths with an extension are only inputs
+/// if they exist or aren't globs. A missing `b.tar.gz` is then reported as not found, instead of
+/// being used as a pattern.
+fn is_decompression_input(path: &Path) -> bool {
+    let has_extensions = || extension::extensions_from_path(path).is_ok_and(|extensions| !extensions.is_empty());
+    let is_glob = || path.to_string_lossy().contains(['*', '?', '[', '{']);
+
+    if is_path_stdin(path) {
+        true
+    } else if path.is_file() {
+        has_extensions() || try_infer_format(path).is_some()
+    } else {
+        !path.exists() && has_extensions() && !is_glob()
+    }
 }
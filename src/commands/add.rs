@@ -0,0 +1,337 @@
+use std::{
+    collections::HashSet,
+    env,
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+
+use crate::{
+    BUFFER_CAPACITY, QuestionPolicy, Result, archive, check,
+    commands::{chain_reader_decoder, chain_writer_encoder, with_archive_password},
+    error::FinalError,
+    extension::{self, CompressionFormat::*, parse_format_flag, split_first_compression_format},
+    format_options::FormatOptions,
+    utils::{FileVisibilityPolicy, PathFmt, PathTransform, absolutize_paths, canonicalize, cd_into_same_dir_as},
+};
+
+/// Options for [`add`], the defaults match running `ouch add` without flags.
+#[non_exhaustive]
+pub struct AddOptions {
+    /// Formats to use instead of the archive path extensions, same syntax as `--format`, e.g. "tar.gz"
+    pub format: Option<String>,
+    /// Compression level of archives that are compressed again, like `.tar.gz`, applied to all formats
+    /// without a level in `format_options.levels`
+    pub level: Option<i16>,
+    /// Format specific tunables of archives that are compressed again, its zstd dictionary is also used
+    /// to read them
+    pub format_options: FormatOptions,
+    /// Read from target files instead of from symlinks (relevant for `tar` and `zip`)
+    pub follow_symlinks: bool,
+    pub question_policy: QuestionPolicy,
+    /// Encrypts the added zip entries, and the whole archive for 7z (needed to read encrypted 7z archives)
+    pub password: Option<Vec<u8>>,
+    pub file_visibility_policy: FileVisibilityPolicy,
+    /// Directory of the temporary file that rewritten archives are written to, the archive's directory
+    /// by default
+    pub temp_dir: Option<PathBuf>,
+}
+
+impl Default for AddOptions {
+    fn default() -> Self {
+        Self {
+            format: None,
+            level: None,
+            format_options: FormatOptions::default(),
+            follow_symlinks: false,
+            question_policy: QuestionPolicy::Ask,
+            password: None,
+            file_visibility_policy: FileVisibilityPolicy::default(),
+            temp_dir: None,
+        }
+    }
+}
+
+/// Add `files` to the existing archive at `archive_path`, detecting its formats from its extensions
+/// (or `options.format`).
+///
+/// Uncompressed tar and zip archives are appended to in place. Compressed tar archives are
+/// decompressed and compressed again with the new entries, 7z archives are always rewritten.
+///
+/// Existing zip and 7z entries with the same name as an added file are replaced. Tar entries are
+/// appended after the old ones instead, like `tar --append`, and the last one wins when extracting.
+///
+/// Not thread-safe: the current directory of the process is changed while walking `files`, nothing
+/// else relying on it (like the other functions of this crate) should run at the same time.
+pub fn add(files: Vec<PathBuf>, archive_path: &Path, options: AddOptions) -> Result<()> {
+    crate::force_lazy_locks_to_load();
+
+    let files = absolutize_paths(&files)?;
+
+    if files.is_empty() {
+        return Err(FinalError::with_title("No files to add").into());
+    }
+
+    if !archive_path.exists() {
+        let error = FinalError::with_title(format!("Cannot add files to {}", PathFmt(archive_path)))
+            .detail("The archive does not exist")
+            .hint("Use `ouch compress` to create a new archive");
+        return Err(error.into());
+    }
+
+    let archive_path = canonicalize(archive_path)?;
+
+    let formats = match &options.format {
+        Some(formats) => parse_format_flag(formats)?,
+        None => extension::extensions_from_path(&archive_path)?,
+    };
+
+    if formats.is_empty() {
+        let error = FinalError::with_title(format!("Cannot add files to {}", PathFmt(&archive_path)))
+            .detail("The archive format could not be detected from its extensions")
+            .hint("Use --format to specify it, e.g. `--format tar.gz`");
+        return Err(error.into());
+    }
+
+    let (first_format, compression_formats) = split_first_compression_format(&formats);
+
+    match first_format {
+        Tar if compression_formats.is_empty() => {
+            let mut file = fs::OpenOptions::new().read(true).write(true).open(&archive_path)?;
+            let end = archive::tar::find_end_of_archive(BufReader::with_capacity(BUFFER_CAPACITY, &mut file))?;
+
+            // Overwrite the end-of-archive marker, a new one is written after the new entries
+            file.seek(SeekFrom::Start(end))?;
+
+            let result = (|| -> Result<u64> {
+                let mut builder = tar::Builder::new(BufWriter::with_capacity(BUFFER_CAPACITY, &mut file));
+                archive::tar::append_files(
+                    &mut builder,
+                    &files,
+                    &archive_path,
+                    None,
+                    &options.file_visibility_policy,
+                    options.follow_symlinks,
+                    &PathTransform::default(),
+                    None,
+                )?;
+                let mut writer = builder.into_inner()?;
+                writer.flush()?;
+                Ok(writer.stream_position()?)
+            })();
+
+            match result {
+                // Drop the padding left after the old end-of-archive marker, if any
+                Ok(new_end) => file.set_len(new_end)?,
+                Err(err) => {
+                    // Put the end-of-archive marker back, so the archive keeps its old entries
+                    file.set_len(end)?;
+                    file.seek(SeekFrom::Start(end))?;
+                    file.write_all(&[0; 1024])?;
+                    return Err(err);
+                }
+            }
+        }
+        Tar => {
+            let create_decoder = || -> Result<Box<dyn Read + Send>> {
+                let file = fs::File::open(&archive_path)?;
+                let mut reader: Box<dyn Read + Send> = Box::new(BufReader::with_capacity(BUFFER_CAPACITY, file));
+                for format in compression_formats.iter().rev() {
+                    reader = chain_reader_decoder(*format, reader, options.format_options.zstd_dict.as_deref())?;
+                }
+                Ok(reader)
+            };
+
+            let end = archive::tar::find_end_of_archive(create_decoder()?)?;
+            let format_options = &options.format_options;
+            check::check_compression_levels(&compression_formats, options.level, format_options);
+
+            replace_archive(&archive_path, options.temp_dir.as_deref(), |temp_file, temp_path| {
+                let mut writer: Box<dyn Write + Send> =
+                    Box::new(BufWriter::with_capacity(BUFFER_CAPACITY, temp_file.try_clone()?));
+                for format in compression_formats.iter().rev() {
+                    writer = chain_writer_encoder(
+                        *format,
+                        writer,
+                        format_options.level(*format, options.level),
+                        format_options,
+                    )?;
+                }
+
+                // Copy the existing entries without the end-of-archive marker
+                io::copy(&mut create_decoder()?.take(end), &mut writer)?;
+
+                let mut builder = tar::Builder::new(writer);
+                archive::tar::append_files(
+                    &mut builder,
+                    &files,
+                    &archive_path,
+                    Some(temp_path),
+                    &options.file_visibility_policy,
+                    options.follow_symlinks,
+                    &PathTransform::default(),
+                    None,
+                )?;
+                builder.into_inner()?.flush()?;
+                Ok(())
+            })?;
+        }
+        Zip if compression_formats.is_empty() => {
+            let new_entries = new_entry_names(&files, &options.file_visibility_policy)?;
+            let is_replaced = |name: &Path| new_entries.contains(name);
+
+            let archive = zip::ZipArchive::new(BufReader::with_capacity(
+                BUFFER_CAPACITY,
+                fs::File::open(&archive_path)?,
+            ))?;
+            let has_replaced_entries = archive
+                .file_names()
+                .any(|name| is_replaced(Path::new(name.trim_end_matches('/'))));
+
+            if has_replaced_entries {
+                replace_archive(&archive_path, options.temp_dir.as_deref(), |temp_file, temp_path| {
+                    archive::zip::update_archive(
+                        archive,
+                        BufWriter::with_capacity(BUFFER_CAPACITY, temp_file),
+                        is_replaced,
+                        &files,
+                        &archive_path,
+                        Some(temp_path),
+                        &options.file_visibility_policy,
+                        options.follow_symlinks,
+                        options.password.as_deref(),
+                    )?
+                    .flush()?;
+                    Ok(())
+                })?;
+            } else {
+                let central_directory_start = archive.central_directory_start();
+                drop(archive);
+
+                // The new entries overwrite the central directory, keep it to put it back on errors
+                let mut file = fs::OpenOptions::new().read(true).write(true).open(&archive_path)?;
+                let end = file.seek(SeekFrom::End(0))?;
+                let mut central_directory = Vec::new();
+                file.seek(SeekFrom::Start(central_directory_start))?;
+                file.read_to_end(&mut central_directory)?;
+
+                let result = archive::zip::append_to_archive(
+                    &mut file,
+                    &files,
+                    &archive_path,
+                    &options.file_visibility_policy,
+                    options.follow_symlinks,
+                    options.password.as_deref(),
+                );
+
+                if let Err(err) = result {
+                    file.set_len(end)?;
+                    file.seek(SeekFrom::Start(central_directory_start))?;
+                    file.write_all(&central_directory)?;
+                    return Err(err);
+                }
+            }
+        }
+        SevenZip if compression_formats.is_empty() => {
+            let new_entries = new_entry_names(&files, &options.file_visibility_policy)?;
+
+            // The existing entries are decompressed, so the password must be right
+            let password = with_archive_password(
+                &archive_path,
+                options.password.as_deref(),
+                options.question_policy,
+                |password| {
+                    archive::sevenz::check_password(fs::File::open(&archive_path)?, password)
+                        .map(|()| password.map(ToOwned::to_owned))
+                },
+            )?;
+
+            replace_archive(&archive_path, options.temp_dir.as_deref(), |temp_file, temp_path| {
+                archive::sevenz::update_archive(
+                    BufReader::with_capacity(BUFFER_CAPACITY, fs::File::open(&archive_path)?),
+                    BufWriter::with_capacity(BUFFER_CAPACITY, temp_file),
+                    password.as_deref(),
+                    |name| new_entries.contains(name),
+                    &files,
+                    &archive_path,
+                    Some(temp_path),
+                    &options.file_visibility_policy,
+                )?
+                .flush()?;
+                Ok(())
+            })?;
+        }
+        Zip | SevenZip => {
+            let error = FinalError::with_title(format!("Cannot add files to {}", PathFmt(&archive_path)))
+                .detail("Adding files to zip and 7z archives is only supported when they aren't compressed again")
+                .hint("Decompress the archive, then compress its files together with the new ones");
+            return Err(error.into());
+        }
+        Rar => {
+            let error = FinalError::with_title(format!("Cannot add files to {}", PathFmt(&archive_path)))
+                .detail("Ouch can't write RAR archives, as the format is proprietary");
+            return Err(error.into());
+        }
+        Gzip | Bzip | Bzip3 | Lz4 | Lzma | Xz | Lzip | Snappy | Zstd | Brotli => {
+            let error = FinalError::with_title(format!("Cannot add files to {}", PathFmt(&archive_path)))
+                .detail("Its format compresses a single file, it's not an archive")
+                .hint("Files can only be added to tar, zip and 7z archives");
+            return Err(error.into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Write the new version of the archive to a temporary file with `write_fn`, replacing the archive at
+/// `archive_path` once complete and keeping its permissions.
+///
+/// The temporary file is created in `temp_dir`, or next to the archive, its path is given to `write_fn`
+/// to skip it when walking the files to add.
+fn replace_archive(
+    archive_path: &Path,
+    temp_dir: Option<&Path>,
+    write_fn: impl FnOnce(&mut std::fs::File, &Path) -> Result<()>,
+) -> Result<()> {
+    let permissions = fs::metadata(archive_path)?.permissions();
+    // Unwrap safety:
+    //   the archive path is canonicalized, so it has a parent directory.
+    let archive_dir = archive_path.parent().unwrap();
+
+    let mut temp_file = tempfile::NamedTempFile::new_in(temp_dir.unwrap_or(archive_dir))?;
+    let temp_path = temp_file.path().to_path_buf();
+    write_fn(temp_file.as_file_mut(), &temp_path)?;
+
+    // Renaming fails if the temporary directory is in another filesystem, copy the file next to the
+    // archive first so it's still replaced at once
+    if let Err(err) = temp_file.persist(archive_path) {
+        let mut copy = tempfile::NamedTempFile::new_in(archive_dir)?;
+        io::copy(&mut fs::File::open(err.file.path())?, copy.as_file_mut())?;
+        copy.persist(archive_path).map_err(|err| err.error)?;
+    }
+
+    fs::set_permissions(archive_path, permissions)?;
+    Ok(())
+}
+
+/// Names the entries of `files` will have in the archive, used to find the existing entries they replace.
+fn new_entry_names(files: &[PathBuf], file_visibility_policy: &FileVisibilityPolicy) -> Result<HashSet<PathBuf>> {
+    let mut names = HashSet::new();
+
+    for explicit_path in files {
+        let previous_location = cd_into_same_dir_as(explicit_path)?;
+
+        // Unwrap safety:
+        //   paths should be canonicalized by now, and the root directory rejected.
+        let filename = explicit_path.file_name().unwrap();
+
+        for path in file_visibility_policy.workaround_build_walker_or_broken_link_path(explicit_path, filename) {
+            names.insert(path?);
+        }
+
+        env::set_current_dir(previous_location)?;
+    }
+
+    Ok(names)
+}
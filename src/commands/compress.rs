@@ -1,32 +1,267 @@
 use std::{
-    io::{self, BufWriter, Cursor, Seek, Write},
-    num::NonZeroU64,
+    env,
+    io::{self, BufWriter, Cursor, IsTerminal, Seek, Write},
     path::{Path, PathBuf},
 };
 
 use fs_err as fs;
-use gzp::par::compress::{ParCompress, ParCompressBuilder};
 
-use super::warn_user_about_loading_sevenz_in_memory;
+use super::{chain_writer_encoder, warn_user_about_loading_sevenz_in_memory, warn_user_about_loading_zip_in_memory};
 use crate::{
-    BUFFER_CAPACITY, QuestionAction, QuestionPolicy, Result, archive,
-    commands::warn_user_about_loading_zip_in_memory,
-    extension::{CompressionFormat::*, Extension, split_first_compression_format},
+    BUFFER_CAPACITY, QuestionAction, QuestionPolicy, Result,
+    archive::{
+        self,
+        tar_index::{TarIndexEntry, TarIndexer},
+    },
+    check,
+    error::FinalError,
+    extension::{
+        self, CompressionFormat::*, Extension, parse_format_flag, split_first_compression_format,
+        split_format_flag_levels,
+    },
+    format_options::FormatOptions,
     info_accessible,
     utils::{
-        BytesFmt, FileVisibilityPolicy, file_size,
+        self, BytesFmt, FileVisibilityPolicy, PathFmt, PathTransform, Reproducible, absolutize_paths,
+        cd_into_same_dir_as, colors, file_size,
         io::lock_and_flush_output_stdio,
-        threads::{logical_thread_count, physical_thread_count},
+        is_path_stdout,
+        progress::{Operation, Progress, Side},
         user_wants_to_continue,
     },
+    warning,
 };
 
-/// Compress files into `output_file`.
+/// Options for [`compress`], the defaults match running `ouch compress` without flags.
+#[non_exhaustive]
+pub struct CompressOptions {
+    /// Formats to use instead of the output path extensions, same syntax as `--format`, e.g. "tar.gz",
+    /// or "tar.zst:19" with the level of a format
+    pub format: Option<String>,
+    /// Compression level, applied to all formats without a level in `format_options.levels`. Levels out
+    /// of the range of a format are clamped with a warning, except for `i16::MAX`, which selects the
+    /// highest level of every format
+    pub level: Option<i16>,
+    /// Format specific tunables, like the zstd window size
+    pub format_options: FormatOptions,
+    /// Read from target files instead of from symlinks (relevant for `tar` and `zip`)
+    pub follow_symlinks: bool,
+    pub question_policy: QuestionPolicy,
+    pub file_visibility_policy: FileVisibilityPolicy,
+    /// Encrypt zip entries and 7z archives with AES-256
+    pub password: Option<Vec<u8>>,
+    /// Also encrypt the entry names of 7z archives, requires `password`
+    pub encrypt_headers: bool,
+    /// Show the progress on STDERR when it's a terminal, or periodically in accessible mode
+    pub progress: bool,
+    /// Write an index of the entries of tar archives next to them, in `<output>.ouchidx`, that
+    /// `ouch list` reads instead of decompressing the archive
+    pub index: bool,
+    /// Changes the names of the entries of archives, from `--transform` and `--prefix`
+    pub path_transform: PathTransform,
+    /// Build archives that are the same byte for byte from the same files, the inputs and the entries
+    /// of directories are sorted
+    pub reproducible: Option<Reproducible>,
+}
+
+impl Default for CompressOptions {
+    fn default() -> Self {
+        Self {
+            format: None,
+            level: None,
+            format_options: FormatOptions::default(),
+            follow_symlinks: false,
+            question_policy: QuestionPolicy::Ask,
+            file_visibility_policy: FileVisibilityPolicy::default(),
+            password: None,
+            encrypt_headers: false,
+            progress: true,
+            index: false,
+            path_transform: PathTransform::default(),
+            reproducible: None,
+        }
+    }
+}
+
+/// Summary of a successful compression, returned by [`compress`]
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct CompressionSummary {
+    /// Where the output was written, might differ from the requested path if the user chose to rename it
+    pub output_path: PathBuf,
+    /// Size of the output file in bytes, `None` when writing to STDOUT
+    pub output_size: Option<u64>,
+}
+
+/// Compress `files` into `output_path`, detecting the formats from its extensions (or `options.format`).
+///
+/// If `output_path` is `"-"` (or `"-"` followed by extensions, like `"-.tar.zst"`), the output is
+/// written to STDOUT.
+///
+/// Returns `Ok(None)` if the operation was cancelled, e.g. the user chose not to overwrite the output.
+///
+/// Not thread-safe: the current directory of the process is changed while walking `files`, nothing
+/// else relying on it (like the other functions of this crate) should run at the same time.
+pub fn compress(
+    files: Vec<PathBuf>,
+    output_path: &Path,
+    mut options: CompressOptions,
+) -> Result<Option<CompressionSummary>> {
+    crate::force_lazy_locks_to_load();
+
+    let mut files = absolutize_paths(&files)?;
+
+    // After cleaning, if there are no input files left, exit
+    if files.is_empty() {
+        return Err(FinalError::with_title("No files to compress").into());
+    }
+
+    // Levels in the format flag, like "tar.zst:19", come before the ones of `--level zst=19`
+    if let Some(format) = &options.format {
+        let (format, levels) = split_format_flag_levels(format)?;
+        options.format = Some(format);
+        options.format_options.levels.splice(0..0, levels);
+    }
+
+    // Formats from path extension, like "file.tar.gz.xz" -> vec![Tar, Gzip, Lzma]
+    let formats = match &options.format {
+        Some(formats) => parse_format_flag(formats)?,
+        None => extension::extensions_from_path(output_path)?,
+    };
+
+    check::check_invalid_compression_with_non_archive_format(&formats, output_path, &files, options.format.as_deref())?;
+    check::check_archive_formats_position(&formats, output_path)?;
+    check::check_encryption_support(
+        &formats,
+        output_path,
+        options.password.is_some(),
+        options.encrypt_headers,
+    )?;
+    check::check_compression_levels(
+        &extension::flatten_compression_formats(&formats),
+        options.level,
+        &options.format_options,
+    );
+
+    if options.index && (is_path_stdout(output_path) || formats[0].compression_formats[0] != Tar) {
+        warning!("Ignoring --index, it's only written next to tar archives saved to a file");
+        options.index = false;
+    }
+    let mut tar_index = options.index.then(Vec::new);
+
+    if options.reproducible.is_some() {
+        files.sort();
+        options.file_visibility_policy.sort_entries = true;
+    }
+
+    let total = Progress::should_display(options.progress)
+        .then(|| total_file_size(&files, &options.file_visibility_policy))
+        .transpose()?;
+
+    if is_path_stdout(output_path) {
+        if io::stdout().is_terminal() {
+            let error = FinalError::with_title("Refusing to write compressed data to a terminal")
+                .detail("STDOUT was chosen as the output with \"-\"")
+                .hint("Redirect the output to a file or pipe it into another command");
+            return Err(error.into());
+        }
+
+        let progress = Progress::new(Operation::Compression, total, options.progress);
+        let compressed = compress_files(
+            files,
+            formats,
+            io::stdout(),
+            output_path,
+            options.follow_symlinks,
+            options.question_policy,
+            options.file_visibility_policy,
+            &options.path_transform,
+            options.reproducible,
+            options.level,
+            &options.format_options,
+            options.password.as_deref(),
+            options.encrypt_headers,
+            &progress,
+            None,
+        )?;
+
+        return Ok(compressed.then(|| CompressionSummary {
+            output_path: output_path.to_owned(),
+            output_size: None,
+        }));
+    }
+
+    let (output_file, output_path) = match utils::create_file_or_prompt_on_conflict(
+        output_path,
+        options.question_policy,
+        QuestionAction::Compression,
+    )? {
+        Some(writer) => writer,
+        None => return Ok(None),
+    };
+
+    let progress = Progress::new(Operation::Compression, total, options.progress);
+    let compress_result = compress_files(
+        files,
+        formats,
+        output_file,
+        &output_path,
+        options.follow_symlinks,
+        options.question_policy,
+        options.file_visibility_policy,
+        &options.path_transform,
+        options.reproducible,
+        options.level,
+        &options.format_options,
+        options.password.as_deref(),
+        options.encrypt_headers,
+        &progress,
+        tar_index.as_mut(),
+    );
+    drop(progress);
+
+    if let Ok(true) = compress_result {
+        if let Some(entries) = tar_index {
+            archive::tar_index::write_index(&output_path, entries)?;
+        }
+        let output_size = file_size(&output_path)?;
+        return Ok(Some(CompressionSummary {
+            output_path,
+            output_size: Some(output_size),
+        }));
+    }
+
+    // If Ok(false) or Err() occurred, delete incomplete file at `output_path`
+    //
+    // if deleting fails, print an extra alert message pointing
+    // out that we left a possibly CORRUPTED file at `output_path`
+    if utils::remove_file_or_dir(&output_path).is_err() {
+        eprintln!("{red}FATAL ERROR:\n", red = *colors::RED);
+        eprintln!("  Ouch failed to delete the file {}", PathFmt(&output_path));
+        eprintln!("  Please delete it manually.");
+        eprintln!("  This file is corrupted if compression didn't finished.");
+
+        if compress_result.is_err() {
+            eprintln!("  Compression failed for reasons below.");
+        }
+    }
+
+    compress_result.map(|_| None)
+}
+
+/// Compress files into `output_writer`.
 ///
 /// # Arguments:
 /// - `files`: is the list of paths to be compressed: ["dir/file1.txt", "dir/file2.txt"]
 /// - `extensions`: is a list of compression formats for compressing, example: [Tar, Gz] (in compression order)
-/// - `output_file` is the resulting compressed file name, example: "archive.tar.gz"
+/// - `output_writer` is where the compressed data goes, the file at `output_path` or STDOUT
+/// - `output_path` is the resulting compressed file name, example: "archive.tar.gz"
+/// - `path_transform` changes the names of the entries of archives
+/// - `reproducible` leaves out the owners of tar entries, and normalizes permissions and modification times
+/// - `password` encrypts zip and 7z archives, `encrypt_headers` also encrypts 7z entry names
+/// - `progress` counts the bytes of the input files and of the output, zip and 7z archives count their
+///   input files as they read them
+/// - `tar_index` receives the entries of tar archives, with their offsets in the uncompressed archive
 ///
 /// # Return value
 /// - Returns `Ok(true)` if compressed all files normally.
@@ -34,128 +269,86 @@ use crate::{
 pub fn compress_files(
     files: Vec<PathBuf>,
     extensions: Vec<Extension>,
-    output_file: fs::File,
+    output_writer: impl Write + Send + 'static,
     output_path: &Path,
     follow_symlinks: bool,
     question_policy: QuestionPolicy,
     file_visibility_policy: FileVisibilityPolicy,
+    path_transform: &PathTransform,
+    reproducible: Option<Reproducible>,
     level: Option<i16>,
+    format_options: &FormatOptions,
+    password: Option<&[u8]>,
+    encrypt_headers: bool,
+    progress: &Progress,
+    tar_index: Option<&mut Vec<TarIndexEntry>>,
 ) -> Result<bool> {
     // If the input files contain a directory, then the total size will be underestimated
-    let file_writer = BufWriter::with_capacity(BUFFER_CAPACITY, output_file);
-
-    let mut writer: Box<dyn Send + Write> = Box::new(file_writer);
-
-    // Grab previous encoder and wrap it inside of a new one
-    let chain_writer_encoder = |format: &_, encoder| -> Result<_> {
-        let encoder: Box<dyn Send + Write> = match format {
-            Gzip => Box::new({
-                // by default, ParCompress uses a default compression level of 3
-                // instead of the regular default that flate2 uses
-                let parz: ParCompress<gzp::deflate::Gzip, _> = ParCompressBuilder::new()
-                    .compression_level(
-                        level.map_or_else(Default::default, |l| gzp::Compression::new((l as u32).clamp(0, 9))),
-                    )
-                    .num_threads(logical_thread_count())
-                    .expect("gpz: num_threads must be greater than 0")
-                    .from_writer(encoder);
-                parz
-            }),
-            Bzip => Box::new(bzip2::write::BzEncoder::new(
-                encoder,
-                level.map_or_else(Default::default, |l| bzip2::Compression::new((l as u32).clamp(1, 9))),
-            )),
-            Bzip3 => {
-                #[cfg(not(feature = "bzip3"))]
-                return Err(crate::Error::bzip3_no_support());
-
-                #[cfg(feature = "bzip3")]
-                Box::new(
-                    // Use block size of 16 MiB
-                    bzip3::write::Bz3Encoder::new(encoder, 16 * 2_usize.pow(20))?,
-                )
-            }
-            Lz4 => Box::new(lz4_flex::frame::FrameEncoder::new(encoder).auto_finish()),
-            Lzma => {
-                let options = level.map_or_else(Default::default, |l| {
-                    lzma_rust2::LzmaOptions::with_preset((l as u32).clamp(0, 9))
-                });
-                let writer = lzma_rust2::LzmaWriter::new_use_header(encoder, &options, None)?;
-                Box::new(writer.auto_finish())
-            }
-            Xz => {
-                let mut options = level.map_or_else(Default::default, |l| {
-                    lzma_rust2::XzOptions::with_preset((l as u32).clamp(0, 9))
-                });
-                let dict_size = options.lzma_options.dict_size as u64;
-                options.set_block_size(NonZeroU64::new(dict_size));
-                // Use up to 256 PHYSICAL cores for compression
-                let writer = lzma_rust2::XzWriterMt::new(encoder, options, physical_thread_count() as u32)?;
-                Box::new(writer.auto_finish())
-            }
-            Lzip => {
-                let options = level.map_or_else(Default::default, |l| {
-                    lzma_rust2::LzipOptions::with_preset((l as u32).clamp(0, 9))
-                });
-                let writer = lzma_rust2::LzipWriter::new(encoder, options);
-                Box::new(writer.auto_finish())
-            }
-            Snappy => Box::new({
-                let parz: ParCompress<gzp::snap::Snap, _> = ParCompressBuilder::new()
-                    .compression_level(gzp::par::compress::Compression::new(
-                        level.map_or_else(Default::default, |l| (l as u32).clamp(0, 9)),
-                    ))
-                    .num_threads(logical_thread_count())
-                    .expect("gpz: num_threads must be greater than 0")
-                    .from_writer(encoder);
-                parz
-            }),
-            Zstd => {
-                let mut zstd_encoder = zstd::stream::write::Encoder::new(
-                    encoder,
-                    level.map_or(zstd::DEFAULT_COMPRESSION_LEVEL, |l| {
-                        (l as i32).clamp(zstd::zstd_safe::min_c_level(), zstd::zstd_safe::max_c_level())
-                    }),
-                )?;
-                // Use all available PHYSICAL cores for compression
-                zstd_encoder.multithread(physical_thread_count() as u32)?;
-                Box::new(zstd_encoder.auto_finish())
-            }
-            Brotli => {
-                let default_level = 11; // Same as brotli CLI, default to highest compression
-                let level = level.unwrap_or(default_level).clamp(0, 11) as u32;
-                let win_size = 22; // default to 2^22 = 4 MiB window size
-                Box::new(brotli::CompressorWriter::new(encoder, BUFFER_CAPACITY, level, win_size))
-            }
-            Tar | Zip | Rar | SevenZip => unreachable!(),
-        };
-        Ok(encoder)
-    };
+    let output_writer = progress.count_write(output_writer, Side::Compressed);
+    let buffered_writer = BufWriter::with_capacity(BUFFER_CAPACITY, output_writer);
+
+    let mut writer: Box<dyn Send + Write> = Box::new(buffered_writer);
 
     let (first_format, formats) = split_first_compression_format(&extensions);
 
     for format in formats.iter().rev() {
-        writer = chain_writer_encoder(format, writer)?;
+        writer = chain_writer_encoder(*format, writer, format_options.level(*format, level), format_options)?;
     }
 
     match first_format {
         Gzip | Bzip | Bzip3 | Lz4 | Lzma | Xz | Lzip | Snappy | Zstd | Brotli => {
-            writer = chain_writer_encoder(&first_format, writer)?;
+            writer = chain_writer_encoder(
+                first_format,
+                writer,
+                format_options.level(first_format, level),
+                format_options,
+            )?;
+            let mut writer = progress.count_write(writer, Side::Uncompressed);
             let mut reader = fs::File::open(&files[0])?;
             io::copy(&mut reader, &mut writer)?;
             info_accessible!("Input file size: {}", BytesFmt(file_size(&files[0])?));
         }
         Tar => {
-            archive::tar::build_archive(
+            let mut writer = progress.count_write(writer, Side::Uncompressed);
+            let build_archive = |writer: &mut dyn Write| -> Result<()> {
+                archive::tar::build_archive(
+                    &files,
+                    output_path,
+                    writer,
+                    file_visibility_policy,
+                    follow_symlinks,
+                    path_transform,
+                    reproducible,
+                )?;
+                Ok(())
+            };
+            match tar_index {
+                Some(tar_index) => {
+                    let mut indexer = TarIndexer::new(&mut writer);
+                    build_archive(&mut indexer)?;
+                    *tar_index = indexer.into_entries();
+                }
+                None => {
+                    build_archive(&mut writer)?;
+                }
+            }
+            writer.flush()?;
+        }
+        Zip if password.is_none() && (!formats.is_empty() || is_path_stdout(output_path)) => {
+            archive::zip::build_archive_stream(
                 &files,
                 output_path,
                 &mut writer,
                 file_visibility_policy,
                 follow_symlinks,
+                path_transform,
+                reproducible,
+                progress,
             )?;
             writer.flush()?;
         }
         Zip => {
+            // Encrypted entries can't be streamed, see `build_archive_stream`
             if !formats.is_empty() {
                 // Make thread own locks to keep output messages adjacent
                 let _locks = lock_and_flush_output_stdio();
@@ -174,6 +367,10 @@ pub fn compress_files(
                 &mut vec_buffer,
                 file_visibility_policy,
                 follow_symlinks,
+                password,
+                path_transform,
+                reproducible,
+                progress,
             )?;
             vec_buffer.rewind()?;
             io::copy(&mut vec_buffer, &mut writer)?;
@@ -197,11 +394,54 @@ pub fn compress_files(
             }
 
             let mut vec_buffer = Cursor::new(vec![]);
-            archive::sevenz::build_archive(&files, output_path, &mut vec_buffer, file_visibility_policy)?;
+            archive::sevenz::build_archive(
+                &files,
+                output_path,
+                &mut vec_buffer,
+                file_visibility_policy,
+                password,
+                encrypt_headers,
+                path_transform,
+                reproducible,
+                progress,
+            )?;
             vec_buffer.rewind()?;
+
+            // The headers are stored as is when compressing doesn't make them smaller, even if they should be encrypted
+            if encrypt_headers && !archive::sevenz::has_encrypted_headers(&mut vec_buffer)? {
+                warning!(
+                    "The list of entries is too small to be encrypted, entry names can be read without the password"
+                );
+            }
             io::copy(&mut vec_buffer, &mut writer)?;
         }
     }
 
     Ok(true)
 }
+
+/// Sum of the sizes of the files that will be compressed, used to estimate the remaining time.
+///
+/// Directories are walked from their parent like the archive builders do, so the same ignore files apply.
+fn total_file_size(files: &[PathBuf], file_visibility_policy: &FileVisibilityPolicy) -> Result<u64> {
+    let mut total = 0;
+
+    for explicit_path in files {
+        let previous_location = cd_into_same_dir_as(explicit_path)?;
+
+        // Unwrap safety:
+        //   paths should be canonicalized by now, and the root directory rejected.
+        let filename = explicit_path.file_name().unwrap();
+
+        total += file_visibility_policy
+            .build_walker(filename)
+            .filter_map(|entry| entry.ok()?.metadata().ok())
+            .filter(|metadata| metadata.is_file())
+            .map(|metadata| metadata.len())
+            .sum::<u64>();
+
+        env::set_current_dir(previous_location)?;
+    }
+
+    Ok(total)
+}
@@ -1,28 +1,79 @@
 use std::{
-    io::{self, BufReader, Read},
+    io::{self, BufReader, BufWriter, Read, Seek, Write},
     ops::ControlFlow,
     path::{Path, PathBuf},
 };
 
 use fs_err::{self as fs, PathExt};
+use rayon::prelude::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
 
 use crate::{
-    BUFFER_CAPACITY, QuestionAction, QuestionPolicy, Result,
-    commands::{warn_user_about_loading_sevenz_in_memory, warn_user_about_loading_zip_in_memory},
-    extension::{
-        CompressionFormat::{self, *},
-        Extension, split_first_compression_format,
-    },
-    info, info_accessible,
-    non_archive::lz4::MultiFrameLz4Decoder,
+    BUFFER_CAPACITY, INITIAL_CURRENT_DIR, QuestionAction, QuestionPolicy, Result,
+    check::{self, CheckFileSignatureControlFlow},
+    commands::{chain_reader_decoder, open_seekable_zstd, warn_user_about_buffering_archive, with_archive_password},
+    error::{Error, FinalError},
+    extension::{self, CompressionFormat::*, Extension, parse_format_flag, split_first_compression_format},
+    info,
     utils::{
-        self, BytesFmt, PathFmt, file_size,
-        io::{ReadSeek, lock_and_flush_output_stdio},
-        is_path_stdin, resolve_path_conflict, user_wants_to_continue,
+        self, EntryFilter, NoQuotePathFmt, PathFmt, PathTransform, TempStorage, absolutize_paths, canonicalize,
+        file_size,
+        io::ReadSeek,
+        is_path_stdin,
+        progress::{Operation, Progress, Side},
+        resolve_path_conflict,
     },
 };
 
-pub struct DecompressOptions<'a> {
+/// Options for [`decompress`], the defaults match running `ouch decompress` without flags.
+#[non_exhaustive]
+pub struct DecompressOptions {
+    /// Formats to use instead of the input path extensions, same syntax as `--format`, e.g. "tar.gz"
+    pub format: Option<String>,
+    /// Decompress inside this directory instead of the current directory
+    pub output_dir: Option<PathBuf>,
+    pub question_policy: QuestionPolicy,
+    pub password: Option<Vec<u8>>,
+    /// Remove the source file after successful decompression
+    pub remove: bool,
+    /// Write the decompressed data to STDOUT instead of files, for archives the contents of the
+    /// selected entries are written one after another. Files are then decompressed sequentially
+    pub stdout: bool,
+    /// Archive entries to extract, every entry by default
+    pub entry_filter: EntryFilter,
+    /// Changes the paths of the extracted entries, like `--strip-components`
+    pub path_transform: PathTransform,
+    /// Where to buffer zip, 7z and rar archives that can't be read directly, like `.zip.gz` or STDIN
+    pub temp_storage: TempStorage,
+    /// Dictionary that zstd data was compressed with, if any, see `ouch train-dict`
+    pub zstd_dict: Option<Vec<u8>>,
+    /// Show the progress on STDERR when it's a terminal, or periodically in accessible mode
+    pub progress: bool,
+    /// Unpack archives into a directory named after them, unless they have a single top-level entry,
+    /// which is moved to the output directory. Ignored with `output_dir`
+    pub smart_unpack: bool,
+}
+
+impl Default for DecompressOptions {
+    fn default() -> Self {
+        Self {
+            format: None,
+            output_dir: None,
+            question_policy: QuestionPolicy::Ask,
+            password: None,
+            remove: false,
+            stdout: false,
+            entry_filter: EntryFilter::default(),
+            path_transform: PathTransform::default(),
+            temp_storage: TempStorage::default(),
+            zstd_dict: None,
+            progress: true,
+            smart_unpack: true,
+        }
+    }
+}
+
+/// Options for decompressing a single file with its formats already resolved
+pub struct DecompressFileOptions<'a> {
     /// Example: "archive.tar.gz"
     pub input_file_path: &'a Path,
     /// Example: [Gz, Tar] (notice it's ordered in decompression order)
@@ -33,145 +84,373 @@ pub struct DecompressOptions<'a> {
     pub question_policy: QuestionPolicy,
     pub password: Option<&'a [u8]>,
     pub remove: bool,
+    pub stdout: bool,
+    pub entry_filter: &'a EntryFilter,
+    pub path_transform: &'a PathTransform,
+    pub temp_storage: &'a TempStorage,
+    pub zstd_dict: Option<&'a [u8]>,
+    /// Counts the bytes read from the input and the decompressed bytes
+    pub progress: &'a Progress,
+    /// Unpack archives into `output_file_path`, or move their single top-level entry to `output_dir`
+    pub smart_unpack: bool,
 }
 
-enum DecompressionSummary {
-    Archive { files_unpacked: u64 },
-    NonArchive { output_path: PathBuf },
+/// Summary of a successful decompression, returned by [`decompress`]
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum DecompressionSummary {
+    /// An archive was unpacked
+    #[non_exhaustive]
+    Archive {
+        input_path: PathBuf,
+        /// `"-"` when the entries were written to STDOUT
+        output_dir: PathBuf,
+        files_unpacked: u64,
+    },
+    /// A single compressed file was decompressed
+    #[non_exhaustive]
+    NonArchive {
+        input_path: PathBuf,
+        /// `"-"` when written to STDOUT
+        output_path: PathBuf,
+        /// `None` when reading from STDIN
+        input_size: Option<u64>,
+        output_size: u64,
+    },
 }
 
-/// Decompress (or unpack) a compressed (or packed) file.
-pub fn decompress_file(options: DecompressOptions) -> Result<()> {
-    assert!(options.output_dir.fs_err_try_exists()?);
+/// Decompress (or unpack) each of `files`, detecting formats from extensions and file signatures
+/// (or `options.format`), `"-"` reads from STDIN.
+///
+/// Files are decompressed in parallel, files skipped by the user (e.g. by refusing to overwrite the
+/// output) have no summary in the returned list.
+///
+/// Not thread-safe with [`compress`](crate::compress) and [`add`](crate::add), which change the current
+/// directory that relative paths are resolved against.
+pub fn decompress(files: &[PathBuf], options: DecompressOptions) -> Result<Vec<DecompressionSummary>> {
+    crate::force_lazy_locks_to_load();
 
-    let input_is_stdin = is_path_stdin(options.input_file_path);
-    let (first_extension, extensions) = split_first_compression_format(&options.formats);
+    let files = absolutize_paths(files)?;
+    let mut files_output_paths: Vec<_> = vec![];
+    let mut files_extensions: Vec<Vec<_>> = vec![];
+
+    if let Some(format) = &options.format {
+        let format = parse_format_flag(format)?;
+        for path in files.iter() {
+            let file_name = path.file_name().ok_or_else(|| Error::Custom {
+                reason: FinalError::with_title(format!("{} does not have a file name", PathFmt(path))),
+            })?;
+            files_output_paths.push(file_name.into());
+            files_extensions.push(format.clone());
+        }
+    } else {
+        for path in files.iter() {
+            let (output_path, mut extensions) = extension::separate_known_extensions_from_name(path)?;
+            let mut output_path = output_path.to_owned();
 
-    // Grab previous decoder and wrap it inside of a new one
-    let chain_reader_decoder = |format: &CompressionFormat, decoder: Box<dyn Read>| -> Result<Box<dyn Read>> {
-        let decoder: Box<dyn Read> = match format {
-            Gzip => Box::new(flate2::read::MultiGzDecoder::new(decoder)),
-            Bzip => Box::new(bzip2::read::MultiBzDecoder::new(decoder)),
-            Bzip3 => {
-                #[cfg(not(feature = "bzip3"))]
-                return Err(crate::Error::bzip3_no_support());
-                #[cfg(feature = "bzip3")]
-                Box::new(bzip3::read::Bz3Decoder::new(decoder)?)
+            match check::check_file_signature(path, &extensions, options.question_policy)? {
+                CheckFileSignatureControlFlow::HaltProgram => return Ok(vec![]),
+                CheckFileSignatureControlFlow::Continue => {}
+                CheckFileSignatureControlFlow::ChangeToDetectedExtension {
+                    new_extension,
+                    new_path_filename,
+                } => {
+                    extensions = vec![new_extension];
+                    output_path = output_path.with_file_name(new_path_filename);
+                }
             }
-            Lz4 => Box::new(MultiFrameLz4Decoder::new(decoder)),
-            Lzma => Box::new(lzma_rust2::LzmaReader::new_mem_limit(decoder, u32::MAX, None)?),
-            Xz => Box::new(lzma_rust2::XzReader::new(decoder, true)),
-            Lzip => Box::new(lzma_rust2::LzipReader::new(decoder)?),
-            Snappy => Box::new(snap::read::FrameDecoder::new(decoder)),
-            Zstd => Box::new(zstd::stream::Decoder::new(decoder)?),
-            Brotli => Box::new(brotli::Decompressor::new(decoder, BUFFER_CAPACITY)),
-            Tar | Zip | Rar | SevenZip => unreachable!(),
+
+            files_output_paths.push(output_path);
+            files_extensions.push(extensions);
+        }
+    }
+
+    check::check_missing_formats_when_decompressing(&files, &files_extensions)?;
+
+    if options.entry_filter.has_patterns() {
+        check::check_for_non_archive_formats_when_selecting_entries(&files, &files_extensions)?;
+    }
+
+    // The directory that will contain the output files
+    // We default to the current directory if the user didn't specify an output directory with --dir
+    let output_dir = if let Some(dir) = &options.output_dir {
+        utils::create_dir_if_non_existent(dir)?;
+        // If not canonicalized, strip_prefix won't work and logs will break
+        // Led to bugs when output_dir was a symlink
+        canonicalize(dir)?
+    } else {
+        INITIAL_CURRENT_DIR.clone()
+    };
+
+    // Reading from STDIN, the amount of input is unknown
+    let total = files
+        .iter()
+        .map(|path| file_size(path).ok().filter(|_| !is_path_stdin(path)))
+        .sum::<Option<u64>>();
+    let progress = Progress::new(Operation::Decompression, total, options.progress);
+
+    let decompress_one = |((input_path, formats), file_name): ((&PathBuf, Vec<Extension>), PathBuf)| {
+        // Path used by single file format archives
+        let output_file_path = if is_path_stdin(&file_name) {
+            output_dir.join("ouch-output")
+        } else {
+            output_dir.join(file_name)
         };
-        Ok(decoder)
+
+        decompress_file(DecompressFileOptions {
+            input_file_path: input_path,
+            formats,
+            output_dir: &output_dir,
+            output_file_path,
+            question_policy: options.question_policy,
+            password: options.password.as_deref(),
+            remove: options.remove,
+            stdout: options.stdout,
+            entry_filter: &options.entry_filter,
+            path_transform: &options.path_transform,
+            temp_storage: &options.temp_storage,
+            zstd_dict: options.zstd_dict.as_deref(),
+            progress: &progress,
+            // The user chose where the entries go
+            smart_unpack: options.smart_unpack && options.output_dir.is_none(),
+        })
+        .map_err(|err| match err {
+            Error::IoError { reason } => Error::Custom {
+                reason: FinalError::with_title(format!("Failed to decompress {}", NoQuotePathFmt(input_path)))
+                    .detail(reason),
+            },
+            other => other,
+        })
+    };
+
+    // Outputs would be interleaved if written to STDOUT in parallel
+    let summaries = if options.stdout {
+        files
+            .iter()
+            .zip(files_extensions)
+            .zip(files_output_paths)
+            .map(decompress_one)
+            .collect::<Result<Vec<_>>>()?
+    } else {
+        files
+            .par_iter()
+            .zip(files_extensions)
+            .zip(files_output_paths)
+            .map(decompress_one)
+            .collect::<Result<Vec<_>>>()?
     };
 
-    let create_decoder_up_to_first_extension = || -> Result<Box<dyn Read>> {
-        let mut reader: Box<dyn Read> = if input_is_stdin {
-            Box::new(io::stdin())
+    Ok(summaries.into_iter().flatten().collect())
+}
+
+/// Decompress (or unpack) a compressed (or packed) file.
+///
+/// Returns `Ok(None)` if the user cancelled the operation.
+pub fn decompress_file(options: DecompressFileOptions) -> Result<Option<DecompressionSummary>> {
+    assert!(options.output_dir.fs_err_try_exists()?);
+
+    let input_is_stdin = is_path_stdin(options.input_file_path);
+    let (first_extension, extensions) = split_first_compression_format(&options.formats);
+
+    let create_decoder_up_to_first_extension = || -> Result<Box<dyn Read + Send>> {
+        let mut reader: Box<dyn Read + Send> = if input_is_stdin {
+            Box::new(options.progress.count_read(io::stdin(), Side::Compressed))
         } else {
+            let file = fs::File::open(options.input_file_path)?;
             Box::new(BufReader::with_capacity(
                 BUFFER_CAPACITY,
-                fs::File::open(options.input_file_path)?,
+                options.progress.count_read(file, Side::Compressed),
             ))
         };
 
         for format in extensions.iter().rev() {
-            reader = chain_reader_decoder(format, reader)?;
+            reader = chain_reader_decoder(*format, reader, options.zstd_dict)?;
         }
 
         Ok(reader)
     };
 
+    // With a seek table, tar can jump over the entries it doesn't need instead of decompressing them
+    let open_seekable_tar = || -> Result<Option<Box<dyn ReadSeek>>> {
+        if input_is_stdin || extensions != [Zstd] {
+            return Ok(None);
+        }
+        let file = fs::File::open(options.input_file_path)?;
+        let reader = open_seekable_zstd(options.progress.count_read(file, Side::Compressed), options.zstd_dict)?;
+        Ok(reader.map(|reader| Box::new(options.progress.count_read(reader, Side::Uncompressed)) as Box<dyn ReadSeek>))
+    };
+
     let control_flow = match first_extension {
         Gzip | Bzip | Bzip3 | Lz4 | Lzma | Xz | Lzip | Snappy | Zstd | Brotli => {
             let reader = create_decoder_up_to_first_extension()?;
-            let mut reader = chain_reader_decoder(&first_extension, reader)?;
+            let reader = chain_reader_decoder(first_extension, reader, options.zstd_dict)?;
+            let mut reader = options.progress.count_read(reader, Side::Uncompressed);
 
-            let (mut writer, final_output_path) = match utils::create_file_or_prompt_on_conflict(
-                &options.output_file_path,
-                options.question_policy,
-                QuestionAction::Decompression,
-            )? {
-                Some(file) => file,
-                None => return Ok(()),
+            let (output_size, final_output_path) = if options.stdout {
+                let output_size = write_to_stdout(|writer| Ok(io::copy(&mut reader, writer)?))?;
+                (output_size, PathBuf::from("-"))
+            } else {
+                let (mut writer, final_output_path) = match utils::create_file_or_prompt_on_conflict(
+                    &options.output_file_path,
+                    options.question_policy,
+                    QuestionAction::Decompression,
+                )? {
+                    Some(file) => file,
+                    None => return Ok(None),
+                };
+
+                io::copy(&mut reader, &mut writer)?;
+                (file_size(&final_output_path)?, final_output_path)
             };
 
-            io::copy(&mut reader, &mut writer)?;
             ControlFlow::Continue(DecompressionSummary::NonArchive {
+                input_path: options.input_file_path.to_owned(),
+                input_size: if input_is_stdin {
+                    None
+                } else {
+                    Some(file_size(options.input_file_path)?)
+                },
+                output_size,
                 output_path: final_output_path,
             })
         }
+        Tar if options.stdout => write_archive_entries_to_stdout(
+            |writer| match open_seekable_tar()? {
+                Some(reader) => crate::archive::tar::write_entries_with_seek(reader, writer, options.entry_filter),
+                None => crate::archive::tar::write_entries(
+                    options
+                        .progress
+                        .count_read(create_decoder_up_to_first_extension()?, Side::Uncompressed),
+                    writer,
+                    options.entry_filter,
+                ),
+            },
+            options.input_file_path,
+        )?,
         Tar => unpack_archive(
-            |output_dir| crate::archive::tar::unpack_archive(create_decoder_up_to_first_extension()?, output_dir),
-            options.output_dir,
-            options.question_policy,
+            |output_dir| match open_seekable_tar()? {
+                Some(reader) => crate::archive::tar::unpack_archive_with_seek(
+                    reader,
+                    output_dir,
+                    options.entry_filter,
+                    options.path_transform,
+                ),
+                None => crate::archive::tar::unpack_archive(
+                    options
+                        .progress
+                        .count_read(create_decoder_up_to_first_extension()?, Side::Uncompressed),
+                    output_dir,
+                    options.entry_filter,
+                    options.path_transform,
+                ),
+            },
+            &options,
         )?,
         Zip | SevenZip => {
-            let unpack_fn = match first_extension {
-                Zip => crate::archive::zip::unpack_archive,
-                SevenZip => crate::archive::sevenz::unpack_archive,
-                _ => unreachable!(),
-            };
-
-            let should_load_everything_into_memory = input_is_stdin || !extensions.is_empty();
+            let should_buffer_archive = input_is_stdin || !extensions.is_empty();
 
             // due to `io::Seek` being required by `Zip` and `SevenZip`, we might have to
-            // copy all contents into a Vec to pass an `io::Cursor` (impls Seek)
-            let reader: Box<dyn ReadSeek> = if should_load_everything_into_memory {
-                let memory_warning_fn = match first_extension {
-                    Zip => warn_user_about_loading_zip_in_memory,
-                    SevenZip => warn_user_about_loading_sevenz_in_memory,
-                    _ => unreachable!(),
-                };
-
-                // Make thread own locks to keep output messages adjacent
-                let locks = lock_and_flush_output_stdio();
-                memory_warning_fn();
-                if !user_wants_to_continue(
-                    options.input_file_path,
-                    options.question_policy,
-                    QuestionAction::Decompression,
-                )? {
-                    return Ok(());
-                }
-                drop(locks);
-
-                let mut vec = vec![];
-                io::copy(&mut create_decoder_up_to_first_extension()?, &mut vec)?;
-                Box::new(io::Cursor::new(vec))
+            // buffer all contents in memory or in a temporary file
+            let mut reader: Box<dyn ReadSeek> = if should_buffer_archive {
+                warn_user_about_buffering_archive(first_extension, options.temp_storage);
+                options.temp_storage.buffer(create_decoder_up_to_first_extension()?)?
             } else {
+                let file = fs::File::open(options.input_file_path)?;
                 Box::new(BufReader::with_capacity(
                     BUFFER_CAPACITY,
-                    fs::File::open(options.input_file_path)?,
+                    options.progress.count_read(file, Side::Compressed),
                 ))
             };
 
-            unpack_archive(
-                |output_dir| unpack_fn(reader, output_dir, options.password),
-                options.output_dir,
+            let password = with_archive_password(
+                options.input_file_path,
+                options.password,
                 options.question_policy,
-            )?
+                |password| {
+                    match first_extension {
+                        Zip => crate::archive::zip::check_password(&mut reader, password),
+                        SevenZip => crate::archive::sevenz::check_password(&mut reader, password),
+                        _ => unreachable!(),
+                    }
+                    .map(|()| password.map(ToOwned::to_owned))
+                },
+            )?;
+            let password = password.as_deref();
+            reader.rewind()?;
+
+            if options.stdout {
+                write_archive_entries_to_stdout(
+                    |writer| match first_extension {
+                        Zip => crate::archive::zip::write_entries(reader, writer, password, options.entry_filter),
+                        SevenZip => {
+                            crate::archive::sevenz::write_entries(reader, writer, password, options.entry_filter)
+                        }
+                        _ => unreachable!(),
+                    },
+                    options.input_file_path,
+                )?
+            } else {
+                let unpack_fn = match first_extension {
+                    Zip => crate::archive::zip::unpack_archive,
+                    SevenZip => crate::archive::sevenz::unpack_archive,
+                    _ => unreachable!(),
+                };
+
+                unpack_archive(
+                    |output_dir| {
+                        unpack_fn(
+                            reader,
+                            output_dir,
+                            password,
+                            options.entry_filter,
+                            options.path_transform,
+                        )
+                    },
+                    &options,
+                )?
+            }
         }
         #[cfg(feature = "unrar")]
         Rar => {
-            let unpack_fn: Box<dyn FnOnce(&Path) -> Result<u64>> = if options.formats.len() > 1 || input_is_stdin {
-                let mut temp_file = tempfile::NamedTempFile::new()?;
+            let temp_file = if options.formats.len() > 1 || input_is_stdin {
+                let mut temp_file = options.temp_storage.named_temp_file()?;
                 io::copy(&mut create_decoder_up_to_first_extension()?, &mut temp_file)?;
-                Box::new(move |output_dir| {
-                    crate::archive::rar::unpack_archive(temp_file.path(), output_dir, options.password)
-                })
+                Some(temp_file)
             } else {
-                Box::new(|output_dir| {
-                    crate::archive::rar::unpack_archive(options.input_file_path, output_dir, options.password)
-                })
+                None
             };
+            let archive_path = temp_file.as_ref().map_or(options.input_file_path, |file| file.path());
 
-            unpack_archive(unpack_fn, options.output_dir, options.question_policy)?
+            let password = with_archive_password(
+                options.input_file_path,
+                options.password,
+                options.question_policy,
+                |password| {
+                    crate::archive::rar::check_password(archive_path, password)
+                        .map(|()| password.map(ToOwned::to_owned))
+                },
+            )?;
+            let password = password.as_deref();
+
+            if options.stdout {
+                write_archive_entries_to_stdout(
+                    |writer| crate::archive::rar::write_entries(archive_path, writer, password, options.entry_filter),
+                    options.input_file_path,
+                )?
+            } else {
+                unpack_archive(
+                    |output_dir| {
+                        crate::archive::rar::unpack_archive(
+                            archive_path,
+                            output_dir,
+                            password,
+                            options.entry_filter,
+                            options.path_transform,
+                        )
+                    },
+                    &options,
+                )?
+            }
         }
         #[cfg(not(feature = "unrar"))]
         Rar => {
@@ -180,27 +459,21 @@ pub fn decompress_file(options: DecompressOptions) -> Result<()> {
     };
 
     let ControlFlow::Continue(decompression_summary) = control_flow else {
-        return Ok(());
+        return Ok(None);
     };
 
-    match decompression_summary {
-        DecompressionSummary::Archive { files_unpacked } => {
-            info_accessible!("Successfully decompressed archive to {}", PathFmt(options.output_dir));
-            info_accessible!("Files unpacked: {files_unpacked}");
-        }
-        DecompressionSummary::NonArchive { output_path } => {
-            if input_is_stdin {
-                info_accessible!("STDIN decompressed to {}", PathFmt(&output_path));
-            } else {
-                info_accessible!(
-                    "File {} decompressed to {}",
-                    PathFmt(options.input_file_path),
-                    PathFmt(&output_path),
-                );
-                info_accessible!("Input file size: {}", BytesFmt(file_size(options.input_file_path)?));
-            }
-            info_accessible!("Output file size: {}", BytesFmt(file_size(&output_path)?));
-        }
+    if options.entry_filter.has_patterns()
+        && let DecompressionSummary::Archive { files_unpacked: 0, .. } = decompression_summary
+    {
+        let error = FinalError::with_title(format!(
+            "No entries of {} matched the given patterns",
+            PathFmt(options.input_file_path)
+        ))
+        .hint(format!(
+            "Use `ouch list {}` to see the entries of the archive",
+            NoQuotePathFmt(options.input_file_path)
+        ));
+        return Err(error.into());
     }
 
     if !input_is_stdin && options.remove {
@@ -208,18 +481,25 @@ pub fn decompress_file(options: DecompressOptions) -> Result<()> {
         info!("Removed input file {}", PathFmt(options.input_file_path));
     }
 
-    Ok(())
+    Ok(Some(decompression_summary))
 }
 
 /// Unpacks an archive creating the output directory, this function will create the output_dir
 /// directory or replace it if it already exists. The `output_dir` needs to be empty
 /// - If `output_dir` does not exist OR is a empty directory, it will unpack there
 /// - If `output_dir` exist OR is a directory not empty, the user will be asked what to do
+///
+/// With `options.smart_unpack`, see [`smart_unpack`] instead.
 fn unpack_archive(
     unpack_fn: impl FnOnce(&Path) -> Result<u64>,
-    output_dir: &Path,
-    question_policy: QuestionPolicy,
+    options: &DecompressFileOptions,
 ) -> Result<ControlFlow<(), DecompressionSummary>> {
+    if options.smart_unpack {
+        return smart_unpack(unpack_fn, options);
+    }
+
+    let (input_path, output_dir, question_policy) =
+        (options.input_file_path, options.output_dir, options.question_policy);
     let is_valid_output_dir =
         !output_dir.fs_err_try_exists()? || (output_dir.is_dir() && output_dir.read_dir()?.next().is_none());
 
@@ -237,5 +517,88 @@ fn unpack_archive(
 
     let files_unpacked = unpack_fn(&output_dir_cleaned)?;
 
-    Ok(ControlFlow::Continue(DecompressionSummary::Archive { files_unpacked }))
+    Ok(ControlFlow::Continue(DecompressionSummary::Archive {
+        input_path: input_path.to_owned(),
+        output_dir: output_dir_cleaned,
+        files_unpacked,
+    }))
+}
+
+/// Unpacks an archive into a temporary directory inside of `options.output_dir`, then moves what it
+/// contains into place, so archives never spill their entries into the output directory:
+/// - If the archive has a single top-level entry, it's moved to the output directory
+/// - Otherwise the entries are moved to a directory named after the archive, `options.output_file_path`
+///
+/// The user is asked what to do if the destination already exists.
+fn smart_unpack(
+    unpack_fn: impl FnOnce(&Path) -> Result<u64>,
+    options: &DecompressFileOptions,
+) -> Result<ControlFlow<(), DecompressionSummary>> {
+    let temp_dir = tempfile::Builder::new()
+        .prefix(".tmp-ouch-")
+        .tempdir_in(options.output_dir)?;
+    let files_unpacked = unpack_fn(temp_dir.path())?;
+
+    let top_level_entries = fs::read_dir(temp_dir.path())?
+        .map(|entry| Ok(entry?.file_name()))
+        .collect::<Result<Vec<_>>>()?;
+
+    let output_path = match top_level_entries.as_slice() {
+        // Nothing to move, e.g. no entries matched the patterns
+        [] => options.output_dir.to_owned(),
+        [entry] => {
+            let output_path = options.output_dir.join(entry);
+            let Some(output_path) =
+                resolve_path_conflict(&output_path, options.question_policy, QuestionAction::Decompression)?
+            else {
+                return Ok(ControlFlow::Break(()));
+            };
+            utils::move_merging(&temp_dir.path().join(entry), &output_path)?;
+            output_path
+        }
+        entries => {
+            let Some(output_path) = resolve_path_conflict(
+                &options.output_file_path,
+                options.question_policy,
+                QuestionAction::Decompression,
+            )?
+            else {
+                return Ok(ControlFlow::Break(()));
+            };
+            utils::create_dir_if_non_existent(&output_path)?;
+            for entry in entries {
+                utils::move_merging(&temp_dir.path().join(entry), &output_path.join(entry))?;
+            }
+            output_path
+        }
+    };
+
+    Ok(ControlFlow::Continue(DecompressionSummary::Archive {
+        input_path: options.input_file_path.to_owned(),
+        output_dir: output_path,
+        files_unpacked,
+    }))
+}
+
+/// Writes the selected entries of an archive to STDOUT with `write_fn`, which returns how many
+/// entries were written.
+fn write_archive_entries_to_stdout(
+    write_fn: impl FnOnce(&mut dyn Write) -> Result<u64>,
+    input_path: &Path,
+) -> Result<ControlFlow<(), DecompressionSummary>> {
+    let files_unpacked = write_to_stdout(write_fn)?;
+
+    Ok(ControlFlow::Continue(DecompressionSummary::Archive {
+        input_path: input_path.to_owned(),
+        output_dir: PathBuf::from("-"),
+        files_unpacked,
+    }))
+}
+
+/// Calls `write_fn` with a buffered writer to STDOUT, flushing it afterwards.
+fn write_to_stdout<T>(write_fn: impl FnOnce(&mut dyn Write) -> Result<T>) -> Result<T> {
+    let mut writer = BufWriter::with_capacity(BUFFER_CAPACITY, io::stdout().lock());
+    let result = write_fn(&mut writer)?;
+    writer.flush()?;
+    Ok(result)
 }
@@ -1,80 +1,172 @@
 use std::{
     io::{self, BufReader, Read},
-    path::Path,
+    path::{Path, PathBuf},
 };
 
 use fs_err as fs;
 
 use crate::{
-    BUFFER_CAPACITY, QuestionAction, QuestionPolicy, Result, archive,
-    commands::warn_user_about_loading_zip_in_memory,
-    extension::CompressionFormat::{self, *},
-    list::{self, FileInArchive, ListOptions},
-    non_archive::lz4::MultiFrameLz4Decoder,
-    utils::{io::lock_and_flush_output_stdio, user_wants_to_continue},
+    BUFFER_CAPACITY, QuestionPolicy, Result, archive,
+    check::{self, CheckFileSignatureControlFlow},
+    commands::{chain_reader_decoder, open_seekable_zstd, warn_user_about_buffering_archive, with_archive_password},
+    extension::{
+        self,
+        CompressionFormat::{self, *},
+        parse_format_flag,
+    },
+    list::FileInArchive,
+    utils::{TempStorage, absolutize_paths},
 };
 
+/// Options for [`list`], the defaults match running `ouch list` without flags.
+#[non_exhaustive]
+pub struct ListArchiveOptions {
+    /// Formats to use instead of the archive path extensions, same syntax as `--format`, e.g. "tar.gz"
+    pub format: Option<String>,
+    pub question_policy: QuestionPolicy,
+    pub password: Option<Vec<u8>>,
+    /// Where to buffer zip, 7z and rar archives that can't be read directly, like `.zip.gz`
+    pub temp_storage: TempStorage,
+    /// Dictionary that zstd data was compressed with, if any, see `ouch train-dict`
+    pub zstd_dict: Option<Vec<u8>>,
+}
+
+impl Default for ListArchiveOptions {
+    fn default() -> Self {
+        Self {
+            format: None,
+            question_policy: QuestionPolicy::Ask,
+            password: None,
+            temp_storage: TempStorage::default(),
+            zstd_dict: None,
+        }
+    }
+}
+
+/// Entries of a single archive, returned by [`list`]
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct ArchiveListing {
+    pub archive_path: PathBuf,
+    pub files: Vec<FileInArchive>,
+}
+
+/// Read the entries of each archive in `archives`, detecting formats from extensions and file
+/// signatures (or `options.format`).
+///
+/// Not thread-safe with [`compress`](crate::compress) and [`add`](crate::add), which change the current
+/// directory that relative paths are resolved against.
+pub fn list(archives: &[PathBuf], options: ListArchiveOptions) -> Result<Vec<ArchiveListing>> {
+    let mut listings = vec![];
+    list_each(archives, options, |archive_path, files| {
+        let files = files.collect::<Result<_>>()?;
+        listings.push(ArchiveListing { archive_path, files });
+        Ok(())
+    })?;
+    Ok(listings)
+}
+
+/// Like [`list`], but `on_archive` receives the entries of each archive as they are read, instead
+/// of collecting them first. Not thread-safe either.
+pub fn list_each(
+    archives: &[PathBuf],
+    options: ListArchiveOptions,
+    mut on_archive: impl FnMut(PathBuf, Box<dyn Iterator<Item = Result<FileInArchive>>>) -> Result<()>,
+) -> Result<()> {
+    crate::force_lazy_locks_to_load();
+
+    let files = absolutize_paths(archives)?;
+    let mut formats = vec![];
+
+    if let Some(format) = &options.format {
+        let format = parse_format_flag(format)?;
+        for _ in 0..files.len() {
+            formats.push(format.clone());
+        }
+    } else {
+        for path in files.iter() {
+            let mut extensions = extension::extensions_from_path(path)?;
+
+            match check::check_file_signature(path, &extensions, options.question_policy)? {
+                CheckFileSignatureControlFlow::HaltProgram => return Ok(()),
+                CheckFileSignatureControlFlow::Continue => {}
+                CheckFileSignatureControlFlow::ChangeToDetectedExtension { new_extension, .. } => {
+                    extensions = vec![new_extension]
+                }
+            }
+
+            formats.push(extensions);
+        }
+    }
+
+    // Ensure we were not told to list the content of a non-archive compressed file
+    check::check_for_non_archive_formats(&files, &formats)?;
+
+    for (archive_path, formats) in files.into_iter().zip(formats) {
+        let formats = extension::flatten_compression_formats(&formats);
+        let files = list_archive_contents(
+            &archive_path,
+            formats,
+            options.question_policy,
+            options.password.as_deref(),
+            &options.temp_storage,
+            options.zstd_dict.as_deref(),
+        )?;
+        on_archive(archive_path, files)?;
+    }
+
+    Ok(())
+}
+
 /// File at archive_path is opened for reading, example: "archive.tar.gz"
 /// formats contains each format necessary for decompression, example: [Gz, Tar] (in decompression order)
 pub fn list_archive_contents(
     archive_path: &Path,
     formats: Vec<CompressionFormat>,
-    list_options: ListOptions,
     question_policy: QuestionPolicy,
     password: Option<&[u8]>,
-) -> Result<()> {
+    temp_storage: &TempStorage,
+    zstd_dict: Option<&[u8]>,
+) -> Result<Box<dyn Iterator<Item = Result<FileInArchive>>>> {
+    if formats[0] == Tar
+        && let Some(files) = archive::tar_index::read_index(archive_path)?
+    {
+        return Ok(Box::new(files.into_iter().map(Ok)));
+    }
+
+    // With a seek table, tar can jump over the contents of the entries instead of decompressing them
+    if let &[Tar, Zstd] = formats.as_slice()
+        && let Some(reader) = open_seekable_zstd(fs::File::open(archive_path)?, zstd_dict)?
+    {
+        return Ok(Box::new(crate::archive::tar::list_archive_with_seek(
+            tar::Archive::new(reader),
+        )?));
+    }
+
     let reader = fs::File::open(archive_path)?;
 
     // Zip archives are special, because they require io::Seek, so it requires its logic separated
     // from decoder chaining.
     //
-    // This is the only case where we can read and unpack it directly, without having to do
-    // in-memory decompression/copying first.
-    //
-    // Any other Zip decompression done can take up the whole RAM and freeze ouch.
+    // This is the only case where we can read and unpack it directly, without having to buffer
+    // the decompressed archive first.
     if let &[Zip] = formats.as_slice() {
         let zip_archive = zip::ZipArchive::new(reader)?;
         let files = crate::archive::zip::list_archive(zip_archive, password);
-        list::list_files(archive_path, files, list_options)?;
-        return Ok(());
+        return Ok(Box::new(files));
     }
 
     // Will be used in decoder chaining
     let reader = BufReader::with_capacity(BUFFER_CAPACITY, reader);
     let mut reader: Box<dyn Read + Send> = Box::new(reader);
 
-    // Grab previous decoder and wrap it inside of a new one
-    let chain_reader_decoder =
-        |format: CompressionFormat, decoder: Box<dyn Read + Send>| -> Result<Box<dyn Read + Send>> {
-            let decoder: Box<dyn Read + Send> = match format {
-                Gzip => Box::new(flate2::read::MultiGzDecoder::new(decoder)),
-                Bzip => Box::new(bzip2::read::MultiBzDecoder::new(decoder)),
-                Bzip3 => {
-                    #[cfg(not(feature = "bzip3"))]
-                    return Err(crate::Error::bzip3_no_support());
-
-                    #[cfg(feature = "bzip3")]
-                    Box::new(bzip3::read::Bz3Decoder::new(decoder).unwrap())
-                }
-                Lz4 => Box::new(MultiFrameLz4Decoder::new(decoder)),
-                Lzma => Box::new(lzma_rust2::LzmaReader::new_mem_limit(decoder, u32::MAX, None)?),
-                Xz => Box::new(lzma_rust2::XzReader::new(decoder, true)),
-                Lzip => Box::new(lzma_rust2::LzipReader::new(decoder)?),
-                Snappy => Box::new(snap::read::FrameDecoder::new(decoder)),
-                Zstd => Box::new(zstd::stream::Decoder::new(decoder)?),
-                Brotli => Box::new(brotli::Decompressor::new(decoder, BUFFER_CAPACITY)),
-                Tar | Zip | Rar | SevenZip => unreachable!("should be treated by caller"),
-            };
-            Ok(decoder)
-        };
-
     let mut misplaced_archive_format = None;
     for &format in formats.iter().skip(1).rev() {
         if format.is_archive_format() {
             misplaced_archive_format = Some(format);
             break;
         }
-        reader = chain_reader_decoder(format, reader)?;
+        reader = chain_reader_decoder(format, reader, zstd_dict)?;
     }
 
     let archive_format = misplaced_archive_format.unwrap_or(formats[0]);
@@ -82,30 +174,27 @@ pub fn list_archive_contents(
         Tar => Box::new(crate::archive::tar::list_archive(tar::Archive::new(reader))?),
         Zip => {
             if formats.len() > 1 {
-                // Make thread own locks to keep output messages adjacent
-                let _locks = lock_and_flush_output_stdio();
-
-                warn_user_about_loading_zip_in_memory();
-                if !user_wants_to_continue(archive_path, question_policy, QuestionAction::Decompression)? {
-                    return Ok(());
-                }
+                warn_user_about_buffering_archive(Zip, temp_storage);
             }
 
-            let mut vec = vec![];
-            io::copy(&mut reader, &mut vec)?;
-            let zip_archive = zip::ZipArchive::new(io::Cursor::new(vec))?;
+            let zip_archive = zip::ZipArchive::new(temp_storage.buffer(reader)?)?;
 
             Box::new(crate::archive::zip::list_archive(zip_archive, password))
         }
         #[cfg(feature = "unrar")]
         Rar => {
+            let mut temp_file = None;
             if formats.len() > 1 {
-                let mut temp_file = tempfile::NamedTempFile::new()?;
-                io::copy(&mut reader, &mut temp_file)?;
-                Box::new(crate::archive::rar::list_archive(temp_file.path(), password)?)
-            } else {
-                Box::new(crate::archive::rar::list_archive(archive_path, password)?)
+                let temp_file = temp_file.insert(temp_storage.named_temp_file()?);
+                io::copy(&mut reader, temp_file)?;
             }
+            let path = temp_file.as_ref().map_or(archive_path, |file| file.path());
+
+            // Encrypted headers can't be listed without the password
+            let files = with_archive_password(archive_path, password, question_policy, |password| {
+                crate::archive::rar::list_archive(path, password)?.collect::<Result<Vec<_>>>()
+            })?;
+            Box::new(files.into_iter().map(Ok))
         }
         #[cfg(not(feature = "unrar"))]
         Rar => {
@@ -113,21 +202,21 @@ pub fn list_archive_contents(
         }
         SevenZip => {
             if formats.len() > 1 {
-                // Make thread own locks to keep output messages adjacent
-                let locks = lock_and_flush_output_stdio();
-                warn_user_about_loading_zip_in_memory();
-                if !user_wants_to_continue(archive_path, question_policy, QuestionAction::Decompression)? {
-                    return Ok(());
-                }
-                drop(locks);
-
-                let mut vec = vec![];
-                io::copy(&mut reader, &mut vec)?;
+                warn_user_about_buffering_archive(SevenZip, temp_storage);
+                let mut buffer = temp_storage.buffer(reader)?;
 
-                Box::new(archive::sevenz::list_archive(io::Cursor::new(vec), password)?)
+                // Encrypted headers can't be listed without the password
+                let files = with_archive_password(archive_path, password, question_policy, |password| {
+                    buffer.rewind()?;
+                    archive::sevenz::list_archive(&mut buffer, password)?.collect::<Result<Vec<_>>>()
+                })?;
+                Box::new(files.into_iter().map(Ok))
             } else {
                 // If it's the only format, we can read the archive directly.
-                Box::new(archive::sevenz::list_archive(fs::File::open(archive_path)?, password)?)
+                let files = with_archive_password(archive_path, password, question_policy, |password| {
+                    archive::sevenz::list_archive(fs::File::open(archive_path)?, password)?.collect::<Result<Vec<_>>>()
+                })?;
+                Box::new(files.into_iter().map(Ok))
             }
         }
         Gzip | Bzip | Bzip3 | Lz4 | Lzma | Xz | Lzip | Snappy | Zstd | Brotli => {
@@ -135,5 +224,5 @@ pub fn list_archive_contents(
         }
     };
 
-    list::list_files(archive_path, files, list_options)
+    Ok(files)
 }
@@ -1,27 +1,51 @@
 //! Receive command from the cli and call the respective function for that command.
 
-mod compress;
-mod decompress;
-mod list;
+pub mod add;
+pub mod compress;
+pub mod decompress;
+pub mod list;
+pub mod test;
+pub mod train_dict;
+
+use std::{
+    env,
+    io::{self, BufReader, IsTerminal, Read, Seek, Write},
+    num::NonZeroU64,
+    path::Path,
+};
 
 use bstr::ByteSlice;
-use decompress::DecompressOptions;
-use rayon::prelude::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
-use utils::colors;
+use gzp::par::compress::{ParCompress, ParCompressBuilder};
 
 use crate::{
-    CliArgs, INITIAL_CURRENT_DIR, QuestionPolicy, Result,
-    check::{self, CheckFileSignatureControlFlow},
+    BUFFER_CAPACITY, CliArgs, QuestionPolicy, Result,
     cli::Subcommand,
-    commands::{compress::compress_files, decompress::decompress_file, list::list_archive_contents},
+    commands::{
+        add::{AddOptions, add},
+        compress::{CompressOptions, compress},
+        decompress::{DecompressOptions, DecompressionSummary, decompress},
+        list::{ListArchiveOptions, list_each},
+        test::{TestOptions, test},
+        train_dict::{TrainDictOptions, train_dict},
+    },
     error::{Error, FinalError},
-    extension::{self, parse_format_flag},
+    extension::CompressionFormat::{self, *},
+    format_options::{FormatOptions, parse_level_flags},
     info_accessible,
-    list::ListOptions,
+    list::{ListOptions, ListPrinter},
+    non_archive::{
+        lz4::MultiFrameLz4Decoder,
+        zstd::MultiFrameZstdDecoder,
+        zstd_seekable::{SeekableZstdReader, SeekableZstdWriter},
+    },
     utils::{
-        self, BytesFmt, FileVisibilityPolicy, NoQuotePathFmt, PathFmt, QuestionAction, canonicalize, colors::*,
-        file_size, is_path_stdin,
+        BytesFmt, EntryFilter, FileVisibilityPolicy, PathFmt, PathTransform, Reproducible, TempStorage, TransformRule,
+        ask_for_password,
+        colors::*,
+        is_path_stdout,
+        threads::{logical_thread_count, physical_thread_count},
     },
+    warning,
 };
 
 /// Warn the user that (de)compressing this .zip archive might freeze their system.
@@ -44,11 +68,344 @@ fn warn_user_about_loading_sevenz_in_memory() {
     eprintln!("{}[WARNING]{}: {SEVENZ_IN_MEMORY_LIMITATION_WARNING}", *ORANGE, *RESET);
 }
 
-/// This function checks what command needs to be run and performs A LOT of ahead-of-time checks
-/// to assume everything is OK.
+/// Warn the user that this .zip or .7z archive has to be buffered before reading it, as it's only
+/// available as a stream.
+fn warn_user_about_buffering_archive(format: CompressionFormat, temp_storage: &TempStorage) {
+    let format = match format {
+        Zip => "zip",
+        SevenZip => "7z",
+        _ => unreachable!(),
+    };
+    let dir = temp_storage.dir.clone().unwrap_or_else(env::temp_dir);
+
+    warning!(
+        "The format '.{format}' can't be read as a stream, so the archive is buffered first: up to {} in memory, \
+         the rest in a temporary file in {}",
+        BytesFmt(temp_storage.max_memory).to_string().trim_start(),
+        PathFmt(&dir),
+    );
+}
+
+/// Grab previous decoder and wrap it inside of a new one, used to chain the decoders of an input file.
+///
+/// `zstd_dict` is the dictionary zstd data was compressed with, if any.
 ///
-/// There are a lot of custom errors to give enough error description and explanation.
+/// Archive formats should be treated by the caller.
+fn chain_reader_decoder(
+    format: CompressionFormat,
+    decoder: Box<dyn Read + Send>,
+    zstd_dict: Option<&[u8]>,
+) -> Result<Box<dyn Read + Send>> {
+    let decoder: Box<dyn Read + Send> = match format {
+        Gzip => Box::new(flate2::read::MultiGzDecoder::new(decoder)),
+        Bzip => Box::new(bzip2::read::MultiBzDecoder::new(decoder)),
+        Bzip3 => {
+            #[cfg(not(feature = "bzip3"))]
+            return Err(crate::Error::bzip3_no_support());
+
+            #[cfg(feature = "bzip3")]
+            Box::new(bzip3::read::Bz3Decoder::new(decoder)?)
+        }
+        Lz4 => Box::new(MultiFrameLz4Decoder::new(decoder)),
+        Lzma => Box::new(lzma_rust2::LzmaReader::new_mem_limit(decoder, u32::MAX, None)?),
+        Xz => Box::new(lzma_rust2::XzReader::new(decoder, true)),
+        Lzip => Box::new(lzma_rust2::LzipReader::new(decoder)?),
+        Snappy => Box::new(snap::read::FrameDecoder::new(decoder)),
+        Zstd => {
+            let mut decoder = BufReader::with_capacity(BUFFER_CAPACITY, decoder);
+            check_zstd_dictionary(io::BufRead::fill_buf(&mut decoder)?, zstd_dict)?;
+            Box::new(MultiFrameZstdDecoder::new(decoder, zstd_dict)?)
+        }
+        Brotli => Box::new(brotli::Decompressor::new(decoder, BUFFER_CAPACITY)),
+        Tar | Zip | Rar | SevenZip => unreachable!("should be treated by caller"),
+    };
+    Ok(decoder)
+}
+
+/// Fail with a clear error if the zstd frame at the start of `data` needs a dictionary other than `zstd_dict`.
+///
+/// Frames written without the ID of their dictionary can't be checked, decoding them with the wrong
+/// one fails later as corrupted data.
+fn check_zstd_dictionary(data: &[u8], zstd_dict: Option<&[u8]>) -> Result<()> {
+    let Some(frame_dict_id) = zstd::zstd_safe::get_dict_id_from_frame(data) else {
+        return Ok(());
+    };
+    let dict_id = zstd_dict.and_then(zstd::zstd_safe::get_dict_id_from_dict);
+
+    let error = match (zstd_dict, dict_id) {
+        (None, _) => FinalError::with_title("Cannot decompress zstd data without its dictionary")
+            .detail(format!("The data was compressed with the dictionary {frame_dict_id}"))
+            .hint("Pass the dictionary with --zstd-dict"),
+        (Some(_), Some(dict_id)) if dict_id == frame_dict_id => return Ok(()),
+        (Some(_), dict_id) => FinalError::with_title("Cannot decompress zstd data with this dictionary")
+            .detail(format!("The data was compressed with the dictionary {frame_dict_id}"))
+            .detail(match dict_id {
+                Some(dict_id) => format!("--zstd-dict is the dictionary {dict_id}"),
+                None => "--zstd-dict is a raw dictionary, without an ID".to_owned(),
+            })
+            .hint("Pass the dictionary used to compress it with --zstd-dict"),
+    };
+    Err(error.into())
+}
+
+/// Open the seekable zstd data of `reader`, or `None` if it isn't seekable, e.g. it was compressed by
+/// a single zstd stream.
+fn open_seekable_zstd<R: Read + Seek>(
+    reader: R,
+    zstd_dict: Option<&[u8]>,
+) -> Result<Option<SeekableZstdReader<BufReader<R>>>> {
+    let mut reader = BufReader::with_capacity(BUFFER_CAPACITY, reader);
+    check_zstd_dictionary(io::BufRead::fill_buf(&mut reader)?, zstd_dict)?;
+    Ok(SeekableZstdReader::new(reader, zstd_dict)?)
+}
+
+/// Grab previous encoder and wrap it inside of a new one, used to chain the encoders of an output file.
+///
+/// Archive formats should be treated by the caller.
+fn chain_writer_encoder(
+    format: CompressionFormat,
+    encoder: Box<dyn Write + Send>,
+    level: Option<i16>,
+    format_options: &FormatOptions,
+) -> Result<Box<dyn Write + Send>> {
+    // Out of range levels were already reported by `check::check_compression_levels`
+    let level = level
+        .zip(format.level_range())
+        .map(|(level, range)| level.clamp(*range.start(), *range.end()));
+
+    let encoder: Box<dyn Write + Send> = match format {
+        Gzip => Box::new({
+            // by default, ParCompress uses a default compression level of 3
+            // instead of the regular default that flate2 uses
+            let mut builder = ParCompressBuilder::new()
+                .compression_level(level.map_or_else(Default::default, |l| gzp::Compression::new(l as u32)));
+            if let Some(block_size) = format_options.gzip_block_size {
+                builder = builder.buffer_size(block_size).map_err(invalid_block_size)?;
+            }
+            let parz: ParCompress<gzp::deflate::Gzip, _> = builder
+                .num_threads(logical_thread_count())
+                .expect("gpz: num_threads must be greater than 0")
+                .from_writer(encoder);
+            parz
+        }),
+        Bzip => Box::new(bzip2::write::BzEncoder::new(
+            encoder,
+            level.map_or_else(Default::default, |l| bzip2::Compression::new(l as u32)),
+        )),
+        Bzip3 => {
+            #[cfg(not(feature = "bzip3"))]
+            return Err(crate::Error::bzip3_no_support());
+
+            #[cfg(feature = "bzip3")]
+            Box::new(
+                // Use block size of 16 MiB by default
+                bzip3::write::Bz3Encoder::new(
+                    encoder,
+                    format_options.bzip3_block_size.unwrap_or(16 * 2_usize.pow(20)),
+                )?,
+            )
+        }
+        Lz4 => Box::new(lz4_flex::frame::FrameEncoder::new(encoder).auto_finish()),
+        Lzma => {
+            let mut options = level.map_or_else(Default::default, |l| lzma_rust2::LzmaOptions::with_preset(l as u32));
+            if let Some(dict_size) = format_options.lzma_dict_size {
+                options.dict_size = dict_size;
+            }
+            let writer = lzma_rust2::LzmaWriter::new_use_header(encoder, &options, None)?;
+            Box::new(writer.auto_finish())
+        }
+        Xz => {
+            let mut options = level.map_or_else(Default::default, |l| lzma_rust2::XzOptions::with_preset(l as u32));
+            if let Some(dict_size) = format_options.xz_dict_size {
+                options.lzma_options.dict_size = dict_size;
+            }
+            let block_size = format_options
+                .xz_block_size
+                .unwrap_or(options.lzma_options.dict_size as u64);
+            options.set_block_size(NonZeroU64::new(block_size));
+            // Use up to 256 PHYSICAL cores for compression
+            let writer = lzma_rust2::XzWriterMt::new(encoder, options, physical_thread_count() as u32)?;
+            Box::new(writer.auto_finish())
+        }
+        Lzip => {
+            let mut options = level.map_or_else(Default::default, |l| lzma_rust2::LzipOptions::with_preset(l as u32));
+            if let Some(dict_size) = format_options.lzip_dict_size {
+                options.lzma_options.dict_size = dict_size;
+            }
+            let writer = lzma_rust2::LzipWriter::new(encoder, options);
+            Box::new(writer.auto_finish())
+        }
+        Snappy => Box::new({
+            let mut builder = ParCompressBuilder::new().compression_level(gzp::par::compress::Compression::new(
+                level.map_or_else(Default::default, |l| l as u32),
+            ));
+            if let Some(block_size) = format_options.snappy_block_size {
+                builder = builder.buffer_size(block_size).map_err(invalid_block_size)?;
+            }
+            let parz: ParCompress<gzp::snap::Snap, _> = builder
+                .num_threads(logical_thread_count())
+                .expect("gpz: num_threads must be greater than 0")
+                .from_writer(encoder);
+            parz
+        }),
+        Zstd => {
+            let level = level.map_or(zstd::DEFAULT_COMPRESSION_LEVEL, i32::from);
+            let dict = format_options.zstd_dict.as_deref();
+            if let Some(frame_size) = format_options.zstd_seekable_frame_size {
+                return Ok(Box::new(SeekableZstdWriter::new(
+                    encoder,
+                    frame_size,
+                    level,
+                    dict,
+                    format_options.zstd_long_window_log,
+                )));
+            }
+
+            let mut zstd_encoder =
+                zstd::stream::write::Encoder::with_dictionary(encoder, level, dict.unwrap_or_default())?;
+            // Use all available PHYSICAL cores for compression
+            zstd_encoder.multithread(physical_thread_count() as u32)?;
+            // Same default as the zstd CLI, lets `ouch test` detect corrupted data
+            zstd_encoder.include_checksum(true)?;
+            if let Some(window_log) = format_options.zstd_long_window_log {
+                zstd_encoder.long_distance_matching(true)?;
+                zstd_encoder.window_log(window_log)?;
+            }
+            Box::new(zstd_encoder.auto_finish())
+        }
+        Brotli => {
+            let default_level = 11; // Same as brotli CLI, default to highest compression
+            let level = level.unwrap_or(default_level) as u32;
+            let win_size = format_options.brotli_window_log.unwrap_or(22); // default to 2^22 = 4 MiB window size
+            Box::new(brotli::CompressorWriter::new(encoder, BUFFER_CAPACITY, level, win_size))
+        }
+        Tar | Zip | Rar | SevenZip => unreachable!("should be treated by caller"),
+    };
+    Ok(encoder)
+}
+
+fn invalid_block_size(err: gzp::GzpError) -> FinalError {
+    FinalError::with_title("Invalid block size").detail(err.to_string())
+}
+
+/// Run `read_fn` with a password that can decrypt the archive at `archive_path`, asking the user for
+/// another one while it fails with [`Error::InvalidPassword`].
+///
+/// Fails with a [`FinalError`] if the user can't be asked, because of `--no` or because STDIN isn't a
+/// terminal.
+fn with_archive_password<T>(
+    archive_path: &Path,
+    password: Option<&[u8]>,
+    question_policy: QuestionPolicy,
+    mut read_fn: impl FnMut(Option<&[u8]>) -> Result<T>,
+) -> Result<T> {
+    const MAX_ATTEMPTS: usize = 3;
+
+    let mut password = password.map(ToOwned::to_owned);
+    let mut attempts = 0;
+
+    loop {
+        let reason = match read_fn(password.as_deref()) {
+            Err(Error::InvalidPassword { reason }) => reason,
+            result => return result,
+        };
+
+        let can_ask = question_policy != QuestionPolicy::AlwaysNo && io::stdin().is_terminal();
+        if !can_ask || attempts == MAX_ATTEMPTS {
+            let error = FinalError::with_title(format!("Cannot decrypt {}", PathFmt(archive_path)))
+                .detail(reason)
+                .hint("Pass the password with --password, --password-file or the OUCH_PASSWORD environment variable");
+            return Err(error.into());
+        }
+
+        if password.is_some() {
+            warning!("{reason}");
+        }
+        password = Some(ask_for_password(
+            &format!("Password for {}", PathFmt(archive_path)),
+            false,
+        )?);
+        attempts += 1;
+    }
+}
+
+/// Read `$SOURCE_DATE_EPOCH`, the time reproducible builds should use for files modified after it, see
+/// https://reproducible-builds.org/specs/source-date-epoch/
+fn source_date_epoch() -> Result<Option<u64>> {
+    let Some(value) = env::var_os("SOURCE_DATE_EPOCH").filter(|value| !value.is_empty()) else {
+        return Ok(None);
+    };
+
+    match value.to_str().and_then(|value| value.parse().ok()) {
+        Some(timestamp) => Ok(Some(timestamp)),
+        None => Err(FinalError::with_title("Invalid $SOURCE_DATE_EPOCH")
+            .detail(format!("{value:?} isn't a number of seconds since the Unix epoch"))
+            .into()),
+    }
+}
+
+/// Get the password from `--password` (asking for it if given without a value), `--password-file` or
+/// the `OUCH_PASSWORD` environment variable, in this order.
+///
+/// New archives are only encrypted with a password given explicitly with `--password`, the other
+/// sources only decrypt archives, so an exported `OUCH_PASSWORD` doesn't encrypt every compressed
+/// archive. `add` accepts all of them, as it has to decrypt the 7z archives it rewrites.
+fn resolve_password(args: &CliArgs) -> Result<Option<Vec<u8>>> {
+    let os_str_to_bytes = |str: &std::ffi::OsStr| {
+        <[u8] as ByteSlice>::from_os_str(str)
+            .expect("convert password to bytes failed")
+            .to_vec()
+    };
+
+    let is_encrypting = matches!(args.cmd, Subcommand::Compress { .. });
+
+    let password = match (&args.password, &args.password_file) {
+        (Some(Some(password)), _) => Some(os_str_to_bytes(password)),
+        // Passwords used to create archives are typed twice to avoid locking the files away with a typo
+        (Some(None), _) => Some(ask_for_password("Password", is_encrypting)?),
+        (None, Some(_)) if is_encrypting => {
+            let error = FinalError::with_title("Cannot encrypt with --password-file")
+                .detail("--password-file and $OUCH_PASSWORD are only used to decrypt archives")
+                .hint("Pass the password to encrypt with --password, or --password=VALUE");
+            return Err(error.into());
+        }
+        (None, Some(path)) => {
+            let contents = fs_err::read(path)?;
+            Some(contents.lines().next().unwrap_or_default().to_vec())
+        }
+        (None, None) if is_encrypting => None,
+        (None, None) => env::var_os("OUCH_PASSWORD")
+            .filter(|password| !password.is_empty())
+            .map(|password| os_str_to_bytes(&password)),
+    };
+
+    Ok(password)
+}
+
+/// The level applied to all formats and the levels of specific formats, from `--level`, `--fast` and `--slow`.
+fn compression_levels(
+    levels: &[String],
+    fast: bool,
+    slow: bool,
+) -> Result<(Option<i16>, Vec<(CompressionFormat, i16)>)> {
+    let (level, levels) = parse_level_flags(levels)?;
+    let level = if fast {
+        Some(1) // Lowest level of compression
+    } else if slow {
+        Some(i16::MAX) // Highest level of compression
+    } else {
+        level
+    };
+    Ok((level, levels))
+}
+
+/// This function checks what command needs to be run and calls the respective library function,
+/// which performs A LOT of ahead-of-time checks to assume everything is OK.
+///
+/// Summaries returned by the library are printed here.
 pub fn run(args: CliArgs, question_policy: QuestionPolicy, file_visibility_policy: FileVisibilityPolicy) -> Result<()> {
+    crate::force_lazy_locks_to_load();
+
     if let Some(threads) = args.threads {
         rayon::ThreadPoolBuilder::new()
             .num_threads(threads)
@@ -56,223 +413,277 @@ pub fn run(args: CliArgs, question_policy: QuestionPolicy, file_visibility_polic
             .unwrap();
     }
 
+    let password = resolve_password(&args)?;
+    let zstd_dict = args.zstd_dict.as_ref().map(fs_err::read).transpose()?;
+    let temp_storage = TempStorage {
+        dir: args.temp_dir,
+        max_memory: args.max_memory.saturating_mul(1024 * 1024),
+    };
     match args.cmd {
         Subcommand::Compress {
             files,
             output: output_path,
-            level,
+            level: levels,
             fast,
             slow,
+            options: format_options,
             follow_symlinks,
+            encrypt_headers,
+            index,
+            transform,
+            prefix,
+            reproducible,
+            mtime,
+            // The flags selecting the files to compress are read by `CliArgs::parse_and_validate_args`
+            ..
         } => {
-            // After cleaning, if there are no input files left, exit
-            if files.is_empty() {
-                return Err(FinalError::with_title("No files to compress").into());
-            }
+            let (level, levels) = compression_levels(&levels, fast, slow)?;
 
-            // Formats from path extension, like "file.tar.gz.xz" -> vec![Tar, Gzip, Lzma]
-            let (formats_from_flag, formats) = match args.format {
-                Some(formats) => {
-                    let parsed_formats = parse_format_flag(&formats)?;
-                    (Some(formats), parsed_formats)
-                }
-                None => (None, extension::extensions_from_path(&output_path)?),
-            };
-
-            check::check_invalid_compression_with_non_archive_format(
-                &formats,
-                &output_path,
-                &files,
-                formats_from_flag.as_deref(),
-            )?;
-            check::check_archive_formats_position(&formats, &output_path)?;
-
-            let (output_file, output_path) = match utils::create_file_or_prompt_on_conflict(
-                &output_path,
-                question_policy,
-                QuestionAction::Compression,
-            )? {
-                Some(writer) => writer,
-                None => return Ok(()),
-            };
-
-            let level = if fast {
-                Some(1) // Lowest level of compression
-            } else if slow {
-                Some(i16::MAX) // Highest level of compression
-            } else {
-                level
-            };
-
-            let compress_result = compress_files(
-                files,
-                formats,
-                output_file,
-                &output_path,
+            let options = CompressOptions {
+                format: args.format,
+                level,
+                format_options: FormatOptions {
+                    levels,
+                    zstd_dict,
+                    ..FormatOptions::parse(&format_options)?
+                },
                 follow_symlinks,
                 question_policy,
                 file_visibility_policy,
-                level,
-            );
+                password,
+                encrypt_headers,
+                progress: !args.quiet,
+                index,
+                path_transform: PathTransform {
+                    rules: transform
+                        .iter()
+                        .map(|rule| TransformRule::parse(rule))
+                        .collect::<Result<_>>()?,
+                    prefix,
+                    ..PathTransform::default()
+                },
+                reproducible: if reproducible {
+                    Some(Reproducible {
+                        max_mtime: mtime.map_or_else(source_date_epoch, |mtime| Ok(Some(mtime)))?,
+                    })
+                } else {
+                    None
+                },
+            };
 
-            if let Ok(true) = compress_result {
-                info_accessible!("Output file size: {}", BytesFmt(file_size(&output_path)?));
-                info_accessible!("Successfully compressed to {}", PathFmt(&output_path));
-            } else {
-                // If Ok(false) or Err() occurred, delete incomplete file at `output_path`
-                //
-                // if deleting fails, print an extra alert message pointing
-                // out that we left a possibly CORRUPTED file at `output_path`
-                if utils::remove_file_or_dir(&output_path).is_err() {
-                    eprintln!("{red}FATAL ERROR:\n", red = *colors::RED);
-                    eprintln!("  Ouch failed to delete the file {}", PathFmt(&output_path));
-                    eprintln!("  Please delete it manually.");
-                    eprintln!("  This file is corrupted if compression didn't finished.");
-
-                    if compress_result.is_err() {
-                        eprintln!("  Compression failed for reasons below.");
+            if let Some(summary) = compress(files, &output_path, options)? {
+                match summary.output_size {
+                    Some(output_size) => {
+                        info_accessible!("Output file size: {}", BytesFmt(output_size));
+                        info_accessible!("Successfully compressed to {}", PathFmt(&summary.output_path));
                     }
+                    None => info_accessible!("Successfully compressed to STDOUT"),
                 }
             }
 
-            compress_result.map(|_| ())
+            Ok(())
         }
         Subcommand::Decompress {
             files,
             output_dir,
             remove,
+            stdout,
+            include,
+            exclude,
+            no_smart_unpack,
+            strip_components,
+            flatten,
+            transform,
+            prefix,
         } => {
-            let mut files_output_paths: Vec<_> = vec![];
-            let mut files_extensions: Vec<Vec<_>> = vec![];
-
-            if let Some(format) = args.format {
-                let format = parse_format_flag(&format)?;
-                for path in files.iter() {
-                    let file_name = path.file_name().ok_or_else(|| Error::Custom {
-                        reason: FinalError::with_title(format!("{} does not have a file name", PathFmt(path))),
-                    })?;
-                    files_output_paths.push(file_name.into());
-                    files_extensions.push(format.clone());
-                }
-            } else {
-                for path in files.iter() {
-                    let (output_path, mut extensions) = extension::separate_known_extensions_from_name(path)?;
-                    let mut output_path = output_path.to_owned();
-
-                    match check::check_file_signature(path, &extensions, question_policy)? {
-                        CheckFileSignatureControlFlow::HaltProgram => return Ok(()),
-                        CheckFileSignatureControlFlow::Continue => {}
-                        CheckFileSignatureControlFlow::ChangeToDetectedExtension {
-                            new_extension,
-                            new_path_filename,
-                        } => {
-                            extensions = vec![new_extension];
-                            output_path = output_path.with_file_name(new_path_filename);
-                        }
-                    }
+            let options = DecompressOptions {
+                format: args.format,
+                output_dir,
+                question_policy,
+                password,
+                remove,
+                stdout,
+                entry_filter: EntryFilter::new(&include, &exclude)?,
+                path_transform: PathTransform {
+                    strip_components,
+                    flatten,
+                    rules: transform
+                        .iter()
+                        .map(|rule| TransformRule::parse(rule))
+                        .collect::<Result<_>>()?,
+                    prefix,
+                },
+                temp_storage,
+                zstd_dict,
+                progress: !args.quiet,
+                smart_unpack: !no_smart_unpack,
+            };
 
-                    files_output_paths.push(output_path);
-                    files_extensions.push(extensions);
-                }
+            for summary in decompress(&files, options)? {
+                print_decompression_summary(&summary);
             }
 
-            check::check_missing_formats_when_decompressing(&files, &files_extensions)?;
+            Ok(())
+        }
+        Subcommand::List {
+            archives: files,
+            tree,
+            long,
+            output_format,
+        } => {
+            let options = ListArchiveOptions {
+                format: args.format,
+                question_policy,
+                password,
+                temp_storage,
+                zstd_dict,
+            };
 
-            // The directory that will contain the output files
-            // We default to the current directory if the user didn't specify an output directory with --dir
-            let output_dir = if let Some(dir) = output_dir {
-                utils::create_dir_if_non_existent(&dir)?;
-                // If not canonicalized, strip_prefix won't work and logs will break
-                // Led to bugs when output_dir was a symlink
-                canonicalize(&dir)?
-            } else {
-                INITIAL_CURRENT_DIR.clone()
+            let list_options = ListOptions {
+                tree,
+                quiet: args.quiet,
+                long,
+                output_format: output_format.into(),
             };
 
-            files
-                .par_iter()
-                .zip(files_extensions)
-                .zip(files_output_paths)
-                .try_for_each(|((input_path, formats), file_name)| {
-                    // Path used by single file format archives
-                    let output_file_path = if is_path_stdin(&file_name) {
-                        output_dir.join("ouch-output")
-                    } else {
-                        output_dir.join(file_name)
-                    };
-
-                    decompress_file(DecompressOptions {
-                        input_file_path: input_path,
-                        formats,
-                        output_dir: &output_dir,
-                        output_file_path,
-                        question_policy,
-                        password: args.password.as_deref().map(|str| {
-                            <[u8] as ByteSlice>::from_os_str(str).expect("convert password to bytes failed")
-                        }),
-                        remove,
-                    })
-                    .map_err(|err| match err {
-                        Error::IoError { reason } => Error::Custom {
-                            reason: FinalError::with_title(format!(
-                                "Failed to decompress {}",
-                                NoQuotePathFmt(input_path)
-                            ))
-                            .detail(reason),
-                        },
-                        other => other,
-                    })
-                })
+            let mut printer = ListPrinter::new(list_options);
+            list_each(&files, options, |archive_path, files| {
+                printer.print(&archive_path, files)
+            })?;
+            printer.finish()
+        }
+        Subcommand::Add {
+            archive,
+            files,
+            follow_symlinks,
+            level: levels,
+            fast,
+            slow,
+            options: format_options,
+        } => {
+            let (level, levels) = compression_levels(&levels, fast, slow)?;
+
+            let options = AddOptions {
+                format: args.format,
+                level,
+                format_options: FormatOptions {
+                    levels,
+                    zstd_dict,
+                    ..FormatOptions::parse(&format_options)?
+                },
+                follow_symlinks,
+                question_policy,
+                password,
+                file_visibility_policy,
+                temp_dir: temp_storage.dir,
+            };
+
+            add(files, &archive, options)?;
+            info_accessible!("Successfully added files to {}", PathFmt(&archive));
+
+            Ok(())
         }
-        Subcommand::List { archives: files, tree } => {
-            let mut formats = vec![];
+        Subcommand::Test { files } => {
+            let options = TestOptions {
+                format: args.format,
+                question_policy,
+                password,
+                temp_storage,
+                zstd_dict,
+            };
+
+            let mut error = FinalError::with_title("Found corrupted files");
+            let mut found_corruption = false;
 
-            if let Some(format) = args.format {
-                let format = parse_format_flag(&format)?;
-                for _ in 0..files.len() {
-                    formats.push(format.clone());
+            for report in test(&files, options)? {
+                if report.is_ok() {
+                    info_accessible!(
+                        "No errors found in {}, entries tested: {}",
+                        PathFmt(&report.input_path),
+                        report.entries_tested,
+                    );
+                    continue;
                 }
-            } else {
-                for path in files.iter() {
-                    let mut extensions = extension::extensions_from_path(path)?;
-
-                    match check::check_file_signature(path, &extensions, question_policy)? {
-                        CheckFileSignatureControlFlow::HaltProgram => return Ok(()),
-                        CheckFileSignatureControlFlow::Continue => {}
-                        CheckFileSignatureControlFlow::ChangeToDetectedExtension { new_extension, .. } => {
-                            extensions = vec![new_extension]
-                        }
-                    }
 
-                    formats.push(extensions);
+                found_corruption = true;
+                for failure in report.failures {
+                    let reason = FinalError::from(failure.error).to_single_line();
+                    error = error.detail(match failure.entry {
+                        Some(entry) => format!("{} in {}: {reason}", PathFmt(&entry), PathFmt(&report.input_path)),
+                        None => format!("{}: {reason}", PathFmt(&report.input_path)),
+                    });
                 }
             }
 
-            // Ensure we were not told to list the content of a non-archive compressed file
-            check::check_for_non_archive_formats(&files, &formats)?;
+            if found_corruption {
+                return Err(error.into());
+            }
 
-            let list_options = ListOptions {
-                tree,
-                quiet: args.quiet,
+            Ok(())
+        }
+        Subcommand::TrainDict {
+            samples,
+            output,
+            max_size,
+        } => {
+            let options = TrainDictOptions {
+                max_size,
+                question_policy,
+                file_visibility_policy,
             };
 
-            for (i, (archive_path, formats)) in files.iter().zip(formats).enumerate() {
-                if i > 0 && !args.quiet {
-                    println!();
-                }
-                let formats = extension::flatten_compression_formats(&formats);
-                list_archive_contents(
-                    archive_path,
-                    formats,
-                    list_options,
-                    question_policy,
-                    args.password
-                        .as_deref()
-                        .map(|str| <[u8] as ByteSlice>::from_os_str(str).expect("convert password to bytes failed")),
-                )?;
+            if let Some(dictionary) = train_dict(samples, &output, options)? {
+                info_accessible!(
+                    "Trained the dictionary {} on {} samples, size: {}",
+                    dictionary.dictionary_id,
+                    dictionary.samples,
+                    BytesFmt(dictionary.size as u64),
+                );
+                info_accessible!(
+                    "Successfully wrote the dictionary to {}",
+                    PathFmt(&dictionary.output_path)
+                );
             }
 
             Ok(())
         }
     }
 }
+
+fn print_decompression_summary(summary: &DecompressionSummary) {
+    match summary {
+        DecompressionSummary::Archive {
+            output_dir,
+            files_unpacked,
+            ..
+        } => {
+            if is_path_stdout(output_dir) {
+                info_accessible!("Successfully decompressed archive to STDOUT");
+            } else {
+                info_accessible!("Successfully decompressed archive to {}", PathFmt(output_dir));
+            }
+            info_accessible!("Files unpacked: {files_unpacked}");
+        }
+        DecompressionSummary::NonArchive {
+            input_path,
+            output_path,
+            input_size,
+            output_size,
+        } => {
+            let output = if is_path_stdout(output_path) {
+                "STDOUT".to_owned()
+            } else {
+                PathFmt(output_path).to_string()
+            };
+
+            match input_size {
+                None => info_accessible!("STDIN decompressed to {output}"),
+                Some(input_size) => {
+                    info_accessible!("File {} decompressed to {output}", PathFmt(input_path));
+                    info_accessible!("Input file size: {}", BytesFmt(*input_size));
+                }
+            }
+            info_accessible!("Output file size: {}", BytesFmt(*output_size));
+        }
+    }
+}
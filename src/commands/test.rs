@@ -0,0 +1,243 @@
+use std::{
+    io::{self, BufReader, Read, Seek},
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+use rayon::prelude::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+
+use crate::{
+    BUFFER_CAPACITY, QuestionPolicy, Result,
+    check::{self, CheckFileSignatureControlFlow},
+    commands::{chain_reader_decoder, warn_user_about_buffering_archive, with_archive_password},
+    error::Error,
+    extension::{self, CompressionFormat::*, Extension, parse_format_flag, split_first_compression_format},
+    utils::{TempStorage, absolutize_paths, io::ReadSeek, is_path_stdin},
+};
+
+/// Options for [`test()`], the defaults match running `ouch test` without flags.
+#[non_exhaustive]
+pub struct TestOptions {
+    /// Formats to use instead of the input path extensions, same syntax as `--format`, e.g. "tar.gz"
+    pub format: Option<String>,
+    pub question_policy: QuestionPolicy,
+    pub password: Option<Vec<u8>>,
+    /// Where to buffer zip, 7z and rar archives that can't be read directly, like `.zip.gz` or STDIN
+    pub temp_storage: TempStorage,
+    /// Dictionary that zstd data was compressed with, if any, see `ouch train-dict`
+    pub zstd_dict: Option<Vec<u8>>,
+}
+
+impl Default for TestOptions {
+    fn default() -> Self {
+        Self {
+            format: None,
+            question_policy: QuestionPolicy::Ask,
+            password: None,
+            temp_storage: TempStorage::default(),
+            zstd_dict: None,
+        }
+    }
+}
+
+/// Integrity check results of a single file, returned by [`test()`]
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct TestReport {
+    pub input_path: PathBuf,
+    /// Number of archive entries read, single file formats count as one entry
+    pub entries_tested: u64,
+    pub failures: Vec<TestFailure>,
+}
+
+impl TestReport {
+    /// No corruption was found
+    pub fn is_ok(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// A corruption found while testing a file
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct TestFailure {
+    /// The entry that failed, `None` if the failure isn't specific to an entry, e.g. a broken archive header
+    pub entry: Option<PathBuf>,
+    pub error: Error,
+}
+
+/// Check the integrity of each of `files` without extracting them, detecting formats from extensions
+/// and file signatures (or `options.format`), `"-"` reads from STDIN.
+///
+/// Every entry is decompressed and discarded, so all checksums available in the formats (like CRC-32
+/// for zip and 7z entries, or the gzip trailer) are verified.
+///
+/// Corruptions are reported in [`TestReport::failures`] instead of returned as errors.
+///
+/// Not thread-safe with [`compress`](crate::compress) and [`add`](crate::add), which change the current
+/// directory that relative paths are resolved against.
+pub fn test(files: &[PathBuf], options: TestOptions) -> Result<Vec<TestReport>> {
+    crate::force_lazy_locks_to_load();
+
+    let files = absolutize_paths(files)?;
+    let mut files_extensions: Vec<Vec<_>> = vec![];
+
+    if let Some(format) = &options.format {
+        let format = parse_format_flag(format)?;
+        for _ in 0..files.len() {
+            files_extensions.push(format.clone());
+        }
+    } else {
+        for path in files.iter() {
+            let mut extensions = extension::extensions_from_path(path)?;
+
+            match check::check_file_signature(path, &extensions, options.question_policy)? {
+                CheckFileSignatureControlFlow::HaltProgram => return Ok(vec![]),
+                CheckFileSignatureControlFlow::Continue => {}
+                CheckFileSignatureControlFlow::ChangeToDetectedExtension { new_extension, .. } => {
+                    extensions = vec![new_extension]
+                }
+            }
+
+            files_extensions.push(extensions);
+        }
+    }
+
+    check::check_missing_formats_when_decompressing(&files, &files_extensions)?;
+
+    files
+        .par_iter()
+        .zip(files_extensions)
+        .map(|(input_path, formats)| {
+            test_file(
+                input_path,
+                &formats,
+                options.question_policy,
+                options.password.as_deref(),
+                &options.temp_storage,
+                options.zstd_dict.as_deref(),
+            )
+        })
+        .collect()
+}
+
+/// Decompress every entry of a file into a sink, collecting the failures.
+///
+fn test_file(
+    input_path: &Path,
+    formats: &[Extension],
+    question_policy: QuestionPolicy,
+    password: Option<&[u8]>,
+    temp_storage: &TempStorage,
+    zstd_dict: Option<&[u8]>,
+) -> Result<TestReport> {
+    let input_is_stdin = is_path_stdin(input_path);
+    let (first_extension, extensions) = split_first_compression_format(formats);
+
+    let mut entries_tested = 0;
+    let mut failures = vec![];
+
+    let mut on_entry = |path: &Path, result: Result<u64>| {
+        entries_tested += 1;
+        if let Err(error) = result {
+            failures.push(TestFailure {
+                entry: Some(path.to_owned()),
+                error,
+            });
+        }
+    };
+
+    let create_decoder_up_to_first_extension = || -> Result<Box<dyn Read + Send>> {
+        let mut reader: Box<dyn Read + Send> = if input_is_stdin {
+            Box::new(io::stdin())
+        } else {
+            Box::new(BufReader::with_capacity(BUFFER_CAPACITY, fs::File::open(input_path)?))
+        };
+
+        for format in extensions.iter().rev() {
+            reader = chain_reader_decoder(*format, reader, zstd_dict)?;
+        }
+
+        Ok(reader)
+    };
+
+    let result = match first_extension {
+        Gzip | Bzip | Bzip3 | Lz4 | Lzma | Xz | Lzip | Snappy | Zstd | Brotli => create_decoder_up_to_first_extension()
+            .and_then(|reader| chain_reader_decoder(first_extension, reader, zstd_dict))
+            .and_then(|mut reader| {
+                let size = io::copy(&mut reader, &mut io::sink())?;
+                on_entry(input_path, Ok(size));
+                Ok(())
+            }),
+        Tar => create_decoder_up_to_first_extension()
+            .and_then(|reader| crate::archive::tar::test_archive(reader, &mut on_entry)),
+        Zip | SevenZip => {
+            let should_buffer_archive = input_is_stdin || !extensions.is_empty();
+
+            // due to `io::Seek` being required by `Zip` and `SevenZip`, we might have to
+            // buffer all contents in memory or in a temporary file
+            let reader: Result<Box<dyn ReadSeek>> = if should_buffer_archive {
+                warn_user_about_buffering_archive(first_extension, temp_storage);
+                create_decoder_up_to_first_extension().and_then(|reader| temp_storage.buffer(reader))
+            } else {
+                fs::File::open(input_path)
+                    .map(|file| Box::new(BufReader::with_capacity(BUFFER_CAPACITY, file)) as Box<dyn ReadSeek>)
+                    .map_err(Into::into)
+            };
+
+            reader.and_then(|mut reader| {
+                let password = with_archive_password(input_path, password, question_policy, |password| {
+                    match first_extension {
+                        Zip => crate::archive::zip::check_password(&mut reader, password),
+                        SevenZip => crate::archive::sevenz::check_password(&mut reader, password),
+                        _ => unreachable!(),
+                    }
+                    .map(|()| password.map(ToOwned::to_owned))
+                })?;
+                let password = password.as_deref();
+                reader.rewind()?;
+
+                match first_extension {
+                    Zip => zip::ZipArchive::new(reader)
+                        .map_err(Into::into)
+                        .and_then(|archive| crate::archive::zip::test_archive(archive, password, &mut on_entry)),
+                    SevenZip => crate::archive::sevenz::test_archive(reader, password, &mut on_entry),
+                    _ => unreachable!(),
+                }
+            })
+        }
+        #[cfg(feature = "unrar")]
+        Rar => {
+            let mut test_fn = |archive_path: &Path| {
+                let password = with_archive_password(input_path, password, question_policy, |password| {
+                    crate::archive::rar::check_password(archive_path, password)
+                        .map(|()| password.map(ToOwned::to_owned))
+                })?;
+                crate::archive::rar::test_archive(archive_path, password.as_deref(), &mut on_entry)
+            };
+
+            if formats.len() > 1 || input_is_stdin {
+                temp_storage.named_temp_file().and_then(|mut temp_file| {
+                    io::copy(&mut create_decoder_up_to_first_extension()?, &mut temp_file)?;
+                    test_fn(temp_file.path())
+                })
+            } else {
+                test_fn(input_path)
+            }
+        }
+        #[cfg(not(feature = "unrar"))]
+        Rar => {
+            return Err(crate::Error::rar_no_support());
+        }
+    };
+
+    if let Err(error) = result {
+        failures.push(TestFailure { entry: None, error });
+    }
+
+    Ok(TestReport {
+        input_path: input_path.to_owned(),
+        entries_tested,
+        failures,
+    })
+}
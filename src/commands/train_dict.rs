@@ -0,0 +1,105 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+
+use crate::{
+    QuestionAction, QuestionPolicy, Result,
+    error::FinalError,
+    utils::{self, FileVisibilityPolicy, absolutize_paths},
+};
+
+/// Options for [`train_dict`], the defaults match running `ouch train-dict` without flags.
+#[non_exhaustive]
+pub struct TrainDictOptions {
+    /// Maximum size of the dictionary in bytes, 110 KiB by default like the zstd CLI
+    pub max_size: usize,
+    pub question_policy: QuestionPolicy,
+    pub file_visibility_policy: FileVisibilityPolicy,
+}
+
+impl Default for TrainDictOptions {
+    fn default() -> Self {
+        Self {
+            max_size: 112_640,
+            question_policy: QuestionPolicy::Ask,
+            file_visibility_policy: FileVisibilityPolicy::default(),
+        }
+    }
+}
+
+/// Summary of a trained dictionary, returned by [`train_dict`]
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct TrainedDictionary {
+    /// Where the dictionary was written, might differ from the requested path if the user chose to rename it
+    pub output_path: PathBuf,
+    /// ID stored in the zstd frames compressed with the dictionary
+    pub dictionary_id: u32,
+    /// Size of the dictionary in bytes
+    pub size: usize,
+    /// Number of sample files it was trained on
+    pub samples: usize,
+}
+
+/// Train a zstd dictionary on the files in `samples` (and inside of them, for directories) and write it
+/// to `output_path`.
+///
+/// Each file is a sample, the dictionary helps when compressing many small files that look alike, like
+/// JSON documents with the same fields. zstd recommends samples about 100 times larger than the
+/// dictionary in total.
+///
+/// Returns `Ok(None)` if the operation was cancelled, e.g. the user chose not to overwrite the output.
+///
+/// Not thread-safe with [`compress`](crate::compress) and [`add`](crate::add), which change the current
+/// directory that relative paths are resolved against.
+pub fn train_dict(
+    samples: Vec<PathBuf>,
+    output_path: &Path,
+    options: TrainDictOptions,
+) -> Result<Option<TrainedDictionary>> {
+    crate::force_lazy_locks_to_load();
+
+    let samples = absolutize_paths(&samples)?;
+
+    let mut sample_contents = Vec::new();
+    for path in &samples {
+        for entry in options.file_visibility_policy.build_walker(path) {
+            let entry = entry?;
+            if entry.file_type().is_some_and(|file_type| file_type.is_file()) {
+                sample_contents.push(fs::read(entry.path())?);
+            }
+        }
+    }
+
+    if sample_contents.is_empty() {
+        return Err(FinalError::with_title("No samples to train the dictionary on").into());
+    }
+
+    let dictionary = zstd::dict::from_samples(&sample_contents, options.max_size).map_err(|err| {
+        FinalError::with_title("Failed to train the zstd dictionary")
+            .detail(err.to_string())
+            .hint(format!(
+                "Use more samples, the {} samples add up to {} bytes",
+                sample_contents.len(),
+                sample_contents.iter().map(Vec::len).sum::<usize>(),
+            ))
+            .hint("Or a smaller dictionary with --max-size")
+    })?;
+
+    let Some((mut output_file, output_path)) =
+        utils::create_file_or_prompt_on_conflict(output_path, options.question_policy, QuestionAction::Compression)?
+    else {
+        return Ok(None);
+    };
+    output_file.write_all(&dictionary)?;
+
+    Ok(Some(TrainedDictionary {
+        output_path,
+        dictionary_id: zstd::zstd_safe::get_dict_id_from_dict(&dictionary).map_or(0, |id| id.get()),
+        size: dictionary.len(),
+        samples: sample_contents.len(),
+    }))
+}
@@ -55,6 +55,20 @@ impl Error {
         }
     }
 
+    /// The archive is encrypted but no password was given.
+    pub fn password_required() -> Self {
+        Self::InvalidPassword {
+            reason: "The archive is encrypted, but no password was given".into(),
+        }
+    }
+
+    /// The given password can't decrypt the archive.
+    pub fn wrong_password() -> Self {
+        Self::InvalidPassword {
+            reason: "The provided password is incorrect".into(),
+        }
+    }
+
     /// BZip3 support is disabled for this build.
     pub fn bzip3_no_support() -> Self {
         Self::UnsupportedFormat {
@@ -145,10 +159,20 @@ impl FinalError {
         self
     }
 
+    /// The title followed by the details in a single line, without colors.
+    pub fn to_single_line(&self) -> String {
+        let mut line = self.title.to_string();
+        for detail in &self.details {
+            line.push_str(": ");
+            line.push_str(detail);
+        }
+        line
+    }
+
     /// Adds all supported formats as hints.
     ///
     /// This is what it looks like:
-    /// ```
+    /// ```text
     /// hint: Supported extensions are: tar, zip, bz, bz2, gz, lz4, xz, lzma, lz, sz, zst
     /// hint: Supported aliases are: tgz, tbz, tlz4, txz, tlzma, tsz, tzst, tlz
     /// ```
@@ -234,10 +258,9 @@ impl From<zip::result::ZipError> for Error {
             ZipError::FileNotFound => Self::Custom {
                 reason: FinalError::with_title("Unexpected error in zip archive").detail("File not found"),
             },
+            ZipError::UnsupportedArchive(ZipError::PASSWORD_REQUIRED) => Self::password_required(),
             ZipError::UnsupportedArchive(filename) => Self::UnsupportedZipArchive(filename),
-            ZipError::InvalidPassword => Self::InvalidPassword {
-                reason: "The provided password is incorrect".to_string(),
-            },
+            ZipError::InvalidPassword => Self::wrong_password(),
             _ => Self::Custom {
                 reason: FinalError::with_title("Unexpected error in zip archive").detail(err.to_string()),
             },
@@ -248,16 +271,25 @@ impl From<zip::result::ZipError> for Error {
 #[cfg(feature = "unrar")]
 impl From<unrar::error::UnrarError> for Error {
     fn from(err: unrar::error::UnrarError) -> Self {
-        Self::Custom {
-            reason: FinalError::with_title("Unexpected error in rar archive").detail(format!("{:?}", err.code)),
+        use unrar::error::Code;
+        match err.code {
+            Code::MissingPassword => Self::password_required(),
+            Code::BadPassword => Self::wrong_password(),
+            _ => Self::Custom {
+                reason: FinalError::with_title("Unexpected error in rar archive").detail(format!("{:?}", err.code)),
+            },
         }
     }
 }
 
 impl From<sevenz_rust2::Error> for Error {
     fn from(err: sevenz_rust2::Error) -> Self {
-        Self::SevenzipError {
-            reason: err.to_string(),
+        match err {
+            sevenz_rust2::Error::PasswordRequired => Self::password_required(),
+            sevenz_rust2::Error::MaybeBadPassword(_) => Self::wrong_password(),
+            _ => Self::SevenzipError {
+                reason: err.to_string(),
+            },
         }
     }
 }
@@ -270,6 +302,12 @@ impl From<ignore::Error> for Error {
     }
 }
 
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        io::Error::from(err).into()
+    }
+}
+
 impl From<FinalError> for Error {
     fn from(err: FinalError) -> Self {
         Self::Custom { reason: err }
@@ -1,6 +1,6 @@
 //! Our representation of all the supported compression formats.
 
-use std::{fmt, path::Path};
+use std::{fmt, ops::RangeInclusive, path::Path};
 
 use CompressionFormat::*;
 use bstr::ByteSlice;
@@ -128,6 +128,20 @@ impl CompressionFormat {
         }
     }
 
+    /// Compression levels supported by the format, `None` for formats without levels.
+    pub fn level_range(&self) -> Option<RangeInclusive<i16>> {
+        match self {
+            Gzip | Snappy | Lzma | Xz | Lzip => Some(0..=9),
+            Bzip => Some(1..=9),
+            Brotli => Some(0..=11),
+            // Negative levels are faster than 1, and "ultra" levels from 20 to 22 use a lot more memory
+            Zstd => {
+                Some(zstd::zstd_safe::min_c_level().max(i16::MIN.into()) as i16..=zstd::zstd_safe::max_c_level() as i16)
+            }
+            Tar | Zip | Rar | SevenZip | Bzip3 | Lz4 => None,
+        }
+    }
+
     pub fn as_str(&self) -> &str {
         match self {
             Tar => "tar",
@@ -210,6 +224,38 @@ pub fn parse_format_flag(text: &str) -> Result<Vec<Extension>> {
     Ok(extensions)
 }
 
+/// Separate the levels from a `--format` flag with per-format levels, like "tar.zst:19" or "tar.gz:9.xz:6".
+///
+/// Returns the format flag without the levels, and the level of each format that has one. The level
+/// of an alias, like "tgz:9", is used for its last format.
+pub fn split_format_flag_levels(text: &str) -> Result<(String, Vec<(CompressionFormat, i16)>)> {
+    let mut formats = Vec::new();
+    let mut levels = Vec::new();
+
+    for part in text.split('.') {
+        let Some((extension, level)) = part.split_once(':') else {
+            formats.push(part);
+            continue;
+        };
+
+        let invalid_flag = |reason| Error::InvalidFormatFlag {
+            text: text.to_owned(),
+            reason,
+        };
+        let format = slice_to_extension(extension.as_bytes())
+            .and_then(|extension| extension.compression_formats.last().copied())
+            .ok_or_else(|| invalid_flag(format!("Unsupported extension '{extension}'")))?;
+        let level = level
+            .parse()
+            .map_err(|_| invalid_flag(format!("Invalid level '{level}' for '{extension}'")))?;
+
+        formats.push(extension);
+        levels.push((format, level));
+    }
+
+    Ok((formats.join("."), levels))
+}
+
 /// Extracts extensions from a path.
 ///
 /// Returns both the remaining path and the list of extension objects.
@@ -385,6 +431,23 @@ mod tests {
         assert!(parse_format_flag(".tar.!@#.gz").is_err());
     }
 
+    #[test]
+    fn test_split_format_flag_levels() {
+        assert_eq!(split_format_flag_levels("tar.gz").unwrap(), ("tar.gz".into(), vec![]));
+        assert_eq!(
+            split_format_flag_levels("tar.zst:19").unwrap(),
+            ("tar.zst".into(), vec![(Zstd, 19)])
+        );
+        assert_eq!(
+            split_format_flag_levels("tgz:9.xz:-1").unwrap(),
+            ("tgz.xz".into(), vec![(Gzip, 9), (Xz, -1)])
+        );
+
+        assert!(split_format_flag_levels("tar.zst:").is_err());
+        assert!(split_format_flag_levels("tar.zst:ultra").is_err());
+        assert!(split_format_flag_levels("tar.unknown:9").is_err());
+    }
+
     #[test]
     fn builds_suggestion_correctly() {
         assert_eq!(build_archive_file_suggestion(Path::new("linux.png"), ".tar"), None);
@@ -0,0 +1,279 @@
+//! Tunables of the compression formats, set with `--opt FORMAT.KEY=VALUE`.
+
+use crate::{
+    error::{FinalError, Result},
+    extension::CompressionFormat::{self, *},
+};
+
+/// Largest window log of zstd, its limit is lower on 32-bit platforms.
+#[cfg(target_pointer_width = "32")]
+pub const ZSTD_WINDOW_LOG_MAX: u32 = 30;
+/// Largest window log of zstd, its limit is lower on 32-bit platforms.
+#[cfg(not(target_pointer_width = "32"))]
+pub const ZSTD_WINDOW_LOG_MAX: u32 = 31;
+
+/// Supported options, shown when an option is invalid.
+const SUPPORTED_OPTIONS: &str = "gz.block=SIZE, sz.block=SIZE, zst.long=WINDOW_LOG, zst.seekable=SIZE, xz.dict=SIZE, xz.block=SIZE, \
+                                 lzma.dict=SIZE, lz.dict=SIZE, br.window=WINDOW_LOG, bz3.block=SIZE";
+
+/// Format specific compression tunables, the defaults are the ones `ouch compress` uses without `--opt`.
+///
+/// Sizes are in bytes, and window logs are the base-2 logarithm of the window size in bytes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct FormatOptions {
+    /// Size of the blocks compressed in parallel by gzip, 128 KiB by default
+    pub gzip_block_size: Option<usize>,
+    /// Size of the blocks compressed in parallel by snappy, 128 KiB by default
+    pub snappy_block_size: Option<usize>,
+    /// Enable long distance matching of zstd with this window log, 10 to 31 (30 on 32-bit platforms)
+    pub zstd_long_window_log: Option<u32>,
+    /// Write zstd in the seekable format, with independent frames of this many uncompressed bytes and a
+    /// seek table, so tar entries can be listed and extracted without decompressing everything before them
+    pub zstd_seekable_frame_size: Option<usize>,
+    /// Dictionary size of xz, defined by the level by default
+    pub xz_dict_size: Option<u32>,
+    /// Size of the blocks compressed in parallel by xz, the dictionary size by default
+    pub xz_block_size: Option<u64>,
+    /// Dictionary size of lzma, defined by the level by default
+    pub lzma_dict_size: Option<u32>,
+    /// Dictionary size of lzip, defined by the level by default
+    pub lzip_dict_size: Option<u32>,
+    /// Window log of brotli, 10 to 24, 22 by default
+    pub brotli_window_log: Option<u32>,
+    /// Block size of bzip3, 65 KiB to 511 MiB, 16 MiB by default
+    pub bzip3_block_size: Option<usize>,
+    /// Contents of a dictionary to compress zstd with, like the ones written by `ouch train-dict`. The
+    /// same dictionary is needed to decompress
+    pub zstd_dict: Option<Vec<u8>>,
+    /// Compression levels of specific formats, used instead of the level applied to all formats, the
+    /// last one of a format wins
+    pub levels: Vec<(CompressionFormat, i16)>,
+}
+
+impl FormatOptions {
+    /// Parse a list of options like `["zst.long=27", "xz.dict=64MiB"]`, later options override earlier ones.
+    pub fn parse<S: AsRef<str>>(options: &[S]) -> Result<Self> {
+        let mut format_options = Self::default();
+        for option in options {
+            let option = option.as_ref();
+            format_options.set(option).map_err(|reason| {
+                FinalError::with_title(format!("Failed to parse `--opt {option}`"))
+                    .detail(reason)
+                    .hint(format!("Supported options are: {SUPPORTED_OPTIONS}"))
+                    .hint("Sizes accept the suffixes K, KiB, M, MiB, G and GiB, e.g. 64MiB")
+            })?;
+        }
+        Ok(format_options)
+    }
+
+    /// The level to compress `format` with: its own level if set, or `level` (applied to all formats).
+    pub fn level(&self, format: CompressionFormat, level: Option<i16>) -> Option<i16> {
+        self.levels
+            .iter()
+            .rev()
+            .find(|(level_format, _)| *level_format == format)
+            .map(|(_, level)| *level)
+            .or(level)
+    }
+
+    fn set(&mut self, option: &str) -> Result<(), String> {
+        let (name, value) = option
+            .split_once('=')
+            .ok_or_else(|| "Expected the syntax FORMAT.KEY=VALUE".to_string())?;
+        let (format, key) = name
+            .split_once('.')
+            .ok_or_else(|| "Expected the syntax FORMAT.KEY=VALUE".to_string())?;
+        let format = format_from_name(format).ok_or_else(|| format!("Unsupported format '{format}'"))?;
+
+        match (format, key) {
+            (Gzip, "block") => self.gzip_block_size = Some(parse_size(value, 32 * KIB, u32::MAX as u64)? as usize),
+            (Snappy, "block") => self.snappy_block_size = Some(parse_size(value, 32 * KIB, u32::MAX as u64)? as usize),
+            (Zstd, "long") => self.zstd_long_window_log = Some(parse_number(value, 10, ZSTD_WINDOW_LOG_MAX)?),
+            // The seek table stores the sizes of the frames as 32-bit integers
+            (Zstd, "seekable") => self.zstd_seekable_frame_size = Some(parse_size(value, 4 * KIB, GIB)? as usize),
+            (Xz, "dict") => self.xz_dict_size = Some(parse_dict_size(value, lzma_rust2::DICT_SIZE_MAX)?),
+            (Xz, "block") => self.xz_block_size = Some(parse_size(value, KIB, u64::MAX)?),
+            (Lzma, "dict") => self.lzma_dict_size = Some(parse_dict_size(value, lzma_rust2::DICT_SIZE_MAX)?),
+            // Limit of the lzip format
+            (Lzip, "dict") => self.lzip_dict_size = Some(parse_dict_size(value, 512 * MIB as u32)?),
+            (Brotli, "window") => self.brotli_window_log = Some(parse_number(value, 10, 24)?),
+            (Bzip3, "block") => self.bzip3_block_size = Some(parse_size(value, 65 * KIB, 511 * MIB)? as usize),
+            _ => return Err(format!("Unknown option '{key}' for the format '{}'", format.as_str())),
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse the values of `--level`, either a level applied to all formats, like "9", or the level of a
+/// format, like "xz=9" or "zstd=-5".
+///
+/// Returns the last level applied to all formats and the levels of specific formats.
+pub fn parse_level_flags<S: AsRef<str>>(levels: &[S]) -> Result<(Option<i16>, Vec<(CompressionFormat, i16)>)> {
+    let mut level_for_all = None;
+    let mut format_levels = Vec::new();
+
+    for text in levels {
+        let text = text.as_ref();
+        let error = |detail: String| {
+            FinalError::with_title(format!("Failed to parse `--level {text}`"))
+                .detail(detail)
+                .hint("Pass a level for all formats, like `--level 9`, or for one format, like `--level xz=9`")
+        };
+
+        match text.split_once('=') {
+            Some((format, level)) => {
+                let format = format_from_name(format).ok_or_else(|| error(format!("Unsupported format '{format}'")))?;
+                let level = level.parse().map_err(|_| error(format!("Invalid level '{level}'")))?;
+                format_levels.push((format, level));
+            }
+            None => level_for_all = Some(text.parse().map_err(|_| error(format!("Invalid level '{text}'")))?),
+        }
+    }
+
+    Ok((level_for_all, format_levels))
+}
+
+const KIB: u64 = 1024;
+const MIB: u64 = 1024 * KIB;
+const GIB: u64 = 1024 * MIB;
+
+/// Accept the long names of the formats too, like `zstd.long` for `zst.long`.
+fn format_from_name(name: &str) -> Option<CompressionFormat> {
+    let format = match name {
+        "tar" => Tar,
+        "zip" => Zip,
+        "rar" => Rar,
+        "7z" => SevenZip,
+        "gz" | "gzip" => Gzip,
+        "bz" | "bz2" | "bzip" | "bzip2" => Bzip,
+        "bz3" | "bzip3" => Bzip3,
+        "lz4" => Lz4,
+        "xz" => Xz,
+        "lzma" => Lzma,
+        "lz" | "lzip" => Lzip,
+        "sz" | "snappy" => Snappy,
+        "zst" | "zstd" => Zstd,
+        "br" | "brotli" => Brotli,
+        _ => return None,
+    };
+    Some(format)
+}
+
+fn parse_number(value: &str, min: u32, max: u32) -> Result<u32, String> {
+    match value.parse() {
+        Ok(number) if (min..=max).contains(&number) => Ok(number),
+        _ => Err(format!("Expected a number from {min} to {max}, got '{value}'")),
+    }
+}
+
+fn parse_dict_size(value: &str, max: u32) -> Result<u32, String> {
+    parse_size(value, lzma_rust2::DICT_SIZE_MIN as u64, max as u64).map(|size| size as u32)
+}
+
+/// Parse sizes like "4096", "512K", "64MiB" or "1G", the suffixes are powers of 1024.
+fn parse_size(value: &str, min: u64, max: u64) -> Result<u64, String> {
+    let digits_end = value.find(|ch: char| !ch.is_ascii_digit()).unwrap_or(value.len());
+    let (number, suffix) = value.split_at(digits_end);
+
+    let multiplier = match suffix.to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kib" => KIB,
+        "m" | "mib" => MIB,
+        "g" | "gib" => GIB,
+        _ => return Err(format!("Invalid size '{value}'")),
+    };
+    let size = number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .ok_or_else(|| format!("Invalid size '{value}'"))?;
+
+    if !(min..=max).contains(&size) {
+        return Err(format!(
+            "Expected a size from {} to {}, got '{value}'",
+            format_size(min),
+            format_size(max)
+        ));
+    }
+    Ok(size)
+}
+
+fn format_size(size: u64) -> String {
+    match size {
+        _ if size >= GIB && size % GIB == 0 => format!("{}GiB", size / GIB),
+        _ if size >= MIB && size % MIB == 0 => format!("{}MiB", size / MIB),
+        _ if size >= KIB && size % KIB == 0 => format!("{}KiB", size / KIB),
+        _ => size.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_format_options() {
+        let options = FormatOptions::parse(&[
+            "zstd.long=27",
+            "zst.seekable=1MiB",
+            "xz.dict=64MiB",
+            "br.window=24",
+            "bz3.block=65K",
+        ])
+        .unwrap();
+        assert_eq!(
+            options,
+            FormatOptions {
+                zstd_long_window_log: Some(27),
+                zstd_seekable_frame_size: Some(1024 * 1024),
+                xz_dict_size: Some(64 * 1024 * 1024),
+                brotli_window_log: Some(24),
+                bzip3_block_size: Some(65 * 1024),
+                ..FormatOptions::default()
+            }
+        );
+
+        // Later options override earlier ones
+        let options = FormatOptions::parse(&["gz.block=1M", "gzip.block=256k"]).unwrap();
+        assert_eq!(options.gzip_block_size, Some(256 * 1024));
+    }
+
+    #[test]
+    fn test_parse_level_flags() {
+        let (level, levels) = parse_level_flags(&["3", "zstd=-5", "xz=9", "zst=19"]).unwrap();
+        assert_eq!(level, Some(3));
+
+        let options = FormatOptions {
+            levels,
+            ..FormatOptions::default()
+        };
+        assert_eq!(options.level(Zstd, level), Some(19));
+        assert_eq!(options.level(Xz, level), Some(9));
+        assert_eq!(options.level(Gzip, level), Some(3));
+        assert_eq!(options.level(Gzip, None), None);
+
+        assert!(parse_level_flags(&["ultra"]).is_err());
+        assert!(parse_level_flags(&["foo=9"]).is_err());
+        assert!(parse_level_flags(&["xz=high"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_invalid_format_options() {
+        for option in [
+            "zstd.long",
+            "zstd=27",
+            "zstd.window=27",
+            "foo.long=27",
+            "tar.block=1M",
+            "zstd.long=32",
+            "br.window=big",
+            "bz3.block=1GiB",
+            "xz.dict=64MB",
+            "lz.dict=1G",
+        ] {
+            assert!(FormatOptions::parse(&[option]).is_err(), "{option} should be invalid");
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! Ouch, the Obvious Unified Compression Helper, as a library.
+//!
+//! The `ouch` binary is a thin wrapper around the functions exported here, so you get the same
+//! format detection, checks and error messages without shelling out:
+//!
+//! - [`compress`] bundles/compresses files into an output file.
+//! - [`add`] appends files to existing archives.
+//! - [`decompress`] unpacks archives and decompresses single-file formats.
+//! - [`list`] reads the entries of archives, or [`list_each`] to go through them without collecting them.
+//! - [`test()`] checks the integrity of archives and compressed files without extracting them.
+//! - [`train_dict`] trains zstd dictionaries, for the `zstd_dict` fields of the other options.
+//!
+//! Results are returned as structured summaries instead of being printed, but progress logs (like
+//! `[INFO] Compressing "file"`) are still sent to STDERR, use [`set_log_display_level`] to silence them.
+//!
+//! The options are built from their defaults, as new fields can be added to them:
+//!
+//! ```no_run
+//! let mut options = ouch::CompressOptions::default();
+//! options.level = Some(19);
+//! ```
+//!
+//! [`compress`] and [`add`] change the current directory of the process while they walk the input
+//! files, and every function resolves relative paths against it, so they must not run concurrently
+//! with each other or with other code relying on the current directory.
+//!
+//! ```no_run
+//! use std::path::{Path, PathBuf};
+//!
+//! let summary = ouch::compress(
+//!     vec![PathBuf::from("src")],
+//!     Path::new("src.tar.zst"),
+//!     ouch::CompressOptions::default(),
+//! )?;
+//!
+//! let listings = ouch::list(&[PathBuf::from("src.tar.zst")], ouch::ListArchiveOptions::default())?;
+//! # Ok::<(), ouch::Error>(())
+//! ```
+
+mod accessible;
+mod archive;
+mod check;
+mod cli;
+mod commands;
+mod error;
+mod extension;
+mod format_options;
+mod list;
+mod non_archive;
+mod utils;
+
+use std::{env, path::PathBuf, sync::LazyLock};
+
+use cli::CliArgs;
+
+use self::utils::logger::{shutdown_logger_and_wait, spawn_logger_thread};
+pub use self::{
+    commands::{
+        add::{AddOptions, add},
+        compress::{CompressOptions, CompressionSummary, compress},
+        decompress::{DecompressOptions, DecompressionSummary, decompress},
+        list::{ArchiveListing, ListArchiveOptions, list, list_each},
+        test::{TestFailure, TestOptions, TestReport, test},
+        train_dict::{TrainDictOptions, TrainedDictionary, train_dict},
+    },
+    error::{Error, FinalError, Result},
+    extension::CompressionFormat,
+    format_options::FormatOptions,
+    list::{FileInArchive, ListFileType},
+    utils::{
+        EntryFilter, FileVisibilityPolicy, PathTransform, QuestionAction, QuestionPolicy, Reproducible, TempStorage,
+        TransformRule, logger::set_log_display_level,
+    },
+};
+
+const BUFFER_CAPACITY: usize = 1024 * 32;
+
+/// Current directory, canonicalized for consistent path comparisons across platforms
+static INITIAL_CURRENT_DIR: LazyLock<PathBuf> = LazyLock::new(|| {
+    let dir = env::current_dir().expect("can't read current directory");
+    utils::canonicalize(&dir).expect("can't canonicalize current directory")
+});
+
+/// Load lazy locks that must be initialized before any work changes the current directory.
+fn force_lazy_locks_to_load() {
+    LazyLock::force(&INITIAL_CURRENT_DIR);
+}
+
+/// Parse the command line and run it, the whole `ouch` binary. Not part of the library API.
+#[doc(hidden)]
+pub fn run_cli() -> Result<()> {
+    spawn_logger_thread();
+    let result =
+        CliArgs::parse_and_validate_args().and_then(|(args, skip_questions_positively, file_visibility_policy)| {
+            commands::run(args, skip_questions_positively, file_visibility_policy)
+        });
+    shutdown_logger_and_wait();
+    result
+}
@@ -4,10 +4,18 @@ use std::{
     fmt,
     io::{BufWriter, Write, stdout},
     path::{Path, PathBuf},
+    time::SystemTime,
 };
 
+use serde::{Serialize, Serializer};
+use time::OffsetDateTime;
+
 use self::tree::Tree;
-use crate::{Result, accessible::is_running_in_accessible_mode, utils::PathFmt};
+use crate::{
+    Result,
+    accessible::is_running_in_accessible_mode,
+    utils::{BytesFmt, PathFmt},
+};
 
 /// Options controlling how archive contents should be listed
 #[derive(Debug, Clone, Copy)]
@@ -17,49 +25,300 @@ pub struct ListOptions {
 
     /// Whether to suppress extra output like symlink targets (for scripting)
     pub quiet: bool,
+
+    /// Whether to show permissions, sizes and modification times, like `ls -l`
+    pub long: bool,
+
+    /// Text for humans or JSON for scripts
+    pub output_format: OutputFormat,
+}
+
+/// How archive contents are printed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    /// A single JSON array, with a document per archive
+    Json,
+    /// Newline-delimited JSON, an archive header line followed by a line per entry, for each archive
+    Ndjson,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
 pub enum ListFileType {
     File,
     Directory,
-    Symlink { target: PathBuf },
-    Hardlink { target: PathBuf },
+    Symlink {
+        #[serde(serialize_with = "serialize_path_lossy")]
+        target: PathBuf,
+    },
+    Hardlink {
+        #[serde(serialize_with = "serialize_path_lossy")]
+        target: PathBuf,
+    },
 }
 
-/// Represents a single file in an archive, used in `list::list_files()`
-#[derive(Debug, Clone)]
+/// Represents a single file in an archive, returned by [`list`](crate::list())
+///
+/// Serializes to a flat JSON object, with the file type in `"type"` and the modification time in
+/// seconds since the Unix epoch.
+#[derive(Debug, Clone, Serialize)]
 pub struct FileInArchive {
     /// The file path
+    #[serde(serialize_with = "serialize_path_lossy")]
     pub path: PathBuf,
 
     /// The type of file
+    #[serde(flatten)]
     pub file_type: ListFileType,
+
+    /// Uncompressed size in bytes
+    pub size: Option<u64>,
+
+    /// Size of the compressed data in bytes, only known if the format compresses entries separately
+    pub compressed_size: Option<u64>,
+
+    /// Last modification time
+    #[serde(serialize_with = "serialize_unix_timestamp")]
+    pub modified: Option<SystemTime>,
+
+    /// Unix file mode, with the permission bits
+    pub mode: Option<u32>,
+
+    /// Whether the entry data is encrypted
+    pub encrypted: bool,
 }
 
-/// Actually print the files
-/// Returns an Error, if one of the files can't be read
-pub fn list_files(
-    archive: &Path,
-    files: impl IntoIterator<Item = Result<FileInArchive>>,
+/// Prints the contents of archives one after the other.
+///
+/// JSON documents are only printed by [`ListPrinter::finish`], in a single array.
+pub struct ListPrinter {
     list_options: ListOptions,
-) -> Result<()> {
-    let mut out = BufWriter::new(stdout().lock());
+    archive_count: usize,
+    json_documents: Vec<serde_json::Value>,
+}
 
-    if !list_options.quiet {
-        let _ = writeln!(out, "Archive: {}", PathFmt(archive));
+impl ListPrinter {
+    pub fn new(list_options: ListOptions) -> Self {
+        Self {
+            list_options,
+            archive_count: 0,
+            json_documents: vec![],
+        }
     }
 
-    if list_options.tree {
-        let tree = files.into_iter().collect::<Result<Tree>>()?;
-        tree.print(&mut out);
+    /// Print the `files` of `archive`, returns an Error if one of them can't be read
+    pub fn print(&mut self, archive: &Path, files: impl IntoIterator<Item = Result<FileInArchive>>) -> Result<()> {
+        let list_options = self.list_options;
+        let mut out = BufWriter::new(stdout().lock());
+
+        self.archive_count += 1;
+
+        if list_options.output_format != OutputFormat::Text {
+            let files = files.into_iter().collect::<Result<Vec<_>>>()?;
+            let (header, entries) = json_listing(archive, files, list_options.tree)?;
+
+            if list_options.output_format == OutputFormat::Ndjson {
+                serde_json::to_writer(&mut out, &serde_json::json!({ "archive": header }))?;
+                writeln!(out)?;
+                for entry in entries {
+                    serde_json::to_writer(&mut out, &entry)?;
+                    writeln!(out)?;
+                }
+            } else {
+                self.json_documents
+                    .push(serde_json::json!({ "archive": header, "entries": entries }));
+            }
+            return Ok(());
+        }
+
+        if !list_options.quiet {
+            if self.archive_count > 1 {
+                let _ = writeln!(out);
+            }
+            let _ = writeln!(out, "Archive: {}", PathFmt(archive));
+        }
+
+        if list_options.tree {
+            let tree = files.into_iter().collect::<Result<Tree>>()?;
+            tree.print(&mut out);
+        } else if list_options.long {
+            let mut totals = Totals::default();
+            for file in files {
+                let file = file?;
+                totals.add(&file);
+                print_long_entry(&mut out, &file, list_options.quiet);
+            }
+            if !list_options.quiet {
+                totals.print(&mut out);
+            }
+        } else {
+            for file in files {
+                let FileInArchive { path, file_type, .. } = file?;
+                print_entry(&mut out, path.display(), &file_type, list_options.quiet);
+            }
+        }
+        Ok(())
+    }
+
+    /// Print the JSON array of the archives, if the output format is JSON
+    pub fn finish(self) -> Result<()> {
+        if self.list_options.output_format == OutputFormat::Json {
+            let mut out = BufWriter::new(stdout().lock());
+            serde_json::to_writer_pretty(&mut out, &self.json_documents)?;
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+}
+
+/// The archive header and the entries of an archive as JSON, entries are nested by directories with `tree`
+fn json_listing(
+    archive: &Path,
+    files: Vec<FileInArchive>,
+    tree: bool,
+) -> Result<(serde_json::Value, Vec<serde_json::Value>)> {
+    let header = serde_json::json!({
+        "path": archive.to_string_lossy(),
+        "entry_count": files.len(),
+    });
+
+    let entries = if tree {
+        files.into_iter().collect::<Tree>().to_json()?
     } else {
-        for file in files {
-            let FileInArchive { path, file_type } = file?;
-            print_entry(&mut out, path.display(), &file_type, list_options.quiet);
+        files
+            .iter()
+            .map(serde_json::to_value)
+            .collect::<serde_json::Result<Vec<_>>>()?
+    };
+
+    Ok((header, entries))
+}
+
+fn serialize_path_lossy<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&path.to_string_lossy())
+}
+
+fn serialize_unix_timestamp<S: Serializer>(time: &Option<SystemTime>, serializer: S) -> Result<S::Ok, S::Error> {
+    match time {
+        Some(time) => serializer.serialize_i64(OffsetDateTime::from(*time).unix_timestamp()),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Sums of the sizes shown in the footer of the long listing
+#[derive(Default)]
+struct Totals {
+    files: u64,
+    directories: u64,
+    size: u64,
+    /// Both sizes of the entries whose compressed size is known, to calculate the ratio
+    compressed: Option<(u64, u64)>,
+}
+
+impl Totals {
+    fn add(&mut self, file: &FileInArchive) {
+        match file.file_type {
+            ListFileType::Directory => self.directories += 1,
+            _ => self.files += 1,
+        }
+        self.size += file.size.unwrap_or(0);
+
+        if let (Some(size), Some(compressed_size)) = (file.size, file.compressed_size) {
+            let (total_size, total_compressed_size) = self.compressed.get_or_insert((0, 0));
+            *total_size += size;
+            *total_compressed_size += compressed_size;
+        }
+    }
+
+    fn print(&self, out: &mut impl Write) {
+        let _ = write!(
+            out,
+            "Total: {} files, {} directories, {} uncompressed",
+            self.files,
+            self.directories,
+            BytesFmt(self.size).to_string().trim_start(),
+        );
+        if let Some((size, compressed_size)) = self.compressed {
+            let _ = write!(
+                out,
+                ", {} compressed ({})",
+                BytesFmt(compressed_size).to_string().trim_start(),
+                RatioFmt(Some(size), Some(compressed_size)).to_string().trim_start(),
+            );
+        }
+        let _ = writeln!(out);
+    }
+}
+
+/// Print an entry with its permissions, size, compressed size, ratio and modification time,
+/// unknown values are shown as `-`. Encrypted entries are marked with a `*` before the name
+fn print_long_entry(out: &mut impl Write, file: &FileInArchive, quiet: bool) {
+    let size = file.size.map(|size| BytesFmt(size).to_string());
+    let compressed_size = file.compressed_size.map(|size| BytesFmt(size).to_string());
+    let modified = file.modified.map(|time| {
+        let time = OffsetDateTime::from(time);
+        format!(
+            "{:04}-{:02}-{:02} {:02}:{:02}",
+            time.year(),
+            time.month() as u8,
+            time.day(),
+            time.hour(),
+            time.minute()
+        )
+    });
+
+    let _ = write!(
+        out,
+        "{} {:>10} {:>10} {} {:>16} {}",
+        ModeFmt(&file.file_type, file.mode),
+        size.as_deref().unwrap_or("-"),
+        compressed_size.as_deref().unwrap_or("-"),
+        RatioFmt(file.size, file.compressed_size),
+        modified.as_deref().unwrap_or("-"),
+        if file.encrypted { '*' } else { ' ' },
+    );
+    print_entry(out, file.path.display(), &file.file_type, quiet);
+}
+
+/// Unix-like permissions string, e.g. `drwxr-xr-x`, with `?` for unknown permissions
+struct ModeFmt<'a>(&'a ListFileType, Option<u32>);
+
+impl fmt::Display for ModeFmt<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let file_type = match self.0 {
+            ListFileType::File => '-',
+            ListFileType::Directory => 'd',
+            ListFileType::Symlink { .. } => 'l',
+            ListFileType::Hardlink { .. } => 'h',
+        };
+        write!(f, "{file_type}")?;
+
+        for (i, permission) in "rwxrwxrwx".chars().enumerate() {
+            let character = match self.1 {
+                Some(mode) if mode & (0o400 >> i) != 0 => permission,
+                Some(_) => '-',
+                None => '?',
+            };
+            write!(f, "{character}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Compressed size as a percentage of the uncompressed size, `-` if unknown
+struct RatioFmt(Option<u64>, Option<u64>);
+
+impl fmt::Display for RatioFmt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.0, self.1) {
+            (Some(size), Some(compressed_size)) if size > 0 => {
+                write!(f, "{:>6.1}%", compressed_size as f64 * 100.0 / size as f64)
+            }
+            _ => write!(f, "{:>7}", "-"),
         }
     }
-    Ok(())
 }
 
 /// Print an entry and highlight directories, either by coloring them
@@ -175,6 +434,28 @@ mod tree {
             }
         }
 
+        /// Convert the tree into JSON nodes, which are the serialized entries with a `"name"` and
+        /// the `"children"` of directories. Directories without an entry in the archive only have
+        /// a name, type and children
+        pub fn to_json(&self) -> serde_json::Result<Vec<serde_json::Value>> {
+            self.children
+                .iter()
+                .map(|(name, subtree)| {
+                    let mut node = match &subtree.file {
+                        Some(file) => serde_json::to_value(file)?,
+                        None => serde_json::json!({ "type": "directory" }),
+                    };
+                    if let Some(node) = node.as_object_mut() {
+                        node.insert("name".into(), name.to_string_lossy().into());
+                        if !subtree.children.is_empty() {
+                            node.insert("children".into(), subtree.to_json()?.into());
+                        }
+                    }
+                    Ok(node)
+                })
+                .collect()
+        }
+
         /// Print the file tree using Unicode line characters
         pub fn print(&self, out: &mut impl Write) {
             for (i, (name, subtree)) in self.children.iter().enumerate() {
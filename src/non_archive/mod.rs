@@ -1 +1,3 @@
 pub mod lz4;
+pub mod zstd;
+pub mod zstd_seekable;
@@ -0,0 +1,184 @@
+//! Decoding of zstd data made of several frames, like concatenated `.zst` files or seekable zstd,
+//! where each frame can need a different window size.
+
+use std::io::{self, Read};
+
+use zstd::stream::raw::{DParameter, Decoder, InBuffer, Operation, OutBuffer};
+
+use crate::{BUFFER_CAPACITY, format_options::ZSTD_WINDOW_LOG_MAX};
+
+/// Largest window that zstd decodes without raising its limit, 128 MiB.
+const DEFAULT_WINDOW_LOG_MAX: u32 = 27;
+/// Size of the largest frame header, enough to read the window size of any frame.
+const MAX_FRAME_HEADER_SIZE: usize = 18;
+
+/// Decodes all the zstd frames of `reader`, raising the window limit only for the frames whose header
+/// needs it, like the ones written with `--opt zst.long`.
+///
+/// The limit is set again at the start of each frame, so a small first frame doesn't keep a later one
+/// with a larger window from being decoded.
+pub struct MultiFrameZstdDecoder<R: Read> {
+    reader: R,
+    decoder: Decoder<'static>,
+    /// Data read from `reader`, the part from `pos` isn't decoded yet
+    buffer: Vec<u8>,
+    pos: usize,
+    /// Set before the header of the next frame is decoded
+    is_frame_start: bool,
+}
+
+impl<R: Read> MultiFrameZstdDecoder<R> {
+    /// `dict` is the dictionary the frames were compressed with, if any.
+    pub fn new(reader: R, dict: Option<&[u8]>) -> io::Result<Self> {
+        Ok(Self {
+            reader,
+            decoder: Decoder::with_dictionary(dict.unwrap_or_default())?,
+            buffer: Vec::with_capacity(BUFFER_CAPACITY),
+            pos: 0,
+            is_frame_start: true,
+        })
+    }
+
+    /// Read more data after the part of the buffer that isn't decoded yet, returns `false` at the end
+    /// of the reader.
+    fn fill_buffer(&mut self) -> io::Result<bool> {
+        self.buffer.drain(..self.pos);
+        self.pos = 0;
+
+        let len = self.buffer.len();
+        self.buffer.resize(len + BUFFER_CAPACITY, 0);
+        let result = self.reader.read(&mut self.buffer[len..]);
+        self.buffer.truncate(len + result.as_ref().map_or(0, |read| *read));
+        Ok(result? > 0)
+    }
+
+    /// Set the window limit from the header at the start of the buffer, returns `false` if there's no
+    /// frame left.
+    fn start_frame(&mut self) -> io::Result<bool> {
+        while self.buffer.len() - self.pos < MAX_FRAME_HEADER_SIZE && self.fill_buffer()? {}
+
+        if self.pos == self.buffer.len() {
+            return Ok(false);
+        }
+
+        // Frames that aren't zstd (or are skippable) are left to the decoder to reject (or skip)
+        let window_log = frame_window_log(&self.buffer[self.pos..]).unwrap_or(DEFAULT_WINDOW_LOG_MAX);
+        self.decoder.reinit()?;
+        self.decoder.set_parameter(DParameter::WindowLogMax(
+            window_log.clamp(DEFAULT_WINDOW_LOG_MAX, ZSTD_WINDOW_LOG_MAX),
+        ))?;
+        self.is_frame_start = false;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for MultiFrameZstdDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            if self.is_frame_start && !self.start_frame()? {
+                return Ok(0);
+            }
+
+            // Still called at the end of the reader, to flush the output kept by the decoder
+            let is_end = self.pos == self.buffer.len() && !self.fill_buffer()?;
+
+            let mut input = InBuffer::around(&self.buffer[self.pos..]);
+            let mut output = OutBuffer::around(buf);
+            let remaining = self.decoder.run(&mut input, &mut output)?;
+            self.pos += input.pos();
+            self.is_frame_start = remaining == 0;
+
+            if output.pos() > 0 {
+                return Ok(output.pos());
+            }
+            if is_end && !self.is_frame_start {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "incomplete zstd frame"));
+            }
+        }
+    }
+}
+
+/// Window log needed to decode the zstd frame starting `data`, read from its header.
+///
+/// Returns `None` if `data` doesn't start with the header of a zstd frame.
+fn frame_window_log(data: &[u8]) -> Option<u32> {
+    const MAGIC_NUMBER: [u8; 4] = 0xFD2FB528_u32.to_le_bytes();
+
+    let (magic_number, rest) = data.split_first_chunk::<4>()?;
+    let (&descriptor, rest) = rest.split_first()?;
+    if *magic_number != MAGIC_NUMBER {
+        return None;
+    }
+
+    let is_single_segment = descriptor & 0x20 != 0;
+    let window_size = if is_single_segment {
+        // The window is the whole content of the frame
+        zstd::zstd_safe::get_frame_content_size(data).ok()??
+    } else {
+        let window_descriptor = *rest.first()?;
+        let window_base = 1_u64 << (10 + (window_descriptor >> 3));
+        window_base + window_base / 8 * u64::from(window_descriptor & 0b111)
+    };
+
+    Some(window_size.max(1).next_power_of_two().ilog2())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    /// Reader returning a single byte at a time, so frame headers are split across reads.
+    struct ByteByByte<'a>(&'a [u8]);
+
+    impl Read for ByteByByte<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            (&mut self.0).take(1).read(buf)
+        }
+    }
+
+    #[test]
+    fn test_later_frame_with_larger_window() {
+        let first = zstd::encode_all(&b"first"[..], 3).unwrap();
+
+        // Streamed without a known size, so the header asks for the whole window
+        let mut encoder = zstd::stream::Encoder::new(Vec::new(), 3).unwrap();
+        encoder.window_log(28).unwrap();
+        encoder.long_distance_matching(true).unwrap();
+        encoder.write_all(b"second").unwrap();
+        let second = encoder.finish().unwrap();
+        assert!(frame_window_log(&first).unwrap() <= DEFAULT_WINDOW_LOG_MAX);
+        assert_eq!(frame_window_log(&second), Some(28));
+
+        let data = [first, second].concat();
+        // A decoder with the limit of the first frame refuses the second one
+        assert!(zstd::decode_all(&data[..]).is_err());
+
+        let mut decoded = Vec::new();
+        MultiFrameZstdDecoder::new(&data[..], None)
+            .unwrap()
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, b"firstsecond");
+
+        let mut decoded = Vec::new();
+        MultiFrameZstdDecoder::new(ByteByByte(&data), None)
+            .unwrap()
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, b"firstsecond");
+    }
+
+    #[test]
+    fn test_truncated_frame() {
+        let data = zstd::encode_all(&b"truncated"[..], 3).unwrap();
+        let mut decoder = MultiFrameZstdDecoder::new(&data[..data.len() - 2], None).unwrap();
+        let err = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
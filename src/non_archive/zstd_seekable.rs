@@ -0,0 +1,374 @@
+//! The seekable zstd format: data compressed in independent frames, followed by a seek table with the
+//! sizes of each frame, so a reader can jump to any position by decompressing a single frame.
+//!
+//! Other zstd decoders read these files like regular ones, the seek table is stored in a skippable frame.
+//! See <https://github.com/facebook/zstd/blob/dev/contrib/seekable_format/zstd_seekable_compression_format.md>.
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+
+const SKIPPABLE_FRAME_MAGIC: u32 = 0x184D_2A5E;
+const SEEKABLE_MAGIC: u32 = 0x8F92_EAB1;
+/// Number of frames (4 bytes), descriptor (1 byte) and magic (4 bytes)
+const FOOTER_SIZE: u64 = 9;
+/// Descriptor bit telling that every entry of the seek table has a checksum
+const CHECKSUM_FLAG: u8 = 1 << 7;
+
+/// Writes zstd frames of `frame_size` uncompressed bytes, compressed in parallel, and the seek table
+/// when finished (or dropped).
+pub struct SeekableZstdWriter<W: Write> {
+    writer: W,
+    frame_size: usize,
+    level: i32,
+    dict: Vec<u8>,
+    /// Window log of long distance matching, if enabled
+    long_window_log: Option<u32>,
+    /// Uncompressed data of the frame being filled
+    buffer: Vec<u8>,
+    /// Full frames waiting to be compressed together
+    pending: Vec<Vec<u8>>,
+    /// Compressed and uncompressed sizes of the frames written
+    frame_sizes: Vec<(u32, u32)>,
+    finished: bool,
+}
+
+impl<W: Write> SeekableZstdWriter<W> {
+    /// `frame_size` must fit in a `u32`, `dict` is the dictionary to compress with, if any.
+    ///
+    /// Long distance matching is enabled with the window log `long_window_log`, if set. Matches can't
+    /// cross frames, so windows larger than `frame_size` don't help.
+    pub fn new(writer: W, frame_size: usize, level: i32, dict: Option<&[u8]>, long_window_log: Option<u32>) -> Self {
+        assert!(frame_size > 0 && u32::try_from(frame_size).is_ok());
+        Self {
+            writer,
+            frame_size,
+            level,
+            dict: dict.unwrap_or_default().to_vec(),
+            long_window_log,
+            buffer: Vec::with_capacity(frame_size),
+            pending: Vec::new(),
+            frame_sizes: Vec::new(),
+            finished: false,
+        }
+    }
+
+    /// Write the remaining frames and the seek table.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+
+        // An empty input still gets a frame, decompressing to nothing
+        if !self.buffer.is_empty() || self.frame_sizes.is_empty() && self.pending.is_empty() {
+            self.pending.push(std::mem::take(&mut self.buffer));
+        }
+        self.compress_pending()?;
+        self.write_seek_table()?;
+        self.writer.flush()
+    }
+
+    fn compress_pending(&mut self) -> io::Result<()> {
+        let frames = self
+            .pending
+            .par_iter()
+            .map(|data| {
+                let mut compressor = zstd::bulk::Compressor::with_dictionary(self.level, &self.dict)?;
+                // Same default as the zstd CLI, lets `ouch test` detect corrupted data
+                compressor.include_checksum(true)?;
+                if let Some(window_log) = self.long_window_log {
+                    compressor.long_distance_matching(true)?;
+                    compressor.window_log(window_log)?;
+                }
+                compressor.compress(data)
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        for (data, frame) in self.pending.drain(..).zip(frames) {
+            self.writer.write_all(&frame)?;
+            self.frame_sizes.push((frame.len() as u32, data.len() as u32));
+        }
+        Ok(())
+    }
+
+    fn write_seek_table(&mut self) -> io::Result<()> {
+        let entries_size = 8 * self.frame_sizes.len() as u64;
+        let frame_size = u32::try_from(entries_size + FOOTER_SIZE)
+            .map_err(|_| io::Error::other("too many frames for a zstd seek table"))?;
+
+        let mut table = Vec::with_capacity(8 + frame_size as usize);
+        table.extend(SKIPPABLE_FRAME_MAGIC.to_le_bytes());
+        table.extend(frame_size.to_le_bytes());
+        for (compressed_size, decompressed_size) in &self.frame_sizes {
+            table.extend(compressed_size.to_le_bytes());
+            table.extend(decompressed_size.to_le_bytes());
+        }
+        table.extend((self.frame_sizes.len() as u32).to_le_bytes());
+        table.push(0); // No checksums, the frames have their own
+        table.extend(SEEKABLE_MAGIC.to_le_bytes());
+
+        self.writer.write_all(&table)
+    }
+}
+
+impl<W: Write> Write for SeekableZstdWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let amount = buf.len().min(self.frame_size - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..amount]);
+
+        if self.buffer.len() == self.frame_size {
+            let frame = std::mem::replace(&mut self.buffer, Vec::with_capacity(self.frame_size));
+            self.pending.push(frame);
+            if self.pending.len() >= rayon::current_num_threads() {
+                self.compress_pending()?;
+            }
+        }
+
+        Ok(amount)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // Frames are only written when full, flushing them earlier would make them smaller
+        self.writer.flush()
+    }
+}
+
+impl<W: Write> Drop for SeekableZstdWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+/// Position of a frame in the compressed and decompressed data.
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    compressed_offset: u64,
+    compressed_size: u64,
+    decompressed_offset: u64,
+    decompressed_size: u64,
+}
+
+/// Reads the decompressed data of a seekable zstd file, seeking only decompresses the frame at the
+/// new position.
+pub struct SeekableZstdReader<R: Read + Seek> {
+    reader: R,
+    frames: Vec<Frame>,
+    decompressor: zstd::bulk::Decompressor<'static>,
+    /// Position in the decompressed data
+    position: u64,
+    /// Index and decompressed data of the last frame read
+    current_frame: Option<(usize, Vec<u8>)>,
+}
+
+impl<R: Read + Seek> SeekableZstdReader<R> {
+    /// Read the seek table at the end of `reader`, returns `None` if it has none.
+    ///
+    /// `dict` is the dictionary the frames were compressed with, if any.
+    pub fn new(mut reader: R, dict: Option<&[u8]>) -> io::Result<Option<Self>> {
+        let Some(frames) = read_seek_table(&mut reader)? else {
+            return Ok(None);
+        };
+
+        Ok(Some(Self {
+            reader,
+            frames,
+            decompressor: zstd::bulk::Decompressor::with_dictionary(dict.unwrap_or_default())?,
+            position: 0,
+            current_frame: None,
+        }))
+    }
+
+    fn decompressed_size(&self) -> u64 {
+        self.frames
+            .last()
+            .map_or(0, |frame| frame.decompressed_offset + frame.decompressed_size)
+    }
+
+    /// Decompress the frame at `index`, unless it's the current one.
+    fn load_frame(&mut self, index: usize) -> io::Result<&[u8]> {
+        if self.current_frame.as_ref().is_none_or(|(current, _)| *current != index) {
+            let frame = self.frames[index];
+            let mut compressed = vec![0; frame.compressed_size as usize];
+            self.reader.seek(SeekFrom::Start(frame.compressed_offset))?;
+            self.reader.read_exact(&mut compressed)?;
+
+            let data = self
+                .decompressor
+                .decompress(&compressed, frame.decompressed_size as usize)?;
+            if data.len() as u64 != frame.decompressed_size {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "zstd frame size doesn't match its seek table entry",
+                ));
+            }
+            self.current_frame = Some((index, data));
+        }
+
+        Ok(&self.current_frame.as_ref().unwrap().1)
+    }
+}
+
+impl<R: Read + Seek> Read for SeekableZstdReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.position >= self.decompressed_size() {
+            return Ok(0);
+        }
+
+        let position = self.position;
+        // Empty frames are skipped, as the next frame starts at the same offset
+        let index = self
+            .frames
+            .partition_point(|frame| frame.decompressed_offset + frame.decompressed_size <= position);
+        let offset_in_frame = (position - self.frames[index].decompressed_offset) as usize;
+
+        let data = &self.load_frame(index)?[offset_in_frame..];
+        let amount = data.len().min(buf.len());
+        buf[..amount].copy_from_slice(&data[..amount]);
+        self.position += amount as u64;
+
+        Ok(amount)
+    }
+}
+
+impl<R: Read + Seek> Seek for SeekableZstdReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.decompressed_size().checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+/// Read the frames listed in the seek table at the end of `reader`, if any.
+fn read_seek_table(reader: &mut (impl Read + Seek)) -> io::Result<Option<Vec<Frame>>> {
+    let file_size = reader.seek(SeekFrom::End(0))?;
+    if file_size < 8 + FOOTER_SIZE {
+        return Ok(None);
+    }
+
+    let mut footer = [0; FOOTER_SIZE as usize];
+    reader.seek(SeekFrom::End(-(FOOTER_SIZE as i64)))?;
+    reader.read_exact(&mut footer)?;
+
+    let frame_count = u32::from_le_bytes(footer[0..4].try_into().unwrap()) as u64;
+    let descriptor = footer[4];
+    let magic = u32::from_le_bytes(footer[5..9].try_into().unwrap());
+    // Reserved bits must be zero
+    if magic != SEEKABLE_MAGIC || descriptor & 0b0111_1100 != 0 {
+        return Ok(None);
+    }
+
+    let entry_size = if descriptor & CHECKSUM_FLAG != 0 { 12 } else { 8 };
+    let table_size = 8 + frame_count * entry_size + FOOTER_SIZE;
+    if table_size > file_size {
+        return Ok(None);
+    }
+
+    let mut table = vec![0; table_size as usize];
+    reader.seek(SeekFrom::Start(file_size - table_size))?;
+    reader.read_exact(&mut table)?;
+
+    let read_u32 = |offset: usize| u64::from(u32::from_le_bytes(table[offset..offset + 4].try_into().unwrap()));
+    if read_u32(0) != u64::from(SKIPPABLE_FRAME_MAGIC) || read_u32(4) != table_size - 8 {
+        return Ok(None);
+    }
+
+    let mut frames = Vec::with_capacity(frame_count as usize);
+    let (mut compressed_offset, mut decompressed_offset) = (0, 0);
+    for entry in 0..frame_count as usize {
+        let entry_offset = 8 + entry * entry_size as usize;
+        let frame = Frame {
+            compressed_offset,
+            compressed_size: read_u32(entry_offset),
+            decompressed_offset,
+            decompressed_size: read_u32(entry_offset + 4),
+        };
+        compressed_offset += frame.compressed_size;
+        decompressed_offset += frame.decompressed_size;
+        frames.push(frame);
+    }
+
+    // The frames must fill the file up to the seek table
+    if compressed_offset != file_size - table_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "zstd seek table doesn't match the size of the file",
+        ));
+    }
+
+    Ok(Some(frames))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use rand::{RngCore, SeedableRng, rngs::SmallRng};
+
+    use super::*;
+
+    #[test]
+    fn test_seekable_zstd_roundtrip() {
+        let data: Vec<u8> = (0..100_000_u32).flat_map(|number| number.to_le_bytes()).collect();
+
+        let mut compressed = Vec::new();
+        let mut writer = SeekableZstdWriter::new(&mut compressed, 30_000, 3, None, None);
+        writer.write_all(&data).unwrap();
+        writer.finish().unwrap();
+        drop(writer);
+
+        // Regular decoders skip the seek table
+        assert_eq!(zstd::decode_all(&compressed[..]).unwrap(), data);
+
+        let mut reader = SeekableZstdReader::new(Cursor::new(&compressed), None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(reader.frames.len(), 14);
+
+        let mut buf = [0; 8];
+        reader.seek(SeekFrom::Start(250_000)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data[250_000..250_008]);
+
+        reader.seek(SeekFrom::Start(0)).unwrap();
+        let mut decompressed = Vec::new();
+        reader.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, data);
+
+        // Regular zstd files have no seek table
+        let regular = zstd::encode_all(&data[..], 3).unwrap();
+        assert!(SeekableZstdReader::new(Cursor::new(regular), None).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_seekable_zstd_long_distance_matching() {
+        // Repeated 3 MiB apart, out of the window of level 1
+        let mut block = vec![0; 3 * 1024 * 1024];
+        SmallRng::seed_from_u64(0).fill_bytes(&mut block);
+        let data = block.repeat(2);
+
+        let compress = |long_window_log| {
+            let mut compressed = Vec::new();
+            let mut writer = SeekableZstdWriter::new(&mut compressed, data.len(), 1, None, long_window_log);
+            writer.write_all(&data).unwrap();
+            writer.finish().unwrap();
+            drop(writer);
+            compressed
+        };
+
+        let regular = compress(None);
+        let long = compress(Some(23));
+        assert!(regular.len() > data.len() * 9 / 10);
+        assert!(long.len() < data.len() * 6 / 10);
+        assert_eq!(zstd::decode_all(&long[..]).unwrap(), data);
+    }
+}
@@ -0,0 +1,149 @@
+use std::path::Path;
+
+use globset::{GlobBuilder, GlobMatcher};
+
+use crate::{Result, error::FinalError};
+
+/// Determines which archive entries should be extracted, from `--include` and `--exclude` glob patterns.
+///
+/// A pattern also matches everything inside of the directories it matches, so `docs` selects
+/// `docs/README.md` too. `*` doesn't cross `/`, use `**` for that.
+#[derive(Debug, Clone, Default)]
+pub struct EntryFilter {
+    include: Vec<GlobMatcher>,
+    exclude: Vec<GlobMatcher>,
+}
+
+impl EntryFilter {
+    /// Entries must match at least one of `include` (if any) and none of `exclude`.
+    pub fn new(include: &[impl AsRef<str>], exclude: &[impl AsRef<str>]) -> Result<Self> {
+        Ok(Self {
+            include: include
+                .iter()
+                .map(|pattern| build_matcher(pattern, false))
+                .collect::<Result<_>>()?,
+            exclude: exclude
+                .iter()
+                .map(|pattern| build_matcher(pattern, false))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    /// Like [`EntryFilter::new`], but patterns without a `/` match names at any depth, like in
+    /// `.gitignore` files, so `*.o` selects `src/main.o` too.
+    pub fn new_unanchored(include: &[impl AsRef<str>], exclude: &[impl AsRef<str>]) -> Result<Self> {
+        Ok(Self {
+            include: include
+                .iter()
+                .map(|pattern| build_matcher(pattern, true))
+                .collect::<Result<_>>()?,
+            exclude: exclude
+                .iter()
+                .map(|pattern| build_matcher(pattern, true))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    /// Whether any pattern was given, if not, every entry is selected.
+    pub fn has_patterns(&self) -> bool {
+        !self.include.is_empty() || !self.exclude.is_empty()
+    }
+
+    /// Check if the entry at `path` (relative to the archive root) is selected.
+    pub fn matches(&self, path: &Path) -> bool {
+        (self.include.is_empty() || matches_any(&self.include, path)) && !self.is_excluded(path)
+    }
+
+    /// Check if the entry at `path` matches an exclude pattern, ignoring the include patterns.
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        matches_any(&self.exclude, path)
+    }
+}
+
+/// Check if `path` or one of its ancestors matches one of `matchers`.
+fn matches_any(matchers: &[GlobMatcher], path: &Path) -> bool {
+    let path = path.strip_prefix(".").unwrap_or(path);
+    path.ancestors()
+        .filter(|ancestor| !ancestor.as_os_str().is_empty())
+        .any(|ancestor| matchers.iter().any(|matcher| matcher.is_match(ancestor)))
+}
+
+fn build_matcher(pattern: impl AsRef<str>, unanchored: bool) -> Result<GlobMatcher> {
+    let pattern = pattern.as_ref();
+    let normalized = pattern.trim_start_matches("./").trim_end_matches('/');
+    let normalized = if unanchored && !normalized.contains('/') {
+        format!("**/{normalized}")
+    } else {
+        normalized.to_owned()
+    };
+
+    let glob = GlobBuilder::new(&normalized).literal_separator(true).build();
+
+    glob.map(|glob| glob.compile_matcher()).map_err(|err| {
+        FinalError::with_title(format!("Invalid pattern \"{pattern}\""))
+            .detail(err.kind().to_string())
+            .into()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(include: &[&str], exclude: &[&str]) -> EntryFilter {
+        EntryFilter::new(include, exclude).unwrap()
+    }
+
+    #[test]
+    fn test_entry_filter_without_patterns_matches_everything() {
+        let filter = filter(&[], &[]);
+        assert!(!filter.has_patterns());
+        assert!(filter.matches(Path::new("a")));
+        assert!(filter.matches(Path::new("a/b/c.rs")));
+    }
+
+    #[test]
+    fn test_entry_filter_include() {
+        let filter = filter(&["src/**/*.rs", "./docs/README.md", "assets"], &[]);
+        assert!(filter.has_patterns());
+        assert!(filter.matches(Path::new("src/main.rs")));
+        assert!(filter.matches(Path::new("src/a/b/lib.rs")));
+        assert!(filter.matches(Path::new("./docs/README.md")));
+        assert!(filter.matches(Path::new("assets/logo.svg")));
+        assert!(!filter.matches(Path::new("src/main.c")));
+        assert!(!filter.matches(Path::new("docs/other.md")));
+        assert!(!filter.matches(Path::new("build.rs")));
+    }
+
+    #[test]
+    fn test_entry_filter_star_does_not_cross_separators() {
+        let filter = filter(&["*.rs"], &[]);
+        assert!(filter.matches(Path::new("build.rs")));
+        assert!(!filter.matches(Path::new("src/main.rs")));
+    }
+
+    #[test]
+    fn test_entry_filter_exclude() {
+        let filter = filter(&["src"], &["src/generated", "**/*.bak"]);
+        assert!(filter.matches(Path::new("src/main.rs")));
+        assert!(!filter.matches(Path::new("src/generated/bindings.rs")));
+        assert!(!filter.matches(Path::new("src/main.rs.bak")));
+        assert!(!filter.matches(Path::new("README.md")));
+    }
+
+    #[test]
+    fn test_entry_filter_unanchored() {
+        let filter = EntryFilter::new_unanchored(&[] as &[&str], &["*.o", "build/tmp", "**/.git"]).unwrap();
+        assert!(!filter.matches(Path::new("main.o")));
+        assert!(!filter.matches(Path::new("project/src/main.o")));
+        assert!(!filter.matches(Path::new("build/tmp/a")));
+        assert!(filter.matches(Path::new("project/build/tmp/a")));
+        assert!(!filter.matches(Path::new("project/.git/HEAD")));
+        assert!(filter.matches(Path::new("project/src/main.rs")));
+    }
+
+    #[test]
+    fn test_entry_filter_invalid_pattern() {
+        assert!(EntryFilter::new(&["src/[a"], &[] as &[&str]).is_err());
+    }
+}
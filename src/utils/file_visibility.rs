@@ -1,11 +1,36 @@
 use std::{
     ffi::OsStr,
+    io::Read,
     iter,
     path::{Path, PathBuf},
 };
 
 use fs_err as fs;
 
+use super::EntryFilter;
+
+/// Names of the directories and files of version control systems, skipped by `--exclude-vcs`
+pub const VCS_NAMES: &[&str] = &[
+    ".git",
+    ".gitignore",
+    ".gitattributes",
+    ".gitmodules",
+    ".hg",
+    ".hgignore",
+    ".hgtags",
+    ".svn",
+    ".bzr",
+    ".bzrignore",
+    "CVS",
+    ".cvsignore",
+    "RCS",
+    "SCCS",
+    "_darcs",
+];
+
+/// Start of the `CACHEDIR.TAG` files marking cache directories, see https://bford.info/cachedir/
+const CACHEDIR_TAG_SIGNATURE: &[u8] = b"Signature: 8a477f597d28d172789f06886806bc55";
+
 /// Determines which files should be read or ignored during directory walking
 pub struct FileVisibilityPolicy {
     /// Enables reading .ignore files.
@@ -27,6 +52,20 @@ pub struct FileVisibilityPolicy {
     pub read_git_exclude: bool,
 
     pub follow_symlinks: bool,
+
+    /// Selects files with `--include` and `--exclude` patterns, matched against the paths relative to
+    /// the parent directory of each input.
+    pub entry_filter: EntryFilter,
+
+    /// Skips the directories tagged with a `CACHEDIR.TAG` file.
+    ///
+    /// Disabled by default.
+    pub exclude_caches: bool,
+
+    /// Walks through the entries of directories sorted by name, instead of in the order of the file system.
+    ///
+    /// Disabled by default.
+    pub sort_entries: bool,
 }
 
 impl Default for FileVisibilityPolicy {
@@ -37,6 +76,9 @@ impl Default for FileVisibilityPolicy {
             read_git_ignore: false,
             read_git_exclude: false,
             follow_symlinks: false,
+            entry_filter: EntryFilter::default(),
+            exclude_caches: false,
+            sort_entries: false,
         }
     }
 }
@@ -84,8 +126,32 @@ impl FileVisibilityPolicy {
         }
     }
 
+    #[must_use]
+    /// Selects files with `--include` and `--exclude` patterns.
+    pub fn entry_filter(self, entry_filter: EntryFilter) -> Self {
+        Self { entry_filter, ..self }
+    }
+
+    #[must_use]
+    /// Skips the directories tagged with a `CACHEDIR.TAG` file.
+    pub fn exclude_caches(self, exclude_caches: bool) -> Self {
+        Self { exclude_caches, ..self }
+    }
+
+    #[must_use]
+    /// Walks through the entries of directories sorted by name.
+    pub fn sort_entries(self, sort_entries: bool) -> Self {
+        Self { sort_entries, ..self }
+    }
+
     /// Walks through a directory using [`ignore::Walk`]
-    pub fn build_walker(&self, path: impl AsRef<Path>) -> ignore::Walk {
+    ///
+    /// Excluded directories aren't walked into, directories that aren't selected by the include
+    /// patterns are walked into, but not returned.
+    pub fn build_walker(
+        &self,
+        path: impl AsRef<Path>,
+    ) -> Box<dyn Iterator<Item = Result<ignore::DirEntry, ignore::Error>> + 'static> {
         let mut builder = ignore::WalkBuilder::new(path);
 
         builder
@@ -96,11 +162,32 @@ impl FileVisibilityPolicy {
             .follow_links(self.follow_symlinks);
 
         if self.read_git_ignore {
-            builder.filter_entry(|p| p.path().file_name().is_some_and(|name| name != ".git"));
             builder.require_git(false);
         }
 
-        builder.build()
+        if self.sort_entries {
+            builder.sort_by_file_name(|a, b| a.cmp(b));
+        }
+
+        let skip_git_dir = self.read_git_ignore;
+        let exclude_caches = self.exclude_caches;
+        let entry_filter = self.entry_filter.clone();
+        builder.filter_entry(move |entry| {
+            let is_dir = entry.file_type().is_some_and(|file_type| file_type.is_dir());
+
+            let is_skipped = (skip_git_dir && entry.file_name() == ".git")
+                || entry_filter.is_excluded(entry.path())
+                || (exclude_caches && is_dir && has_cachedir_tag(entry.path()));
+            !is_skipped
+        });
+
+        let walker = builder.build();
+        if !self.entry_filter.has_patterns() {
+            return Box::new(walker);
+        }
+
+        let entry_filter = self.entry_filter.clone();
+        Box::new(walker.filter(move |entry| entry.as_ref().map_or(true, |entry| entry_filter.matches(entry.path()))))
     }
 
     // workaround for ignore::Walk failing if the first given path is a broken symlink
@@ -125,3 +212,11 @@ impl FileVisibilityPolicy {
         iter
     }
 }
+
+/// Check if `dir` holds a `CACHEDIR.TAG` file, marking it as a cache directory.
+fn has_cachedir_tag(dir: &Path) -> bool {
+    let mut signature = [0; CACHEDIR_TAG_SIGNATURE.len()];
+    fs::File::open(dir.join("CACHEDIR.TAG"))
+        .and_then(|mut file| file.read_exact(&mut signature))
+        .is_ok_and(|()| signature == CACHEDIR_TAG_SIGNATURE)
+}
@@ -13,10 +13,12 @@ use crate::INITIAL_CURRENT_DIR;
 /// This is different from [`Path::display`].
 ///
 /// See <https://gist.github.com/marcospb19/ebce5572be26397cf08bbd0fd3b65ac1> for a comparison.
+#[allow(dead_code)]
 pub fn path_to_str(path: &Path) -> Cow<'_, str> {
     os_str_to_str(path.as_ref())
 }
 
+#[allow(dead_code)]
 pub fn os_str_to_str(os_str: &OsStr) -> Cow<'_, str> {
     let format = || {
         let text = format!("{os_str:?}");
@@ -47,6 +49,7 @@ pub fn pretty_format_list_of_paths(paths: &[impl AsRef<Path>]) -> String {
 }
 
 /// Display the directory name, but use "current directory" when necessary.
+#[allow(dead_code)]
 pub fn nice_directory_display(path: &Path) -> Cow<'_, str> {
     if path == Path::new(".") {
         Cow::Borrowed("current directory")
@@ -4,7 +4,7 @@ use std::{
     borrow::Cow,
     env,
     io::{self, Read},
-    path::{Path, PathBuf},
+    path::{Path, PathBuf, absolute},
 };
 
 use fs_err::{self as fs, PathExt};
@@ -23,6 +23,29 @@ pub fn is_path_stdin(path: &Path) -> bool {
     path.as_os_str() == "-"
 }
 
+/// Check if the output `path` means STDOUT: `"-"`, optionally followed by extensions, like `"-.tar.gz"`.
+pub fn is_path_stdout(path: &Path) -> bool {
+    let path = path.as_os_str().as_encoded_bytes();
+    path == b"-" || path.starts_with(b"-.")
+}
+
+/// Canonicalize all paths, except for STDIN (`"-"`) and symlinks, which are only made absolute.
+pub fn absolutize_paths(paths: &[impl AsRef<Path>]) -> Result<Vec<PathBuf>> {
+    paths
+        .iter()
+        .map(|path| {
+            let path = path.as_ref();
+            if is_path_stdin(path) {
+                Ok(path.into())
+            } else if path.is_symlink() {
+                Ok(absolute(path)?)
+            } else {
+                canonicalize(path)
+            }
+        })
+        .collect()
+}
+
 /// Check if &Path exists, if it does then ask the user if they want to overwrite or rename it.
 /// If the user want to overwrite then the file or directory will be removed and returned the same input path
 /// If the user want to rename then nothing will be removed and a new path will be returned with a new name
@@ -108,6 +131,27 @@ pub fn find_available_filename_by_renaming(path: &Path) -> Result<PathBuf> {
     unreachable!()
 }
 
+/// Move `from` to `to`, merging directories that exist in both, other files in `to` are replaced.
+///
+/// Symlinks to directories in `to` are replaced too, instead of moving files through them.
+pub fn move_merging(from: &Path, to: &Path) -> Result<()> {
+    let to_is_dir = fs::symlink_metadata(to).is_ok_and(|metadata| metadata.is_dir());
+
+    if to_is_dir && fs::symlink_metadata(from)?.is_dir() {
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            move_merging(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        fs::remove_dir(from)?;
+    } else {
+        if to.symlink_metadata().is_ok() {
+            remove_file_or_dir(to)?;
+        }
+        fs::rename(from, to)?;
+    }
+    Ok(())
+}
+
 /// Creates a directory at the path, if there is nothing there.
 pub fn create_dir_if_non_existent(path: &Path) -> Result<()> {
     if !path.fs_err_try_exists()? {
@@ -146,14 +190,6 @@ pub fn is_same_file_as_output(path: &Path, output_handle: &Handle) -> bool {
     false
 }
 
-/// Check if an IO error is caused by a broken symlink.
-///
-/// Returns `true` if the error is `NotFound` and the path is a symlink,
-/// indicating the symlink target doesn't exist.
-pub fn is_broken_symlink_error(error: &io::Error, path: &Path) -> bool {
-    error.kind() == io::ErrorKind::NotFound && path.is_symlink()
-}
-
 /// Try to detect the file extension by looking for known magic strings
 /// Source: <https://en.wikipedia.org/wiki/List_of_file_signatures>
 pub fn try_infer_format(path: &Path) -> Option<CompressionFormat> {
@@ -328,6 +364,23 @@ mod tests {
         assert!(!is_path_stdin(Path::new("")));
     }
 
+    #[test]
+    fn move_merging_merges_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let (from, to) = (dir.path().join("from"), dir.path().join("to"));
+        for path in [from.join("a/b"), to.join("a/c")] {
+            std::fs::create_dir_all(path).unwrap();
+        }
+        std::fs::write(from.join("a/file"), "new").unwrap();
+        std::fs::write(to.join("a/file"), "old").unwrap();
+
+        move_merging(&from, &to).unwrap();
+
+        assert!(!from.exists());
+        assert!(to.join("a/b").is_dir() && to.join("a/c").is_dir());
+        assert_eq!(std::fs::read_to_string(to.join("a/file")).unwrap(), "new");
+    }
+
     #[test]
     fn try_infer_format_recognizes_zip_magic() {
         let dir = tempfile::tempdir().unwrap();
@@ -2,7 +2,7 @@ use std::io::{self, StderrLock, StdoutLock, Write, stderr, stdout};
 
 use fs_err as fs;
 
-use crate::utils::logger;
+use crate::utils::{logger, progress};
 
 type StdioOutputLocks = (StdoutLock<'static>, StderrLock<'static>);
 
@@ -12,6 +12,7 @@ pub fn lock_and_flush_output_stdio() -> io::Result<StdioOutputLocks> {
     let mut stdout = stdout().lock();
     stdout.flush()?;
     let mut stderr = stderr().lock();
+    progress::clear_bar(&mut stderr);
     stderr.flush()?;
 
     Ok((stdout, stderr))
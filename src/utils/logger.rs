@@ -6,7 +6,10 @@ use std::{
 
 pub use logger_thread::spawn_logger_thread;
 
-use super::colors::{GREEN, ORANGE, RESET};
+use super::{
+    colors::{GREEN, ORANGE, RESET},
+    progress,
+};
 use crate::accessible::is_running_in_accessible_mode;
 
 #[macro_export]
@@ -38,7 +41,7 @@ fn should_display_log(level: &MessageLevel) -> bool {
     level >= global_level
 }
 
-/// Set the value of the global [`LOG_DISPLAY_LEVEL`].
+/// Hide the `[INFO]` messages if `quiet` is set, can only be set once per process.
 pub fn set_log_display_level(quiet: bool) {
     let level = if quiet { MessageLevel::Quiet } else { MessageLevel::Info };
     if LOG_DISPLAY_LEVEL.get().is_none() {
@@ -197,14 +200,13 @@ mod logger_thread {
 
     #[track_caller]
     fn get_sender() -> &'static LogSender {
+        // Library users (and tests) don't spawn the logger thread beforehand
+        spawn_logger_thread();
         SENDER.get().expect("No sender, you need to call `setup_channel` first")
     }
 
     #[track_caller]
     pub(super) fn send_print_command(msg: PrintMessage) {
-        if cfg!(test) {
-            spawn_logger_thread();
-        }
         get_sender()
             .send(LoggerCommand::Print(msg))
             .expect("Failed to send print command");
@@ -279,7 +281,9 @@ mod logger_thread {
 
     fn flush_logs_to_stderr(buffer: &mut Vec<u8>) {
         if !buffer.is_empty() {
-            if let Err(err) = stderr().write_all(buffer) {
+            let mut stderr = stderr().lock();
+            progress::clear_bar(&mut stderr);
+            if let Err(err) = stderr.write_all(buffer) {
                 panic!("Failed to write to STDERR: {err}");
             }
             buffer.clear();
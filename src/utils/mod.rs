@@ -6,11 +6,19 @@
 pub mod colors;
 pub mod io;
 pub mod logger;
+pub mod progress;
 pub mod threads;
 
-pub use self::{file_visibility::*, formatting::*, fs::*, question::*, utf8::*};
+pub use self::{
+    entry_filter::*, file_visibility::*, formatting::*, fs::*, path_transform::*, question::*, reproducible::*,
+    temp_storage::*, utf8::*,
+};
+mod entry_filter;
 mod file_visibility;
 mod formatting;
 mod fs;
+mod path_transform;
 mod question;
+mod reproducible;
+mod temp_storage;
 mod utf8;
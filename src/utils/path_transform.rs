@@ -0,0 +1,338 @@
+use std::{
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+};
+
+use bstr::{ByteSlice, ByteVec};
+use regex::bytes::{Regex, RegexBuilder};
+
+use crate::{
+    error::{FinalError, Result},
+    utils::PathFmt,
+    warning,
+};
+
+/// Changes the paths of archive entries, when extracting them (from `--strip-components`, `--flatten`,
+/// `--transform` and `--prefix`) or when adding them to an archive (from `--transform` and `--prefix`).
+#[derive(Debug, Clone, Default)]
+pub struct PathTransform {
+    /// Number of leading components removed from the paths, entries with fewer components are skipped
+    pub strip_components: usize,
+    /// Keep only the file names, directory entries are skipped
+    pub flatten: bool,
+    /// Substitutions applied to the paths, in order
+    pub rules: Vec<TransformRule>,
+    /// Directory the paths are put under, after the other changes
+    pub prefix: Option<PathBuf>,
+}
+
+impl PathTransform {
+    /// Whether paths are kept as they are.
+    pub fn is_identity(&self) -> bool {
+        self.strip_components == 0 && !self.flatten && self.rules.is_empty() && self.prefix.is_none()
+    }
+
+    /// The new path of the entry at `path`, or `None` if the entry should be skipped.
+    ///
+    /// Hardlink targets must go through this too, with `is_dir` unset, to keep pointing at the same entry.
+    pub fn apply(&self, path: &Path, is_dir: bool) -> Option<PathBuf> {
+        if self.is_identity() {
+            return Some(path.to_owned());
+        }
+
+        let mut new_path = if self.flatten {
+            if is_dir {
+                return None;
+            }
+            PathBuf::from(path.file_name()?)
+        } else {
+            path.components()
+                .filter(|component| *component != Component::CurDir)
+                .skip(self.strip_components)
+                .collect()
+        };
+
+        if self.rules.is_empty() && self.prefix.is_none() {
+            return (!new_path.as_os_str().is_empty()).then_some(new_path);
+        }
+
+        // Like tar, directories are matched with a trailing slash, so `s|^build/|bin/|` renames the
+        // `build` directory too
+        if is_dir && !self.rules.is_empty() {
+            new_path.as_mut_os_string().push("/");
+        }
+        for rule in &self.rules {
+            new_path = rule.apply(&new_path);
+        }
+        if is_dir && !self.rules.is_empty() {
+            let bytes = Vec::from_path_lossy(&new_path);
+            new_path = bytes.trim_end_with(|char| char == '/').to_path_lossy().into_owned();
+        }
+        if let Some(prefix) = &self.prefix {
+            new_path = prefix.join(new_path);
+        }
+
+        // Substitutions can build any path, don't let them escape the archive or the output directory
+        let is_enclosed = new_path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        if !is_enclosed {
+            warning!(
+                "Skipping {}, it would be renamed to {} which is outside of the archive",
+                PathFmt(path),
+                PathFmt(&new_path)
+            );
+            return None;
+        }
+
+        (!new_path.as_os_str().is_empty()).then_some(new_path)
+    }
+
+    /// With `--flatten`, fail if the entry at `path` gets the same `new_path` as another entry unpacked
+    /// before, instead of overwriting it. `unpacked` maps the new paths of the entries seen so far to
+    /// their paths in the archive.
+    ///
+    /// Entries with the same path in the archive, like files appended again to a tar, replace each other.
+    pub fn check_flatten_collision(
+        &self,
+        unpacked: &mut HashMap<PathBuf, PathBuf>,
+        path: &Path,
+        new_path: &Path,
+    ) -> Result<()> {
+        if !self.flatten {
+            return Ok(());
+        }
+
+        match unpacked.get(new_path) {
+            Some(other) if other != path => {
+                let error = FinalError::with_title(format!("Cannot flatten {}", PathFmt(path)))
+                    .detail(format!(
+                        "It would be unpacked to {}, like {}",
+                        PathFmt(new_path),
+                        PathFmt(other)
+                    ))
+                    .hint("Select one of them with --include or --exclude, or use --transform to rename it");
+                Err(error.into())
+            }
+            Some(_) => Ok(()),
+            None => {
+                unpacked.insert(new_path.to_owned(), path.to_owned());
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A sed-like substitution of `--transform`, like `s|^build/|myapp-1.0/|`.
+#[derive(Debug, Clone)]
+pub struct TransformRule {
+    regex: Regex,
+    /// Replacement in the syntax of [`Regex::replace`]
+    replacement: Vec<u8>,
+    /// Replace every match instead of the first one
+    global: bool,
+}
+
+impl TransformRule {
+    /// Parse `s<delimiter>regex<delimiter>replacement<delimiter>[flags]`, the delimiter can be any
+    /// character, and the flags are `g` (replace every match) and `i` (ignore case).
+    ///
+    /// The regex uses the extended syntax (like `sed -E`), and like sed, `&` in the replacement is the
+    /// whole match and `\1` to `\9` are the groups.
+    pub fn parse(expression: &str) -> Result<Self> {
+        let error = |detail: &str| {
+            FinalError::with_title(format!("Invalid --transform expression \"{expression}\""))
+                .detail(detail.to_owned())
+                .hint("Expressions look like 's|^build/|myapp-1.0/|', with optional flags 'g' and 'i' at the end")
+        };
+
+        let mut chars = expression.chars();
+        if chars.next() != Some('s') {
+            return Err(error("Expressions must start with 's'").into());
+        }
+        let Some(delimiter) = chars.next() else {
+            return Err(error("Missing the delimiter after 's'").into());
+        };
+        if delimiter == '\\' || delimiter.is_alphanumeric() {
+            return Err(error("The delimiter can't be a backslash or alphanumeric").into());
+        }
+
+        let mut parts = vec![String::new()];
+        let mut is_closed = false;
+        while let Some(char) = chars.next() {
+            match char {
+                '\\' => match chars.next() {
+                    // An escaped delimiter is taken literally
+                    Some(escaped) if escaped == delimiter && parts.len() == 1 => {
+                        parts[0].push_str(&regex::escape(&escaped.to_string()))
+                    }
+                    Some(escaped) => parts.last_mut().unwrap().extend(['\\', escaped]),
+                    None => parts.last_mut().unwrap().push('\\'),
+                },
+                char if char == delimiter && parts.len() == 2 => {
+                    is_closed = true;
+                    break;
+                }
+                char if char == delimiter => parts.push(String::new()),
+                char => parts.last_mut().unwrap().push(char),
+            }
+        }
+        if !is_closed {
+            return Err(error(&format!("Missing the closing '{delimiter}'")).into());
+        }
+        // Unwrap safety: only closed after the pattern and the replacement
+        let [pattern, replacement] = <[String; 2]>::try_from(parts).unwrap();
+
+        let mut case_insensitive = false;
+        let mut global = false;
+        for flag in chars {
+            match flag {
+                'g' => global = true,
+                'i' => case_insensitive = true,
+                _ => return Err(error(&format!("Unknown flag '{flag}'")).into()),
+            }
+        }
+
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(case_insensitive)
+            .build()
+            .map_err(|err| error(&err.to_string()))?;
+
+        Ok(Self {
+            regex,
+            replacement: sed_replacement_to_regex(&replacement).into_bytes(),
+            global,
+        })
+    }
+
+    fn apply(&self, path: &Path) -> PathBuf {
+        let path = Vec::from_path_lossy(path);
+        let replaced = if self.global {
+            self.regex.replace_all(&path, &self.replacement[..])
+        } else {
+            self.regex.replace(&path, &self.replacement[..])
+        };
+        replaced.to_path_lossy().into_owned()
+    }
+}
+
+/// Convert the `&` and `\1` of sed replacements to the `${0}` and `${1}` of [`Regex::replace`].
+fn sed_replacement_to_regex(replacement: &str) -> String {
+    let mut converted = String::with_capacity(replacement.len());
+    let mut chars = replacement.chars();
+    while let Some(char) = chars.next() {
+        match char {
+            '&' => converted.push_str("${0}"),
+            '$' => converted.push_str("$$"),
+            '\\' => match chars.next() {
+                Some(digit @ '0'..='9') => converted.extend(['$', '{', digit, '}']),
+                Some('$') => converted.push_str("$$"),
+                Some(escaped) => converted.push(escaped),
+                None => converted.push('\\'),
+            },
+            char => converted.push(char),
+        }
+    }
+    converted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_transform_strip_components() {
+        let transform = PathTransform {
+            strip_components: 1,
+            ..PathTransform::default()
+        };
+        assert_eq!(
+            transform.apply(Path::new("project-1.2.3/src/main.rs"), false),
+            Some(PathBuf::from("src/main.rs"))
+        );
+        assert_eq!(
+            transform.apply(Path::new("./project-1.2.3/src"), true),
+            Some(PathBuf::from("src"))
+        );
+        assert_eq!(transform.apply(Path::new("project-1.2.3/"), true), None);
+        assert_eq!(transform.apply(Path::new("README"), false), None);
+    }
+
+    #[test]
+    fn test_path_transform_flatten() {
+        let transform = PathTransform {
+            flatten: true,
+            ..PathTransform::default()
+        };
+        assert_eq!(
+            transform.apply(Path::new("a/b/c.txt"), false),
+            Some(PathBuf::from("c.txt"))
+        );
+        assert_eq!(transform.apply(Path::new("a/b"), true), None);
+    }
+
+    #[test]
+    fn test_path_transform_identity() {
+        let transform = PathTransform::default();
+        assert!(transform.is_identity());
+        assert_eq!(transform.apply(Path::new("./a/b"), true), Some(PathBuf::from("./a/b")));
+    }
+
+    #[test]
+    fn test_path_transform_rules_and_prefix() {
+        let transform = PathTransform {
+            rules: vec![
+                TransformRule::parse("s|^build/|bin/|").unwrap(),
+                TransformRule::parse(r"s/(.*)\.TXT$/\1.txt/i").unwrap(),
+            ],
+            prefix: Some(PathBuf::from("myapp-1.0/")),
+            ..PathTransform::default()
+        };
+        assert_eq!(
+            transform.apply(Path::new("build/app"), false),
+            Some(PathBuf::from("myapp-1.0/bin/app"))
+        );
+        assert_eq!(
+            transform.apply(Path::new("docs/README.Txt"), false),
+            Some(PathBuf::from("myapp-1.0/docs/README.txt"))
+        );
+        // Directories are matched with a trailing slash, which isn't kept
+        assert_eq!(
+            transform.apply(Path::new("build/lib/"), true),
+            Some(PathBuf::from("myapp-1.0/bin/lib"))
+        );
+        assert_eq!(
+            transform.apply(Path::new("build"), true).unwrap().as_os_str(),
+            "myapp-1.0/bin"
+        );
+
+        let transform = PathTransform {
+            rules: vec![TransformRule::parse("s|^build/||").unwrap()],
+            ..PathTransform::default()
+        };
+        assert_eq!(transform.apply(Path::new("build"), true), None);
+
+        let transform = PathTransform {
+            rules: vec![TransformRule::parse("s,^,../,").unwrap()],
+            ..PathTransform::default()
+        };
+        assert_eq!(transform.apply(Path::new("file"), false), None);
+    }
+
+    #[test]
+    fn test_transform_rule_parse() {
+        let rule = TransformRule::parse("s/a/[&]/g").unwrap();
+        assert_eq!(rule.apply(Path::new("banana")), Path::new("b[a]n[a]n[a]"));
+
+        let rule = TransformRule::parse(r"s|\||-|").unwrap();
+        assert_eq!(rule.apply(Path::new("a|b|c")), Path::new("a-b|c"));
+
+        let rule = TransformRule::parse(r"s/x/$1 \&/").unwrap();
+        assert_eq!(rule.apply(Path::new("x")), Path::new("$1 &"));
+
+        assert!(TransformRule::parse("build/bin/").is_err());
+        assert!(TransformRule::parse("s|build|bin").is_err());
+        assert!(TransformRule::parse("s|build|bin|x").is_err());
+        assert!(TransformRule::parse("s|(|bin|").is_err());
+    }
+}
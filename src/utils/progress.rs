@@ -0,0 +1,277 @@
+//! Progress of compressions and decompressions, shown on STDERR while they run.
+//!
+//! Readers and writers of the (de)compression chains are wrapped with [`Progress::count_read`] and
+//! [`Progress::count_write`], which count the bytes going through them. A background thread then
+//! redraws a progress bar when STDERR is a terminal, or prints a plain line every few seconds in
+//! accessible mode.
+
+use std::{
+    io::{self, IsTerminal, Read, Seek, SeekFrom, Write, stderr},
+    sync::{
+        Arc, Condvar, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{accessible::is_running_in_accessible_mode, info_accessible, utils::BytesFmt};
+
+/// Whether a progress bar is currently drawn at the bottom of STDERR.
+static BAR_IS_DRAWN: AtomicBool = AtomicBool::new(false);
+
+/// Erase the progress bar, if drawn, so other messages can be written to STDERR.
+///
+/// It's drawn again on the next update.
+pub fn clear_bar(stderr: &mut impl Write) {
+    if BAR_IS_DRAWN.swap(false, Ordering::Relaxed) {
+        let _ = write!(stderr, "\r\x1b[2K");
+    }
+}
+
+/// Which side of the operation the bytes are counted on.
+#[derive(Debug, Clone, Copy)]
+pub enum Side {
+    /// The bytes before compression, or after decompression
+    Uncompressed,
+    /// The bytes after compression, or before decompression
+    Compressed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Compression,
+    Decompression,
+}
+
+#[derive(Debug)]
+struct Counters {
+    operation: Operation,
+    uncompressed: AtomicU64,
+    compressed: AtomicU64,
+    /// Expected bytes of the side that's read: uncompressed for compression, compressed for decompression
+    total: Option<u64>,
+    start: Instant,
+}
+
+impl Counters {
+    fn get(&self, side: Side) -> &AtomicU64 {
+        match side {
+            Side::Uncompressed => &self.uncompressed,
+            Side::Compressed => &self.compressed,
+        }
+    }
+
+    /// Bytes read by the operation so far, that `total` refers to, and bytes written.
+    fn processed_and_written(&self) -> (u64, u64) {
+        let uncompressed = self.uncompressed.load(Ordering::Relaxed);
+        let compressed = self.compressed.load(Ordering::Relaxed);
+        match self.operation {
+            Operation::Compression => (uncompressed, compressed),
+            Operation::Decompression => (compressed, uncompressed),
+        }
+    }
+
+    fn describe(&self) -> String {
+        let (processed, written) = self.processed_and_written();
+        let uncompressed = self.uncompressed.load(Ordering::Relaxed);
+        let compressed = self.compressed.load(Ordering::Relaxed);
+
+        // Encoders can buffer a lot of input before writing their output
+        if let Some(total) = self.total
+            && processed >= total
+        {
+            return format!("{} read, finishing, {} written", bytes(total), bytes(written));
+        }
+
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let rate = if elapsed > 0.0 { processed as f64 / elapsed } else { 0.0 };
+
+        let mut line = match self.total {
+            Some(total) if total > 0 => format!(
+                "{} of {} ({:.0}%)",
+                bytes(processed),
+                bytes(total),
+                100.0 * processed as f64 / total as f64
+            ),
+            _ => bytes(processed),
+        };
+        line += &format!(", {}/s", bytes(rate as u64));
+
+        if uncompressed > 0 && compressed > 0 {
+            line += &format!(", ratio {:.1}%", 100.0 * compressed as f64 / uncompressed as f64);
+        }
+
+        if let Some(total) = self.total
+            && rate > 0.0
+        {
+            let seconds_left = ((total - processed) as f64 / rate).ceil() as u64;
+            line += &format!(", ETA {}:{:02}", seconds_left / 60, seconds_left % 60);
+        }
+
+        line
+    }
+}
+
+fn bytes(amount: u64) -> String {
+    BytesFmt(amount).to_string().trim_start().to_owned()
+}
+
+/// Byte counters of a running operation, with the thread displaying them.
+///
+/// The display stops and the bar is erased when dropped.
+pub struct Progress {
+    counters: Arc<Counters>,
+    display: Option<(Arc<(Mutex<bool>, Condvar)>, JoinHandle<()>)>,
+}
+
+impl Progress {
+    /// Start counting the bytes of an operation, `total` is the expected amount of uncompressed
+    /// bytes for compressions, or of compressed bytes for decompressions.
+    ///
+    /// Nothing is displayed if `show` is false, or if STDERR isn't a terminal (unless running in
+    /// accessible mode).
+    pub fn new(operation: Operation, total: Option<u64>, show: bool) -> Self {
+        let counters = Arc::new(Counters {
+            operation,
+            uncompressed: AtomicU64::new(0),
+            compressed: AtomicU64::new(0),
+            total,
+            start: Instant::now(),
+        });
+
+        let display = Self::should_display(show).then(|| {
+            let finished = Arc::new((Mutex::new(false), Condvar::new()));
+            let handle = thread::spawn({
+                let counters = Arc::clone(&counters);
+                let finished = Arc::clone(&finished);
+                move || run_display(&counters, &finished)
+            });
+            (finished, handle)
+        });
+
+        Self { counters, display }
+    }
+
+    /// Whether [`Progress::new`] would display the progress, to skip computing its `total` otherwise.
+    pub fn should_display(show: bool) -> bool {
+        show && (is_running_in_accessible_mode() || stderr().is_terminal())
+    }
+
+    /// Count the bytes read from `reader` as `side` bytes.
+    pub fn count_read<R: Read>(&self, reader: R, side: Side) -> Counting<R> {
+        Counting {
+            inner: reader,
+            counters: Arc::clone(&self.counters),
+            side,
+        }
+    }
+
+    /// Count the bytes written to `writer` as `side` bytes.
+    pub fn count_write<W: Write>(&self, writer: W, side: Side) -> Counting<W> {
+        Counting {
+            inner: writer,
+            counters: Arc::clone(&self.counters),
+            side,
+        }
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if let Some((finished, handle)) = self.display.take() {
+            *finished.0.lock().unwrap() = true;
+            finished.1.notify_one();
+            let _ = handle.join();
+        }
+    }
+}
+
+fn run_display(counters: &Counters, finished: &(Mutex<bool>, Condvar)) {
+    let accessible = is_running_in_accessible_mode();
+
+    // Quick operations finish before anything is shown
+    let (delay, interval) = if accessible {
+        (Duration::from_secs(5), Duration::from_secs(5))
+    } else {
+        (Duration::from_millis(500), Duration::from_millis(100))
+    };
+
+    let mut is_finished = finished.0.lock().unwrap();
+    let mut timeout = delay;
+
+    loop {
+        let timeout_result;
+        (is_finished, timeout_result) = finished
+            .1
+            .wait_timeout_while(is_finished, timeout, |is_finished| !*is_finished)
+            .unwrap();
+        if !timeout_result.timed_out() {
+            break;
+        }
+        timeout = interval;
+
+        if accessible {
+            info_accessible!("Progress: {}", counters.describe());
+        } else {
+            let mut stderr = stderr().lock();
+            let _ = write!(stderr, "\r\x1b[2K{}", counters.describe());
+            let _ = stderr.flush();
+            BAR_IS_DRAWN.store(true, Ordering::Relaxed);
+        }
+    }
+
+    clear_bar(&mut stderr().lock());
+}
+
+/// A reader or writer that counts the bytes going through it, created by [`Progress`].
+pub struct Counting<T> {
+    inner: T,
+    counters: Arc<Counters>,
+    side: Side,
+}
+
+impl<R: Read> Read for Counting<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let amount = self.inner.read(buf)?;
+        self.counters.get(self.side).fetch_add(amount as u64, Ordering::Relaxed);
+        Ok(amount)
+    }
+}
+
+impl<W: Write> Write for Counting<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let amount = self.inner.write(buf)?;
+        self.counters.get(self.side).fetch_add(amount as u64, Ordering::Relaxed);
+        Ok(amount)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: Seek> Seek for Counting<S> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_and_describes_bytes() {
+        let progress = Progress::new(Operation::Compression, None, false);
+
+        let mut writer = progress.count_write(Vec::new(), Side::Compressed);
+        writer.write_all(&[0; 250]).unwrap();
+        let mut reader = progress.count_read(&[0_u8; 1000][..], Side::Uncompressed);
+        io::copy(&mut reader, &mut io::sink()).unwrap();
+
+        let description = progress.counters.describe();
+        assert!(description.starts_with("1.00 kiB, "), "{description}");
+        assert!(description.ends_with(", ratio 25.0%"), "{description}");
+    }
+}
@@ -5,7 +5,7 @@
 
 use std::{
     borrow::Cow,
-    io::{self, BufRead, stdin},
+    io::{self, BufRead, IsTerminal, stdin},
     path::{Path, PathBuf},
 };
 
@@ -169,6 +169,88 @@ pub fn user_wants_to_continue(
     }
 }
 
+/// Ask the user for a password with `prompt`, without echoing it on Unix terminals.
+///
+/// With `confirm`, the password is asked twice, until both match.
+pub fn ask_for_password(prompt: &str, confirm: bool) -> Result<Vec<u8>> {
+    if !stdin().is_terminal() {
+        let error = FinalError::with_title("Cannot ask for a password")
+            .detail("Stdin is not a terminal, can't read user input")
+            .hint("Pass the password as the value of --password, like --password=VALUE");
+        return Err(error.into());
+    }
+
+    let _locks = lock_and_flush_output_stdio()?;
+
+    loop {
+        eprint!("{prompt}: ");
+        let password = read_password_line()?;
+
+        if password.is_empty() {
+            eprintln!("The password can't be empty");
+            continue;
+        }
+
+        if confirm {
+            eprint!("Confirm password: ");
+            if read_password_line()? != password {
+                eprintln!("The passwords don't match, try again");
+                continue;
+            }
+        }
+
+        return Ok(password);
+    }
+}
+
+/// Read a line from stdin with the terminal echo disabled.
+#[cfg(unix)]
+fn read_password_line() -> Result<Vec<u8>> {
+    use std::{mem::MaybeUninit, os::fd::AsRawFd};
+
+    let fd = stdin().as_raw_fd();
+    let mut termios = MaybeUninit::<libc::termios>::uninit();
+
+    // SAFETY: `fd` is a valid file descriptor and `termios` is only read after being initialized
+    let original = unsafe {
+        if libc::tcgetattr(fd, termios.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        termios.assume_init()
+    };
+
+    // Still echo the newline so following messages start on their own line
+    let mut no_echo = original;
+    no_echo.c_lflag &= !libc::ECHO;
+    no_echo.c_lflag |= libc::ECHONL;
+
+    // SAFETY: both `termios` structs were initialized by `tcgetattr`
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &no_echo) };
+    let line = read_line_bytes();
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &original) };
+
+    line
+}
+
+#[cfg(not(unix))]
+fn read_password_line() -> Result<Vec<u8>> {
+    read_line_bytes()
+}
+
+/// Read a line from stdin without the line ending, failing on EOF.
+fn read_line_bytes() -> Result<Vec<u8>> {
+    let mut line = vec![];
+    if stdin().lock().read_until(b'\n', &mut line)? == 0 {
+        let error = FinalError::with_title("Unexpected EOF when asking for a password.");
+        return Err(error.into());
+    }
+
+    while line.last().is_some_and(|byte| matches!(byte, b'\n' | b'\r')) {
+        line.pop();
+    }
+    Ok(line)
+}
+
 /// Choise dialog for end user with [option1/option2/...] question.
 /// Each option is a [Choice] entity, holding a value "T" returned when that option is selected
 pub struct ChoicePrompt<'a, T: Default> {
@@ -0,0 +1,28 @@
+use std::{fs::Metadata, time::UNIX_EPOCH};
+
+/// Settings of `--reproducible`, to build the same archive byte for byte from the same files on any
+/// machine: entries are sorted, owners are left out, and permissions and modification times are normalized.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Reproducible {
+    /// Later modification times are replaced by this one, in seconds since the Unix epoch, from `--mtime`
+    /// or `$SOURCE_DATE_EPOCH`
+    pub max_mtime: Option<u64>,
+}
+
+impl Reproducible {
+    /// Modification time to store for a file with `metadata`, in seconds since the Unix epoch.
+    pub fn mtime(&self, metadata: &Metadata) -> u64 {
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |duration| duration.as_secs());
+
+        self.max_mtime.map_or(mtime, |max_mtime| mtime.min(max_mtime))
+    }
+
+    /// Permissions to store, `0o755` for directories and executables, `0o644` for other files.
+    pub fn mode(is_dir_or_executable: bool) -> u32 {
+        if is_dir_or_executable { 0o755 } else { 0o644 }
+    }
+}
@@ -0,0 +1,99 @@
+use std::{
+    io::{self, BufReader, Cursor, Read, Seek, Write},
+    path::PathBuf,
+};
+
+use crate::{
+    BUFFER_CAPACITY, Result, info,
+    utils::{BytesFmt, io::ReadSeek},
+};
+
+/// Where to keep archives that must be read in random order (zip, 7z and rar) but are only
+/// available as a stream, because they're compressed again (`.zip.gz`) or read from STDIN.
+#[derive(Debug, Clone)]
+pub struct TempStorage {
+    /// Directory of the temporary files, the system's temporary directory by default
+    pub dir: Option<PathBuf>,
+    /// Bytes kept in memory before spilling to a temporary file
+    pub max_memory: u64,
+}
+
+impl TempStorage {
+    /// Default of [`TempStorage::max_memory`], 256 MiB
+    pub const DEFAULT_MAX_MEMORY: u64 = 256 * 1024 * 1024;
+
+    /// Create a named temporary file, for readers that only accept paths.
+    pub fn named_temp_file(&self) -> Result<tempfile::NamedTempFile> {
+        let file = match &self.dir {
+            Some(dir) => tempfile::NamedTempFile::new_in(dir)?,
+            None => tempfile::NamedTempFile::new()?,
+        };
+        Ok(file)
+    }
+
+    /// Read all of `reader` into a seekable buffer, in memory while it's smaller than `max_memory`,
+    /// in an anonymous temporary file otherwise.
+    pub fn buffer(&self, mut reader: impl Read) -> Result<Box<dyn ReadSeek>> {
+        let mut memory = Vec::new();
+        // Read one byte past the limit to know if there's more
+        (&mut reader)
+            .take(self.max_memory.saturating_add(1))
+            .read_to_end(&mut memory)?;
+
+        if memory.len() as u64 <= self.max_memory {
+            return Ok(Box::new(Cursor::new(memory)));
+        }
+
+        let mut file = match &self.dir {
+            Some(dir) => tempfile::tempfile_in(dir)?,
+            None => tempfile::tempfile()?,
+        };
+        info!(
+            "Archive is larger than {}, buffering it in a temporary file",
+            BytesFmt(self.max_memory)
+        );
+
+        file.write_all(&memory)?;
+        drop(memory);
+        io::copy(&mut reader, &mut file)?;
+        file.rewind()?;
+
+        Ok(Box::new(BufReader::with_capacity(BUFFER_CAPACITY, file)))
+    }
+}
+
+impl Default for TempStorage {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            max_memory: Self::DEFAULT_MAX_MEMORY,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(mut reader: Box<dyn ReadSeek>) -> Vec<u8> {
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn buffers_in_memory_and_spills_to_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = TempStorage {
+            dir: Some(dir.path().to_owned()),
+            max_memory: 4,
+        };
+
+        assert_eq!(read_all(storage.buffer(&b"1234"[..]).unwrap()), b"1234");
+        assert_eq!(read_all(storage.buffer(&b"123456789"[..]).unwrap()), b"123456789");
+
+        let mut reader = storage.buffer(&b"123456789"[..]).unwrap();
+        reader.seek(io::SeekFrom::Start(5)).unwrap();
+        assert_eq!(read_all(reader), b"6789");
+    }
+}
@@ -0,0 +1,74 @@
+/// Tests for using ouch as a library.
+#[macro_use]
+mod utils;
+
+use std::path::PathBuf;
+
+use fs_err as fs;
+use ouch::{
+    CompressOptions, DecompressOptions, DecompressionSummary, ListArchiveOptions, ListFileType, QuestionPolicy,
+};
+use pretty_assertions::assert_eq;
+
+use crate::utils::{assert_same_directory, testdir, write_files_in};
+
+// Keep everything in a single test, the library changes the current directory while compressing
+// so running multiple tests in parallel in the same process is not safe.
+#[test]
+fn compress_list_and_decompress_through_the_library() {
+    let (_tempdir, dir) = testdir().unwrap();
+
+    let before = dir.join("before");
+    let input = before.join("input");
+    write_files_in(
+        &input,
+        &[("file.txt", "some content"), ("nested/other.txt", "more content")],
+    );
+
+    // Archive format
+    let archive = dir.join("archive.tar.zst");
+    let summary = ouch::compress(vec![input.clone()], &archive, CompressOptions::default())
+        .unwrap()
+        .expect("compression should not be cancelled");
+    assert_eq!(summary.output_path, archive);
+    assert_eq!(summary.output_size, Some(fs::metadata(&archive).unwrap().len()));
+
+    let listings = ouch::list(std::slice::from_ref(&archive), ListArchiveOptions::default()).unwrap();
+    assert_eq!(listings.len(), 1);
+    let mut paths: Vec<_> = listings[0]
+        .files
+        .iter()
+        .filter(|file| file.file_type == ListFileType::File)
+        .map(|file| file.path.clone())
+        .collect();
+    paths.sort();
+    assert_eq!(paths, ["input/file.txt", "input/nested/other.txt"].map(PathBuf::from));
+
+    let after = dir.join("after");
+    let mut options = DecompressOptions::default();
+    options.output_dir = Some(after.clone());
+    options.question_policy = QuestionPolicy::AlwaysYes;
+    let summaries = ouch::decompress(&[archive], options).unwrap();
+    assert!(matches!(
+        summaries.as_slice(),
+        [DecompressionSummary::Archive { files_unpacked: 4, .. }]
+    ));
+    assert_same_directory(&before, &after, false);
+
+    // Single file format, using the format option instead of extensions
+    let compressed = dir.join("compressed");
+    let mut options = CompressOptions::default();
+    options.format = Some("gz".into());
+    ouch::compress(vec![input.join("file.txt")], &compressed, options)
+        .unwrap()
+        .unwrap();
+
+    let mut options = DecompressOptions::default();
+    options.format = Some("gz".into());
+    options.output_dir = Some(dir.join("single"));
+    let summaries = ouch::decompress(&[compressed], options).unwrap();
+    let [DecompressionSummary::NonArchive { output_path, .. }] = summaries.as_slice() else {
+        panic!("expected a single non-archive summary, got {summaries:?}");
+    };
+    assert_eq!(fs::read_to_string(output_path).unwrap(), "some content");
+}
@@ -19,7 +19,7 @@ use rand::{Rng, SeedableRng, rngs::SmallRng};
 use strum::IntoEnumIterator as _;
 use test_strategy::{Arbitrary, proptest};
 
-use crate::utils::{assert_same_directory, testdir, write_random_content};
+use crate::utils::{assert_same_directory, create_random_input, testdir, write_files_in, write_random_content};
 
 #[derive(Arbitrary, Clone, Copy, Debug, Display)]
 #[display(style = "lowercase")]
@@ -1310,6 +1310,7 @@ fn cwd_guard_blocks_explicit_overwrite() {
         .current_dir(&cwd)
         .arg("decompress")
         .arg(&archive)
+        .arg("--no-smart-unpack")
         .write_stdin("y")
         .assert()
         .failure();
@@ -1362,3 +1363,1408 @@ fn decompress_concatenated_lz4_frames() {
         encoder.finish().unwrap()
     });
 }
+
+#[test]
+fn test_subcommand_succeeds_for_valid_files() {
+    let (_tempdir, dir) = testdir().unwrap();
+    let input = dir.join("input");
+    fs::create_dir(&input).unwrap();
+    create_n_random_files(5, &input, &mut SmallRng::seed_from_u64(0));
+
+    for format in ["tar.gz", "zip", "7z", "tar.zst", "zip.xz"] {
+        let archive = dir.join(format!("archive.{format}"));
+        ouch!("compress", &input, &archive);
+        ouch!("test", &archive);
+    }
+}
+
+#[test]
+fn test_subcommand_reports_corrupted_entries() {
+    let (_tempdir, dir) = testdir().unwrap();
+    let input = dir.join("input");
+    fs::create_dir(&input).unwrap();
+    let mut content = vec![0; 64 * 1024];
+    SmallRng::seed_from_u64(0).fill(content.as_mut_slice());
+    fs::write(input.join("file"), content).unwrap();
+
+    for format in ["zip", "7z", "tar.gz"] {
+        let archive = dir.join(format!("archive.{format}"));
+        ouch!("compress", &input, &archive);
+
+        // Flip a byte in the middle of the compressed data
+        let mut bytes = fs::read(&archive).unwrap();
+        let middle = bytes.len() / 2;
+        bytes[middle] ^= 0xff;
+        fs::write(&archive, bytes).unwrap();
+
+        let output = crate::utils::cargo_bin()
+            .arg("test")
+            .arg(&archive)
+            .assert()
+            .failure()
+            .get_output()
+            .clone();
+
+        let stderr = output.stderr.to_str_lossy();
+        assert!(stderr.contains("Found corrupted files"), "{stderr}");
+        assert!(stderr.contains(&format!("archive.{format}")), "{stderr}");
+    }
+}
+
+#[test]
+fn decompress_selected_entries() {
+    fn list_files(root: &Path, dir: &Path, files: &mut Vec<String>) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                list_files(root, &path, files);
+            } else {
+                let relative = path.strip_prefix(root).unwrap();
+                files.push(relative.to_str().unwrap().replace('\\', "/"));
+            }
+        }
+        files.sort();
+    }
+
+    let files_in = |dir: &Path| {
+        let mut files = vec![];
+        list_files(dir, dir, &mut files);
+        files
+    };
+
+    let (_tempdir, dir) = testdir().unwrap();
+    let input = dir.join("input");
+    for path in [
+        "src/main.rs",
+        "src/a/lib.rs",
+        "src/a/build.c",
+        "docs/README.md",
+        "docs/other.md",
+    ] {
+        let path = input.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, path.to_str().unwrap()).unwrap();
+    }
+
+    for format in ["tar.gz", "zip", "7z"] {
+        let archive = dir.join(format!("archive.{format}"));
+        ouch!("compress", &input, &archive);
+
+        // Positional patterns
+        let output = dir.join(format!("positional-{format}"));
+        ouch!(
+            "decompress",
+            &archive,
+            "input/src/**/*.rs",
+            "input/docs/README.md",
+            "-d",
+            &output
+        );
+        assert_eq!(
+            files_in(&output),
+            ["input/docs/README.md", "input/src/a/lib.rs", "input/src/main.rs"]
+        );
+
+        // Flags, directories match everything inside of them
+        let output = dir.join(format!("flags-{format}"));
+        ouch!(
+            "decompress",
+            &archive,
+            "--include",
+            "input/src",
+            "--exclude",
+            "*/src/a",
+            "-d",
+            &output
+        );
+        assert_eq!(files_in(&output), ["input/src/main.rs"]);
+
+        // Not matching any entry is an error
+        crate::utils::cargo_bin()
+            .args(["decompress", "--yes", "-d"])
+            .arg(dir.join(format!("nothing-{format}")))
+            .arg(&archive)
+            .arg("does-not-exist")
+            .assert()
+            .failure();
+    }
+}
+
+/// Append an entry to a tar archive, skipping the path validation of `tar::Header::set_path` so
+/// malicious archives can be crafted.
+fn append_raw_tar_entry(
+    builder: &mut tar::Builder<impl std::io::Write>,
+    entry_type: tar::EntryType,
+    path: &str,
+    link_name: Option<&str>,
+) {
+    let mut header = tar::Header::new_gnu();
+    header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+    if let Some(link_name) = link_name {
+        header.as_old_mut().linkname[..link_name.len()].copy_from_slice(link_name.as_bytes());
+    }
+    header.set_entry_type(entry_type);
+    header.set_mode(0o644);
+    let data: &[u8] = if entry_type == tar::EntryType::Regular {
+        b"evil"
+    } else {
+        b""
+    };
+    header.set_size(data.len() as u64);
+    header.set_cksum();
+    builder.append(&header, data).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn tar_extraction_rejects_path_traversal() {
+    let (_tempdir, dir) = testdir().unwrap();
+    let outside = dir.join("outside");
+    fs::create_dir(&outside).unwrap();
+    let outside_file = outside.join("evil");
+    let outside_str = outside.to_str().unwrap();
+    let outside_file_str = outside_file.to_str().unwrap();
+
+    let cases: &[(&str, &[(tar::EntryType, &str, Option<&str>)])] = &[
+        ("parent-dir", &[(tar::EntryType::Regular, "../outside/evil", None)]),
+        ("absolute", &[(tar::EntryType::Regular, outside_file_str, None)]),
+        (
+            "through-symlink",
+            &[
+                (tar::EntryType::Symlink, "link", Some(outside_str)),
+                (tar::EntryType::Regular, "link/evil", None),
+            ],
+        ),
+        (
+            "hardlink-target",
+            &[(tar::EntryType::Link, "hardlink", Some("../outside/secret"))],
+        ),
+        (
+            "absolute-symlink-path",
+            &[(tar::EntryType::Symlink, outside_file_str, Some("target"))],
+        ),
+    ];
+
+    for (name, entries) in cases {
+        let archive = dir.join(format!("{name}.tar"));
+        let mut builder = tar::Builder::new(fs::File::create(&archive).unwrap());
+        for &(entry_type, path, link_name) in entries.iter() {
+            append_raw_tar_entry(&mut builder, entry_type, path, link_name);
+        }
+        builder.into_inner().unwrap();
+
+        let output = crate::utils::cargo_bin()
+            .args(["decompress", "--yes", "-d"])
+            .arg(dir.join(format!("output-{name}")))
+            .arg(&archive)
+            .assert()
+            .failure()
+            .get_output()
+            .clone();
+
+        let stderr = output.stderr.to_str_lossy();
+        let offending_entry = entries.last().unwrap().1;
+        assert!(stderr.contains("Refusing to extract unsafe entry"), "{name}: {stderr}");
+        assert!(stderr.contains(offending_entry), "{name}: {stderr}");
+        assert!(
+            !outside_file.exists(),
+            "{name}: file written outside of the output directory"
+        );
+    }
+}
+
+#[test]
+fn sevenz_extraction_skips_entries_outside_of_output_dir() {
+    let (_tempdir, dir) = testdir().unwrap();
+    let outside = dir.join("outside");
+    fs::create_dir(&outside).unwrap();
+    let absolute_name = outside.join("absolute").to_str().unwrap().replace('\\', "/");
+
+    let archive = dir.join("archive.7z");
+    let mut writer = sevenz_rust2::ArchiveWriter::create(&archive).unwrap();
+    for name in [
+        "../outside/parent",
+        absolute_name.as_str(),
+        "C:\\outside\\drive",
+        "dir/../../outside/nested",
+        "good.txt",
+    ] {
+        writer
+            .push_archive_entry(sevenz_rust2::ArchiveEntry::new_file(name), Some(name.as_bytes()))
+            .unwrap();
+    }
+    writer.finish().unwrap();
+
+    let output_dir = dir.join("output");
+    let output = crate::utils::cargo_bin()
+        .args(["decompress", "--yes", "-d"])
+        .arg(&output_dir)
+        .arg(&archive)
+        .assert()
+        .success()
+        .get_output()
+        .clone();
+
+    let stderr = output.stderr.to_str_lossy();
+    assert_eq!(stderr.matches("Skipping entry").count(), 4, "{stderr}");
+    assert_eq!(fs::read_to_string(output_dir.join("good.txt")).unwrap(), "good.txt");
+    assert_eq!(fs::read_dir(&outside).unwrap().count(), 0);
+}
+
+#[test]
+fn compress_to_stdout() {
+    let (_tempdir, dir) = testdir().unwrap();
+    let (before, input) = &create_random_input(dir, 7);
+
+    // Formats from the extensions after "-", or from --format
+    for (args, format) in [(&["-.tar.zst"][..], "tar.zst"), (&["-", "--format", "zip"], "zip")] {
+        let output = crate::utils::cargo_bin()
+            .args(["-A", "compress", input.to_str().unwrap()])
+            .args(args)
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+
+        let archive = &dir.join(format!("archive.{format}"));
+        fs::write(archive, output).unwrap();
+
+        let after = &dir.join(format!("after-{format}"));
+        ouch!("-A", "d", archive, "-d", after);
+        assert_same_directory(before, after, false);
+    }
+}
+
+#[test]
+fn decompress_to_stdout() {
+    let (_tempdir, dir) = testdir().unwrap();
+    let input = &dir.join("input");
+    write_files_in(input, &[("a.txt", "first\n"), ("nested/b.txt", "second\n")]);
+
+    let decompress_to_stdout = |args: &[&str]| {
+        crate::utils::cargo_bin()
+            .args(["-A", "-y", "decompress", "--stdout"])
+            .args(args)
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone()
+    };
+
+    // Single file formats, written one after another
+    let first = dir.join("a.txt.gz");
+    let second = dir.join("b.txt.xz.zst");
+    ouch!("-A", "c", input.join("a.txt"), &first);
+    ouch!("-A", "c", input.join("nested/b.txt"), &second);
+    assert_eq!(
+        decompress_to_stdout(&[first.to_str().unwrap(), second.to_str().unwrap()]).as_bstr(),
+        "first\nsecond\n"
+    );
+
+    // Archives, only the selected entries
+    for format in ["tar.gz", "zip", "7z"] {
+        let archive = dir.join(format!("archive.{format}"));
+        ouch!("-A", "c", input, &archive);
+        assert_eq!(
+            decompress_to_stdout(&[archive.to_str().unwrap(), "input/nested/*.txt"]).as_bstr(),
+            "second\n"
+        );
+    }
+}
+
+#[test]
+fn list_long_shows_metadata_and_totals() {
+    let (_tempdir, dir) = testdir().unwrap();
+    let input = dir.join("input");
+    fs::create_dir_all(&input).unwrap();
+    fs::write(input.join("file"), vec![b'a'; 2000]).unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(input.join("file"), std::fs::Permissions::from_mode(0o640)).unwrap();
+    }
+
+    for format in ["tar.gz", "zip", "7z"] {
+        let archive = dir.join(format!("archive.{format}"));
+        ouch!("-A", "c", &input, &archive);
+
+        let output = crate::utils::cargo_bin()
+            .args(["-A", "list", "--long"])
+            .arg(&archive)
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let output = output.to_str().unwrap();
+
+        let file_line = output.lines().find(|line| line.ends_with("input/file")).unwrap();
+        assert!(file_line.contains("2.00 kiB"), "{file_line}");
+        #[cfg(unix)]
+        if format != "7z" {
+            assert!(file_line.starts_with("-rw-r-----"), "{file_line}");
+        }
+        // Only zip and 7z compress entries separately, the repetitive content compresses well
+        if format != "tar.gz" {
+            assert!(file_line.contains('%'), "{file_line}");
+        }
+
+        let footer = output.lines().last().unwrap();
+        assert!(
+            footer.starts_with("Total: 1 files, 1 directories, 2.00 kiB uncompressed"),
+            "{footer}"
+        );
+    }
+}
+
+#[test]
+fn list_as_json() {
+    let (_tempdir, dir) = testdir().unwrap();
+    let input = dir.join("input");
+    write_files_in(&input, &[("nested/file", "content")]);
+
+    let archive = dir.join("archive.tar.gz");
+    ouch!("-A", "c", &input, &archive);
+
+    let list = |args: &[&str]| {
+        let output = crate::utils::cargo_bin()
+            .arg("list")
+            .arg(&archive)
+            .args(args)
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        String::from_utf8(output).unwrap()
+    };
+
+    let output = list(&["--output-format", "ndjson"]);
+    let lines: Vec<serde_json::Value> = output.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(lines[0]["archive"]["entry_count"], 3);
+    assert_eq!(lines[0]["archive"]["path"], archive.to_str().unwrap());
+    let file = lines.iter().find(|line| line["path"] == "input/nested/file").unwrap();
+    assert_eq!(file["type"], "file");
+    assert_eq!(file["size"], 7);
+    assert_eq!(file["encrypted"], false);
+
+    let output = list(&["--output-format", "json", "--tree"]);
+    let documents: serde_json::Value = serde_json::from_str(&output).unwrap();
+    let root = &documents[0]["entries"][0];
+    assert_eq!(root["name"], "input");
+    assert_eq!(root["children"][0]["name"], "nested");
+    assert_eq!(root["children"][0]["children"][0]["path"], "input/nested/file");
+
+    // Several archives are listed in a single array
+    let output = list(&[archive.to_str().unwrap(), "--output-format", "json"]);
+    let documents: Vec<serde_json::Value> = serde_json::from_str(&output).unwrap();
+    assert_eq!(documents.len(), 2);
+    assert_eq!(documents[1]["archive"]["entry_count"], 3);
+}
+
+#[test]
+fn add_files_to_existing_archives() {
+    let (_tempdir, dir) = testdir().unwrap();
+    let input = dir.join("input");
+    fs::create_dir_all(&input).unwrap();
+    fs::write(input.join("file"), "old").unwrap();
+    let extra = dir.join("extra");
+    fs::write(&extra, "extra").unwrap();
+
+    for format in ["tar", "tar.gz", "zip", "7z"] {
+        let archive = dir.join(format!("archive.{format}"));
+        fs::write(input.join("file"), "old").unwrap();
+        ouch!("-A", "c", &input, &archive);
+
+        // Add a new file, then update an existing one
+        ouch!("-A", "add", &archive, &extra);
+        fs::write(input.join("file"), "new").unwrap();
+        ouch!("-A", "a", &archive, &input);
+
+        let output_dir = dir.join(format!("output-{format}"));
+        ouch!("-A", "d", &archive, "-d", &output_dir);
+        assert_eq!(
+            fs::read_to_string(output_dir.join("input/file")).unwrap(),
+            "new",
+            "{format}"
+        );
+        assert_eq!(
+            fs::read_to_string(output_dir.join("extra")).unwrap(),
+            "extra",
+            "{format}"
+        );
+
+        // Replaced zip and 7z entries are removed, tar keeps the old ones before the new ones
+        let output = crate::utils::cargo_bin()
+            .args(["-A", "list"])
+            .arg(&archive)
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let file_count = output.lines().filter(|line| line.ends_with(b"input/file")).count();
+        let expected = if format.starts_with("tar") { 2 } else { 1 };
+        assert_eq!(file_count, expected, "{format}");
+    }
+
+    // Archives must exist, and single file formats can't have files added
+    crate::utils::cargo_bin()
+        .args(["add", "missing.zip"])
+        .arg(&extra)
+        .current_dir(dir)
+        .assert()
+        .failure();
+    let compressed = dir.join("extra.gz");
+    ouch!("-A", "c", &extra, &compressed);
+    crate::utils::cargo_bin()
+        .arg("add")
+        .arg(&compressed)
+        .arg(&input)
+        .assert()
+        .failure();
+}
+
+#[test]
+fn compress_with_password() {
+    let (_tempdir, dir) = testdir().unwrap();
+    let input = dir.join("input");
+    write_files_in(&input, &[("secret", "content")]);
+
+    for format in ["zip", "7z"] {
+        let archive = dir.join(format!("archive.{format}"));
+        ouch!("-A", "c", &input, &archive, "--password=hunter2");
+
+        crate::utils::cargo_bin()
+            .args(["-A", "d"])
+            .arg(&archive)
+            .arg("-d")
+            .arg(dir.join("no-password"))
+            .assert()
+            .failure();
+        crate::utils::cargo_bin()
+            .args(["-A", "d", "--password=wrong"])
+            .arg(&archive)
+            .arg("-d")
+            .arg(dir.join("wrong-password"))
+            .assert()
+            .failure();
+
+        let output_dir = dir.join(format!("output-{format}"));
+        ouch!("-A", "d", &archive, "-d", &output_dir, "--password=hunter2");
+        assert_eq!(fs::read_to_string(output_dir.join("input/secret")).unwrap(), "content");
+    }
+
+    // Entry names of 7z archives are only hidden with encrypted headers, they're big enough to be
+    // encrypted with a few entries
+    for i in 0..50 {
+        fs::write(input.join(format!("file-with-a-long-name-{i}")), "content").unwrap();
+    }
+    let archive = dir.join("headers.7z");
+    ouch!("-A", "c", &input, &archive, "--password=hunter2", "--encrypt-headers");
+    crate::utils::cargo_bin()
+        .args(["-A", "list"])
+        .arg(&archive)
+        .assert()
+        .failure();
+    ouch!("-A", "list", &archive, "--password=hunter2");
+
+    // Only zip and 7z can be encrypted
+    crate::utils::cargo_bin()
+        .args(["-A", "c"])
+        .arg(&input)
+        .arg(dir.join("archive.tar.gz"))
+        .args(["--password=hunter2"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn password_from_file_and_env() {
+    let (_tempdir, dir) = testdir().unwrap();
+    let input = dir.join("input");
+    write_files_in(&input, &[("secret", "content")]);
+
+    let archive = dir.join("archive.7z");
+    ouch!("-A", "c", &input, &archive, "--password=hunter2");
+
+    let password_file = dir.join("password");
+    fs::write(&password_file, "hunter2\n").unwrap();
+    ouch!(
+        "-A",
+        "d",
+        &archive,
+        "-d",
+        dir.join("from-file"),
+        "--password-file",
+        &password_file
+    );
+    assert_eq!(
+        fs::read_to_string(dir.join("from-file/input/secret")).unwrap(),
+        "content"
+    );
+
+    crate::utils::cargo_bin()
+        .args(["-A", "d"])
+        .arg(&archive)
+        .arg("-d")
+        .arg(dir.join("from-env"))
+        .env("OUCH_PASSWORD", "hunter2")
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read_to_string(dir.join("from-env/input/secret")).unwrap(),
+        "content"
+    );
+
+    // Can't ask for the password without a terminal
+    let output = crate::utils::cargo_bin()
+        .args(["-A", "d"])
+        .arg(&archive)
+        .arg("-d")
+        .arg(dir.join("no-password"))
+        .env_remove("OUCH_PASSWORD")
+        .assert()
+        .failure()
+        .get_output()
+        .stderr
+        .clone();
+    let stderr = output.to_str().unwrap();
+    assert!(stderr.contains("no password was given"), "{stderr}");
+}
+
+#[test]
+fn compress_chained_zip_without_buffering() {
+    let (_tempdir, dir) = testdir().unwrap();
+    let (before, input) = &create_random_input(dir, 11);
+    fs::create_dir(input.join("empty")).unwrap();
+
+    // Streamed, so there's no in-memory warning to confirm
+    let archive = &dir.join("archive.zip.gz");
+    let output = crate::utils::cargo_bin()
+        .args(["compress", input.to_str().unwrap(), archive.to_str().unwrap()])
+        .assert()
+        .success()
+        .get_output()
+        .stderr
+        .clone();
+    assert!(!output.to_str().unwrap().contains("in-memory"));
+
+    let after = &dir.join("after");
+    ouch!("-A", "-y", "d", archive, "-d", after);
+    assert_same_directory(before, after, false);
+}
+
+#[test]
+fn decompress_chained_archives_through_temp_files() {
+    let (_tempdir, dir) = testdir().unwrap();
+    let (before, input) = &create_random_input(dir, 13);
+    let temp_dir = &dir.join("temp");
+    fs::create_dir(temp_dir).unwrap();
+
+    for format in ["zip.gz", "7z.xz"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        ouch!("-A", "-y", "c", input, archive);
+
+        // Nothing kept in memory, no question asked
+        let after = &dir.join(format!("after-{format}"));
+        let output = crate::utils::cargo_bin()
+            .args(["decompress", "--max-memory", "0", "--temp-dir"])
+            .args([temp_dir, archive, Path::new("-d"), after])
+            .assert()
+            .success()
+            .get_output()
+            .stderr
+            .clone();
+        assert!(output.to_str().unwrap().contains("buffering it in a temporary file"));
+        assert_same_directory(before, after, false);
+
+        ouch!("-A", "--max-memory", "0", "t", archive);
+    }
+
+    // Anonymous temporary files are gone
+    assert_eq!(fs::read_dir(temp_dir).unwrap().count(), 0);
+}
+
+#[test]
+fn compress_with_format_options() {
+    let (_tempdir, dir) = testdir().unwrap();
+    let (before, input) = &create_random_input(dir, 17);
+
+    #[rustfmt::skip]
+    let options = [
+        "--opt", "zstd.long=30", "--opt", "xz.dict=64KiB", "--opt", "xz.block=128K", "--opt", "lz.dict=1MiB",
+        "--opt", "br.window=24", "--opt", "bz3.block=65KiB", "--opt", "gz.block=64K",
+    ];
+    for format in ["tar.zst", "tar.xz", "tar.lz", "tar.br", "tar.bz3", "tar.gz"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        crate::utils::cargo_bin()
+            .args(["compress", "-A"])
+            .args(options)
+            .args([input, archive])
+            .assert()
+            .success();
+
+        let after = &dir.join(format!("after-{format}"));
+        ouch!("-A", "-y", "d", archive, "-d", after);
+        assert_same_directory(before, after, false);
+    }
+
+    for (option, error) in [
+        ("zstd.window=27", "Unknown option 'window' for the format 'zst'"),
+        ("bz3.block=1GiB", "Expected a size from 65KiB to 511MiB, got '1GiB'"),
+    ] {
+        let output = crate::utils::cargo_bin()
+            .args(["compress", "-A", "--opt", option])
+            .args([input, &dir.join("archive.tar")])
+            .assert()
+            .failure()
+            .get_output()
+            .stderr
+            .clone();
+        assert!(output.to_str().unwrap().contains(error), "{option}");
+    }
+}
+
+#[test]
+fn compress_with_per_format_levels() {
+    let (_tempdir, dir) = testdir().unwrap();
+    let (before, input) = &create_random_input(dir, 19);
+
+    for (output, args, warning) in [
+        ("archive.tar.zst.gz", &["--format", "tar.zst:-5.gz:1"][..], None),
+        ("archive.tar.zst", &["--level", "zstd=22"], None),
+        ("archive.tar.xz.zst", &["-l", "3", "-l", "xz=9"], None),
+        (
+            "archive.tar.gz",
+            &["--level", "15"],
+            Some("Level 15 is out of the range of 'gz' (0 to 9), using 9 instead"),
+        ),
+        (
+            "archive.tar.gz",
+            &["--level", "bz=9"],
+            Some("Ignoring the level 9 of 'bz', the output isn't compressed with it"),
+        ),
+    ] {
+        let archive = &dir.join(output);
+        let output = crate::utils::cargo_bin()
+            .args(["compress", "-y"])
+            .args(args)
+            .args([input, archive])
+            .assert()
+            .success()
+            .get_output()
+            .stderr
+            .clone();
+        let output = output.to_str().unwrap();
+        match warning {
+            Some(warning) => assert!(output.contains(warning), "{args:?}: {output}"),
+            None => assert!(!output.contains("[WARNING]"), "{args:?}: {output}"),
+        }
+
+        let after = &dir.join("after");
+        ouch!("-A", "-y", "d", archive, "-d", after);
+        assert_same_directory(before, after, false);
+        fs::remove_dir_all(after).unwrap();
+    }
+}
+
+#[test]
+fn compress_with_trained_zstd_dictionary() {
+    let (_tempdir, dir) = testdir().unwrap();
+    let samples = &dir.join("samples");
+    fs::create_dir(samples).unwrap();
+    let mut rng = SmallRng::seed_from_u64(23);
+    for i in 0..500 {
+        let sample = format!(
+            r#"{{"id": {i}, "name": "user{}", "email": "user{i}@example.com", "active": {}}}"#,
+            rng.gen_range(0..100_000),
+            rng.gen_bool(0.5),
+        );
+        fs::write(samples.join(format!("{i}.json")), sample).unwrap();
+    }
+
+    let dict = &dir.join("samples.dict");
+    ouch!("-A", "train-dict", samples, "-o", dict);
+
+    let archive = &dir.join("samples.tar.zst");
+    ouch!("-A", "c", samples, archive, "--zstd-dict", dict);
+
+    let after = &dir.join("after");
+    ouch!("-A", "d", archive, "-d", after, "--zstd-dict", dict);
+    assert_same_directory(samples, after.join("samples"), false);
+    ouch!("-A", "t", archive, "--zstd-dict", dict);
+
+    let output = crate::utils::cargo_bin()
+        .args(["-A", "l"])
+        .arg(archive)
+        .assert()
+        .failure()
+        .get_output()
+        .stderr
+        .clone();
+    assert!(
+        output
+            .to_str()
+            .unwrap()
+            .contains("Cannot decompress zstd data without its dictionary")
+    );
+}
+
+#[test]
+fn compress_seekable_zstd() {
+    let (_tempdir, dir) = testdir().unwrap();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    let mut rng = SmallRng::seed_from_u64(19);
+    for i in 0..40 {
+        write_random_content(&mut fs::File::create(input.join(format!("{i}.bin"))).unwrap(), &mut rng);
+    }
+
+    let archive = &dir.join("input.tar.zst");
+    ouch!("-A", "c", input, archive, "--opt", "zst.seekable=4KiB");
+
+    // Ends with the footer of the seek table
+    let compressed = fs::read(archive).unwrap();
+    assert!(compressed.ends_with(&[0xB1, 0xEA, 0x92, 0x8F]));
+
+    let listing = crate::utils::cargo_bin()
+        .args(["-A", "l"])
+        .arg(archive)
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    assert_eq!(listing.to_str().unwrap().matches(".bin").count(), 40);
+
+    let selected = &dir.join("selected");
+    ouch!("-A", "d", archive, "-d", selected, "--include", "input/27.bin");
+    assert_eq!(
+        fs::read(selected.join("input/27.bin")).unwrap(),
+        fs::read(input.join("27.bin")).unwrap()
+    );
+    assert_eq!(fs::read_dir(selected.join("input")).unwrap().count(), 1);
+
+    let after = &dir.join("after");
+    ouch!("-A", "d", archive, "-d", after);
+    assert_same_directory(input, after.join("input"), false);
+    ouch!("-A", "t", archive);
+}
+
+#[test]
+fn list_tar_with_index() {
+    let (_tempdir, dir) = testdir().unwrap();
+    let input = &dir.join("input");
+    fs::create_dir_all(input.join("nested")).unwrap();
+    let mut rng = SmallRng::seed_from_u64(20);
+    for path in ["a.txt", "nested/b.txt", &format!("nested/{}.txt", "long".repeat(40))] {
+        write_random_content(&mut fs::File::create(input.join(path)).unwrap(), &mut rng);
+    }
+
+    let archive = &dir.join("input.tar.xz");
+    ouch!("-A", "c", input, archive, "--index");
+    let index = &dir.join("input.tar.xz.ouchidx");
+    assert!(index.exists());
+
+    let list = || {
+        let output = crate::utils::cargo_bin()
+            .args(["-A", "l", "--output-format", "json"])
+            .arg(archive)
+            .assert()
+            .success()
+            .get_output()
+            .clone();
+        (
+            String::from_utf8(output.stdout).unwrap(),
+            String::from_utf8(output.stderr).unwrap(),
+        )
+    };
+
+    // Same listing with the index as without it
+    let (with_index, _) = list();
+    fs::rename(index, dir.join("saved.ouchidx")).unwrap();
+    let (without_index, _) = list();
+    assert_eq!(with_index, without_index);
+
+    // Stale indexes are ignored
+    fs::rename(dir.join("saved.ouchidx"), index).unwrap();
+    ouch!("-A", "c", input.join("a.txt"), archive, "--format", "tar.xz");
+    let (listing, stderr) = list();
+    assert!(stderr.contains("doesn't match the archive anymore"));
+    assert!(listing.contains("a.txt") && !listing.contains("b.txt"));
+}
+
+#[test]
+fn smart_unpack() {
+    let (_tempdir, dir) = testdir().unwrap();
+    let cwd = &dir.join("cwd");
+    fs::create_dir_all(cwd.join("single")).unwrap();
+    fs::write(cwd.join("unrelated.txt"), "keep").unwrap();
+
+    let input = &dir.join("input");
+    write_files_in(input, &[("single/a.txt", "a"), ("b.txt", "b")]);
+
+    let decompress = |archive: &Path, extra_args: &[&str]| {
+        crate::utils::cargo_bin()
+            .current_dir(cwd)
+            .args(["-A", "-y", "d"])
+            .arg(archive)
+            .args(extra_args)
+            .assert()
+            .success();
+    };
+
+    // A single top-level entry is moved into the current directory, merging with what's there
+    let single = &dir.join("single.zip");
+    ouch!("-A", "c", input.join("single"), single);
+    decompress(single, &[]);
+    assert_eq!(fs::read_to_string(cwd.join("single/a.txt")).unwrap(), "a");
+
+    // Several top-level entries go in a directory named after the archive
+    let several = &dir.join("several.tar.gz");
+    ouch!("-A", "c", input.join("single"), input.join("b.txt"), several);
+    decompress(several, &[]);
+    assert_same_directory(input, cwd.join("several"), false);
+
+    // Nothing is left behind in the current directory
+    let mut entries: Vec<_> = fs::read_dir(cwd)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    entries.sort();
+    assert_eq!(entries, ["several", "single", "unrelated.txt"]);
+
+    decompress(several, &["--no-smart-unpack"]);
+    assert_eq!(fs::read_to_string(cwd.join("b.txt")).unwrap(), "b");
+}
+
+#[test]
+fn decompress_strip_components_and_flatten() {
+    let (_tempdir, dir) = testdir().unwrap();
+    let input = &dir.join("project-1.2.3");
+    write_files_in(input, &[("README", "readme"), ("src/main.rs", "main")]);
+    #[cfg(unix)]
+    fs::hard_link(input.join("README"), input.join("src/README.link")).unwrap();
+
+    for format in ["tar.gz", "zip", "7z"] {
+        let archive = &dir.join(format!("project.{format}"));
+        ouch!("-A", "c", input, archive);
+
+        let stripped = &dir.join(format!("stripped-{format}"));
+        ouch!("-A", "d", archive, "-d", stripped, "--strip-components", "1");
+        assert_same_directory(input, stripped, false);
+
+        let flattened = &dir.join(format!("flattened-{format}"));
+        ouch!("-A", "d", archive, "-d", flattened, "--flatten");
+        let mut files: Vec<_> = fs::read_dir(flattened)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        if cfg!(unix) {
+            assert_eq!(files, ["README", "README.link", "main.rs"]);
+        } else {
+            assert_eq!(files, ["README", "main.rs"]);
+        }
+    }
+}
+
+#[test]
+fn compress_and_decompress_with_transform_and_prefix() {
+    let (_tempdir, dir) = testdir().unwrap();
+    let input = &dir.join("build");
+    write_files_in(input, &[("app", "app"), ("lib/libapp.so", "lib")]);
+
+    // What the archives should unpack to
+    let expected = &dir.join("expected");
+    write_files_in(expected, &[("bin/app", "app"), ("bin/lib/libapp.so", "lib")]);
+
+    for format in ["tar.gz", "zip", "7z"] {
+        let archive = &dir.join(format!("myapp.{format}"));
+        ouch!(
+            "-A",
+            "c",
+            input,
+            archive,
+            "--transform",
+            "s|^build/|bin/|",
+            "--prefix",
+            "myapp-1.0/"
+        );
+
+        let output = &dir.join(format!("output-{format}"));
+        ouch!("-A", "d", archive, "-d", output);
+        assert!(output.join("myapp-1.0/bin/lib/libapp.so").exists());
+        // The `build` directory entry is renamed too, instead of being left empty
+        assert!(!output.join("myapp-1.0/build").exists());
+
+        // The same rules apply when extracting
+        let renamed = &dir.join(format!("renamed-{format}"));
+        ouch!(
+            "-A",
+            "d",
+            archive,
+            "-d",
+            renamed,
+            "--transform",
+            "s|^myapp-1.0/||",
+            "--prefix",
+            "usr"
+        );
+        assert_same_directory(expected.join("bin"), renamed.join("usr/bin"), false);
+    }
+
+    let archive = &dir.join("invalid.tar");
+    crate::utils::cargo_bin()
+        .args(["-A", "-y", "c"])
+        .arg(input)
+        .arg(archive)
+        .args(["--transform", "s|build|bin"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn compress_with_exclude_include_and_files_from() {
+    let (_tempdir, dir) = testdir().unwrap();
+    let input = &dir.join("project");
+    for subdir in [".git", "src", "target/debug", "cache"] {
+        fs::create_dir_all(input.join(subdir)).unwrap();
+    }
+    for file in [
+        ".git/HEAD",
+        ".gitignore",
+        "README",
+        "src/main.rs",
+        "src/main.o",
+        "target/debug/app",
+    ] {
+        fs::write(input.join(file), file).unwrap();
+    }
+    fs::write(
+        input.join("cache/CACHEDIR.TAG"),
+        "Signature: 8a477f597d28d172789f06886806bc55\n",
+    )
+    .unwrap();
+    fs::write(input.join("cache/blob"), "blob").unwrap();
+
+    let files_in = |output: &Path| -> Vec<String> {
+        let mut files: Vec<_> = glob::glob(output.join("**/*").to_str().unwrap())
+            .unwrap()
+            .map(|path| path.unwrap())
+            .filter(|path| path.is_file())
+            .map(|path| path.strip_prefix(output).unwrap().to_str().unwrap().replace('\\', "/"))
+            .collect();
+        files.sort();
+        files
+    };
+
+    for format in ["tar.gz", "zip", "7z"] {
+        let archive = &dir.join(format!("excluded.{format}"));
+        ouch!(
+            "-A",
+            "c",
+            input,
+            archive,
+            "--exclude-vcs",
+            "--exclude-caches",
+            "--exclude",
+            "*.o",
+            "--exclude",
+            "project/target"
+        );
+        let output = &dir.join(format!("excluded-{format}"));
+        ouch!("-A", "d", archive, "-d", output);
+        assert_eq!(files_in(output), ["project/README", "project/src/main.rs"]);
+
+        let archive = &dir.join(format!("included.{format}"));
+        ouch!("-A", "c", input, archive, "--include", "*.rs");
+        let output = &dir.join(format!("included-{format}"));
+        ouch!("-A", "d", archive, "-d", output);
+        assert_eq!(files_in(output), ["project/src/main.rs"]);
+    }
+
+    let list = &dir.join("list");
+    fs::write(
+        list,
+        format!(
+            "{}\0{}\0",
+            input.join("README").display(),
+            input.join("src/main.rs").display()
+        ),
+    )
+    .unwrap();
+    let archive = &dir.join("listed.tar");
+    ouch!("-A", "c", "--files-from", list, "--null", archive);
+    let output = &dir.join("listed");
+    ouch!("-A", "d", archive, "-d", output);
+    assert_eq!(files_in(output), ["README", "main.rs"]);
+}
+
+#[test]
+fn compress_reproducible() {
+    let (_tempdir, dir) = testdir().unwrap();
+
+    // The same files, with other modification times and permissions
+    let first = &dir.join("first/project");
+    let second = &dir.join("second/project");
+    for (input, mtime) in [(first, 1_600_000_000), (second, 1_650_000_000)] {
+        fs::create_dir_all(input.join("src")).unwrap();
+        for file in ["README", "src/main.rs", "src/lib.rs"] {
+            fs::write(input.join(file), file).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(input.join(file))
+                .unwrap()
+                .set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(mtime))
+                .unwrap();
+        }
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(second.join("README"), std::fs::Permissions::from_mode(0o600)).unwrap();
+    }
+
+    for format in ["tar.gz", "zip"] {
+        let compress = |input: &Path| {
+            let archive = input.with_extension(format);
+            crate::utils::cargo_bin()
+                .args(["-A", "-y", "c", "--reproducible"])
+                .arg(input)
+                .arg(&archive)
+                .env("SOURCE_DATE_EPOCH", "1500000000")
+                .assert()
+                .success();
+            fs::read(archive).unwrap()
+        };
+
+        assert_eq!(compress(first), compress(second), "{format} archives differ");
+    }
+
+    // gzip headers have no timestamp
+    assert_eq!(&fs::read(dir.join("first/project.tar.gz")).unwrap()[4..8], [0; 4]);
+}
+
+#[test]
+fn decompress_selected_entries_with_extension_in_pattern() {
+    let (_tempdir, dir) = testdir().unwrap();
+    let input = dir.join("input");
+    write_files_in(
+        &input,
+        &[("nested.tar.gz", "not really compressed"), ("other.txt", "other")],
+    );
+
+    let archive = dir.join("archive.tar");
+    ouch!("compress", &input, &archive);
+
+    // The pattern ends with an extension, but it isn't a file to decompress
+    let output = dir.join("output");
+    ouch!("decompress", &archive, "input/*.tar.gz", "-d", &output);
+    assert!(output.join("input/nested.tar.gz").is_file());
+    assert!(!output.join("input/other.txt").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn add_to_archive_in_place_keeps_the_archive_on_error() {
+    let (_tempdir, dir) = testdir().unwrap();
+    let file = dir.join("file");
+    fs::write(&file, "file").unwrap();
+    let extra = dir.join("extra");
+    fs::write(&extra, "extra").unwrap();
+    let broken = dir.join("broken");
+    std::os::unix::fs::symlink(dir.join("missing"), &broken).unwrap();
+
+    for format in ["tar", "zip"] {
+        let archive = dir.join(format!("archive.{format}"));
+        ouch!("-A", "c", &file, &archive);
+        let before = fs::read(&archive).unwrap();
+
+        // The new file is appended, then following the broken symlink fails
+        crate::utils::cargo_bin()
+            .args(["-A", "add", "--follow-symlinks"])
+            .arg(&archive)
+            .arg(&extra)
+            .arg(&broken)
+            .assert()
+            .failure();
+
+        assert_eq!(fs::read(&archive).unwrap(), before, "{format}");
+    }
+}
+
+#[test]
+fn add_to_compressed_tar_with_level() {
+    let (_tempdir, dir) = testdir().unwrap();
+    let file = dir.join("file");
+    fs::write(&file, "file").unwrap();
+
+    let mut rng = SmallRng::seed_from_u64(0);
+    let words = ["ouch", "tar", "gzip", "level", "archive", "entry", "compress"];
+    let text = (0..50_000).map(|_| words[rng.gen_range(0..words.len())]).join(" ");
+    let extra = dir.join("extra.txt");
+    fs::write(&extra, text).unwrap();
+
+    let add_with = |level: &str| {
+        let archive = dir.join(format!("archive{level}.tar.gz"));
+        ouch!("-A", "c", &file, &archive);
+        ouch!("-A", "add", &archive, &extra, level);
+        fs::metadata(archive).unwrap().len()
+    };
+
+    assert!(add_with("--fast") > add_with("--slow"));
+}
+
+#[test]
+fn password_flag_without_value_does_not_take_the_next_argument() {
+    let (_tempdir, dir) = testdir().unwrap();
+    let file = dir.join("file");
+    fs::write(&file, "file").unwrap();
+    let archive = dir.join("archive.zip");
+    ouch!("-A", "c", &file, &archive, "--password=hunter2");
+
+    // The archive isn't taken as the password, which is asked for instead
+    let output = crate::utils::cargo_bin()
+        .args(["-A", "list", "--password"])
+        .arg(&archive)
+        .assert()
+        .failure()
+        .get_output()
+        .stderr
+        .clone();
+    let stderr = String::from_utf8(output).unwrap();
+    assert!(stderr.contains("Cannot ask for a password"), "{stderr}");
+}
+
+#[test]
+fn compress_ignores_password_from_env() {
+    let (_tempdir, dir) = testdir().unwrap();
+    let file = dir.join("file");
+    fs::write(&file, "file").unwrap();
+
+    for format in ["tar.gz", "zip"] {
+        let archive = dir.join(format!("archive.{format}"));
+        crate::utils::cargo_bin()
+            .args(["-A", "--yes", "c"])
+            .arg(&file)
+            .arg(&archive)
+            .env("OUCH_PASSWORD", "hunter2")
+            .assert()
+            .success();
+
+        // Not encrypted, so it can be decompressed without the password
+        crate::utils::cargo_bin()
+            .args(["-A", "--yes", "d"])
+            .arg(&archive)
+            .arg("-d")
+            .arg(dir.join(format!("output-{format}")))
+            .env_remove("OUCH_PASSWORD")
+            .assert()
+            .success();
+        assert_eq!(
+            fs::read_to_string(dir.join(format!("output-{format}/file"))).unwrap(),
+            "file"
+        );
+    }
+
+    // A password file can't be used to encrypt
+    let password_file = dir.join("password");
+    fs::write(&password_file, "hunter2\n").unwrap();
+    crate::utils::cargo_bin()
+        .args(["-A", "--yes", "c", "--password-file"])
+        .arg(&password_file)
+        .arg(&file)
+        .arg(dir.join("from-file.zip"))
+        .assert()
+        .failure();
+}
+
+#[test]
+fn tar_index_of_a_rebuilt_archive_of_the_same_size_is_ignored() {
+    let (_tempdir, dir) = testdir().unwrap();
+    let input = &dir.join("d");
+    write_files_in(input, &[("a.txt", "content")]);
+
+    let archive = &dir.join("out.tar");
+    ouch!("-A", "c", input, archive, "--index");
+    let size = fs::metadata(archive).unwrap().len();
+
+    fs::remove_file(input.join("a.txt")).unwrap();
+    write_files_in(input, &[("b.txt", "content")]);
+    ouch!("-A", "c", input, archive);
+    // Uncompressed tar archives end with zeros, only their entries tell them apart
+    assert_eq!(fs::metadata(archive).unwrap().len(), size);
+
+    let output = crate::utils::cargo_bin()
+        .args(["-A", "l"])
+        .arg(archive)
+        .assert()
+        .success()
+        .get_output()
+        .clone();
+    let listing = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("doesn't match the archive anymore")
+    );
+    assert!(listing.contains("b.txt") && !listing.contains("a.txt"));
+}
+
+#[test]
+fn decompress_flatten_fails_on_colliding_names() {
+    let (_tempdir, dir) = testdir().unwrap();
+    let input = &dir.join("p");
+    write_files_in(input, &[("a/x", "a"), ("b/x", "b")]);
+
+    for format in ["tar.gz", "zip", "7z"] {
+        let archive = &dir.join(format!("p.{format}"));
+        ouch!("-A", "c", input, archive);
+
+        let flattened = &dir.join(format!("flattened-{format}"));
+        let output = crate::utils::cargo_bin()
+            .args(["-A", "--yes", "d"])
+            .arg(archive)
+            .arg("-d")
+            .arg(flattened)
+            .arg("--flatten")
+            .assert()
+            .failure()
+            .get_output()
+            .clone();
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("Cannot flatten"), "{format}: {stderr}");
+
+        // Selecting one of them leaves nothing to overwrite
+        let selected = &dir.join(format!("selected-{format}"));
+        ouch!("-A", "d", archive, "-d", selected, "--flatten", "--exclude", "p/a");
+        assert_eq!(fs::read_to_string(selected.join("x")).unwrap(), "b");
+    }
+}
+
+#[test]
+fn decompress_reports_missing_archive_instead_of_using_it_as_pattern() {
+    let (_tempdir, dir) = testdir().unwrap();
+    let input = dir.join("input");
+    write_files_in(&input, &[("file.txt", "file")]);
+
+    let archive = dir.join("a.tar.gz");
+    ouch!("-A", "c", &input, &archive);
+
+    // `b.tar.gz` was meant to be another archive, not a pattern of entries of `a.tar.gz`
+    let output = crate::utils::cargo_bin()
+        .args(["-A", "--yes", "d"])
+        .arg(&archive)
+        .arg(dir.join("b.tar.gz"))
+        .arg("-d")
+        .arg(dir.join("output"))
+        .assert()
+        .failure()
+        .get_output()
+        .clone();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("File not found"), "{stderr}");
+}
+
+#[test]
+fn add_directory_containing_the_rewritten_archive() {
+    let (_tempdir, dir) = testdir().unwrap();
+    let temp_dir = dir.join("temp");
+    fs::create_dir(&temp_dir).unwrap();
+
+    let file_names = |dir: &Path| -> Vec<String> {
+        let mut names: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    };
+
+    for format in ["tar.gz", "zip", "7z"] {
+        for temp_dir_args in [vec![], vec!["--temp-dir".as_ref(), temp_dir.as_os_str()]] {
+            let input = dir.join("input");
+            let archive = input.join(format!("archive.{format}"));
+            write_files_in(&input, &[("file", "file")]);
+            ouch!("-A", "c", &input, &archive);
+            write_files_in(&input, &[("new", "new")]);
+
+            // Zip entries of `input` are replaced, so zip archives are rewritten too
+            crate::utils::cargo_bin()
+                .args(["-A", "add"])
+                .arg(&archive)
+                .arg(&input)
+                .args(&temp_dir_args)
+                .assert()
+                .success();
+
+            // Only the archive is left next to the files, and the temporary file isn't added
+            assert_eq!(
+                file_names(&input),
+                [format!("archive.{format}"), "file".into(), "new".into()]
+            );
+            assert!(file_names(&temp_dir).is_empty());
+
+            let output = dir.join("output");
+            ouch!("-A", "d", &archive, "-d", &output);
+            assert_eq!(file_names(&output.join("input")), ["file", "new"], "{format}");
+
+            fs::remove_dir_all(&input).unwrap();
+            fs::remove_dir_all(&output).unwrap();
+        }
+    }
+}
+
+#[test]
+fn add_to_compressed_tar_with_format_options() {
+    let (_tempdir, dir) = testdir().unwrap();
+    let input = dir.join("input");
+    write_files_in(&input, &[("file", "file")]);
+    let extra = dir.join("extra");
+    fs::write(&extra, "extra").unwrap();
+
+    let archive = dir.join("archive.tar.zst");
+    ouch!("-A", "c", &input, &archive);
+    ouch!("-A", "add", &archive, &extra, "--opt", "zst.seekable=4KiB");
+
+    // Ends with the footer of the seek table
+    assert!(fs::read(&archive).unwrap().ends_with(&[0xB1, 0xEA, 0x92, 0x8F]));
+
+    let output = dir.join("output");
+    ouch!("-A", "d", &archive, "-d", &output);
+    assert_same_directory(&input, output.join("input"), false);
+    assert_eq!(fs::read_to_string(output.join("extra")).unwrap(), "extra");
+}
+
+#[test]
+fn add_to_encrypted_7z_with_password_from_env_or_file() {
+    let (_tempdir, dir) = testdir().unwrap();
+    let file = dir.join("file");
+    fs::write(&file, "file").unwrap();
+    let extra = dir.join("extra");
+    fs::write(&extra, "extra").unwrap();
+    let password_file = dir.join("password");
+    fs::write(&password_file, "hunter2\n").unwrap();
+
+    for source in ["env", "file"] {
+        let archive = dir.join(format!("archive-{source}.7z"));
+        ouch!("-A", "c", &file, &archive, "--password=hunter2");
+
+        let mut command = crate::utils::cargo_bin();
+        command.args(["-A", "--yes", "add"]).arg(&archive).arg(&extra);
+        match source {
+            "env" => command.env("OUCH_PASSWORD", "hunter2"),
+            _ => command.arg("--password-file").arg(&password_file),
+        };
+        command.assert().success();
+
+        let output = dir.join(format!("output-{source}"));
+        ouch!("-A", "d", &archive, "-d", &output, "--password=hunter2");
+        assert_eq!(fs::read_to_string(output.join("file")).unwrap(), "file", "{source}");
+        assert_eq!(fs::read_to_string(output.join("extra")).unwrap(), "extra", "{source}");
+    }
+}
@@ -9,7 +9,7 @@ use std::{
 
 use assert_cmd::Command;
 use fs_err as fs;
-use rand::{Rng, RngCore};
+use rand::{Rng, RngCore, SeedableRng, rngs::SmallRng};
 
 /// Run ouch with the provided arguments, returns [`assert_cmd::Output`]
 #[macro_export]
@@ -55,6 +55,37 @@ pub fn create_files_in(dir: &Path, files: &[&str]) {
     }
 }
 
+/// Creates files with the given contents in the specified directory, and their parent directories.
+///
+/// ## Example
+///
+/// ```no_run
+/// let (_dropper, dir) = testdir().unwrap();
+/// write_files_in(dir, &[("file.txt", "content"), ("nested/other.txt", "other")]);
+/// ```
+pub fn write_files_in(dir: &Path, files: &[(&str, &str)]) {
+    for (path, contents) in files {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+}
+
+/// Creates `before/input/file` in `dir`, with random content generated from `seed`.
+///
+/// Returns the `before` and `input` directories, `input` is what gets compressed and `before` is
+/// compared with the output of the decompression.
+pub fn create_random_input(dir: &Path, seed: u64) -> (PathBuf, PathBuf) {
+    let before = dir.join("before");
+    let input = before.join("input");
+    fs::create_dir_all(&input).unwrap();
+    write_random_content(
+        &mut fs::File::create(input.join("file")).unwrap(),
+        &mut SmallRng::seed_from_u64(seed),
+    );
+    (before, input)
+}
+
 /// Write random content to a file
 pub fn write_random_content(file: &mut impl Write, rng: &mut impl RngCore) {
     let mut data = vec![0; rng.gen_range(0..8192)];